@@ -1,14 +1,45 @@
-const HYDRA_URL = process.env["HYDRA_URL"] || "http://localhost:4001"
+const TRP_URL = process.env["TRP_URL"] || "http://localhost:8164"
 
-export async function loader() {
-  const response = await fetch(`${HYDRA_URL}/snapshot/utxo`, {
-    method: "GET",
+type UtxoView = {
+  ref: string,
+  address: string,
+  lovelace: number,
+  assets: Record<string, Record<string, number>>,
+}
+
+async function queryUtxos(cursor?: string): Promise<{ utxos: UtxoView[], nextCursor?: string }> {
+  const response = await fetch(TRP_URL, {
+    method: "POST",
     headers: {
       "Content-Type": "application/json"
     },
+    body: JSON.stringify({
+      jsonrpc: "2.0",
+      method: "trp.queryUtxos",
+      params: { cursor, limit: 1000 },
+      id: crypto.randomUUID(),
+    }),
   })
 
-  const utxos = await response.json()
+  const { result } = await response.json()
+  return result
+}
+
+export async function loader() {
+  // keeps the hydra snapshot shape the page renders
+  const utxos: Record<string, object> = {}
+
+  let cursor: string | undefined
+  do {
+    const page = await queryUtxos(cursor)
+    for (const utxo of page.utxos) {
+      utxos[utxo.ref] = {
+        address: utxo.address,
+        value: { lovelace: utxo.lovelace, ...utxo.assets },
+      }
+    }
+    cursor = page.nextCursor
+  } while (cursor)
 
   return new Response(JSON.stringify(utxos), {
     status: 200,
@@ -17,4 +48,3 @@ export async function loader() {
     },
   });
 }
-
@@ -0,0 +1,47 @@
+//! Ledgers the TRP server can be run against
+
+use std::future::Future;
+
+use tokio::sync::broadcast;
+use tx3_cardano::{ChainPoint, PParams};
+
+use crate::{
+    hydra::{
+        HealthStatus, UtxoSnapshot,
+        model::{Event, HydraMessage, HydraPParams},
+    },
+    ledger::time::SlotConfig,
+};
+
+/// Ledger state and submission endpoint behind the TRP methods. Implemented
+/// by the hydra adapter and the devnet emulator, and open to recording
+/// backends, mocks or other L2s.
+pub trait Backend: Send + Sync + 'static {
+    /// Read access to the current utxo set
+    fn read_utxos(&self) -> impl Future<Output = UtxoSnapshot<'_>> + Send;
+
+    fn get_pparams(&self) -> impl Future<Output = anyhow::Result<PParams>> + Send;
+
+    /// Protocol parameters as the head reports them, with the prices and
+    /// limits tx3's [`PParams`] leave out
+    fn protocol_parameters(&self) -> impl Future<Output = anyhow::Result<HydraPParams>> + Send;
+
+    /// Point of the ledger transactions are resolved against
+    fn chain_point(&self) -> impl Future<Output = anyhow::Result<ChainPoint>> + Send;
+
+    /// Slot to time conversion of the ledger, for validity ranges and script contexts
+    fn slot_config(&self) -> SlotConfig;
+
+    /// Sends a transaction, its outcome is reported through [`Backend::events`]
+    fn submit(
+        &self,
+        hydra_message: HydraMessage,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Stream of ledger events, subscribe before submitting to not miss replies
+    fn events(&self) -> broadcast::Receiver<Event>;
+
+    fn check_health(&self) -> impl Future<Output = bool> + Send;
+
+    fn health_status(&self) -> impl Future<Output = HealthStatus> + Send;
+}
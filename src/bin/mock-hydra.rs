@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use tokio::net::TcpListener;
+use tracing::Level;
+use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+use tx3_hydra::mock::{self, Scenario};
+
+/// Scriptable mock hydra-node for offline testing
+#[derive(Parser)]
+#[command(name = "mock-hydra", version)]
+struct Args {
+    /// Scenario file driving the mock replies
+    scenario: Option<PathBuf>,
+
+    /// Overrides the scenario listen address
+    #[arg(long)]
+    listen_address: Option<String>,
+}
+
+#[tokio::main()]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let env_filter = EnvFilter::builder()
+        .with_default_directive(Level::INFO.into())
+        .with_env_var("RUST_LOG")
+        .from_env_lossy();
+
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(env_filter)
+        .init();
+
+    let mut scenario = match &args.scenario {
+        Some(path) => Scenario::load(path)?,
+        None => Scenario::default(),
+    };
+
+    if let Some(listen_address) = args.listen_address {
+        scenario.listen_address = listen_address;
+    }
+
+    let listener = TcpListener::bind(&scenario.listen_address).await?;
+
+    mock::serve(listener, scenario, tx3_hydra::cancellation_token()).await
+}
@@ -0,0 +1,50 @@
+use anyhow::{Context, bail};
+use serde_json::json;
+
+/// Minimal JSON-RPC client for a running TRP server
+pub struct TrpClient {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl TrpClient {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        });
+
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("calling {method}"))?
+            .json::<serde_json::Value>()
+            .await
+            .context("decoding json-rpc response")?;
+
+        if let Some(error) = response.get("error") {
+            bail!("{method} failed: {error}");
+        }
+
+        response
+            .get("result")
+            .cloned()
+            .context("json-rpc response without result")
+    }
+}
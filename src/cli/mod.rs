@@ -0,0 +1,324 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, bail};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use jsonrpsee::types::ErrorObjectOwned;
+use serde_json::json;
+use tokio::sync::RwLock;
+
+use crate::{
+    Config,
+    hydra::{self, Progress, UtxoSnapshot},
+    ledger::time::SlotConfig,
+    trp::{
+        methods::resolve::{self, ResolveOptions},
+        report::TxReport,
+    },
+};
+
+mod client;
+
+use client::TrpClient;
+
+const DEFAULT_TRP_URL: &str = "http://127.0.0.1:8164";
+
+#[derive(Parser)]
+#[command(name = "tx3-hydra", version, about = "Tx3 TRP server for Hydra heads")]
+pub struct Cli {
+    /// Path to the config file
+    #[arg(long, global = true, env = "TRP_HYDRA_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Overrides `trp.listen_address` from the config
+    #[arg(long, global = true)]
+    pub listen_address: Option<String>,
+
+    /// Log filter directives (e.g. `debug`), takes precedence over `RUST_LOG`
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Runs the TRP server against the configured Hydra head (default)
+    Serve,
+    /// Validates and prints the merged config
+    CheckConfig,
+    /// Exits non-zero when the local server is unhealthy
+    Healthcheck(HealthcheckArgs),
+    /// Prints the version
+    Version,
+    /// Resolves a transaction on a running server
+    Resolve(ResolveArgs),
+    /// Submits a signed transaction to a running server
+    Submit(SubmitArgs),
+    /// Lists the head utxos known by a running server
+    Utxos(UtxosArgs),
+    /// Resolves a transaction against a utxo file, without a hydra node
+    ResolveOffline(ResolveOfflineArgs),
+    /// Runs the TRP server against an in-process ledger emulator
+    Devnet(DevnetArgs),
+    /// Rebuilds the adapter state from a hydra journal and serves it, without submits
+    Replay(ReplayArgs),
+}
+
+#[derive(Args, Clone)]
+pub struct ServerArgs {
+    /// URL of the TRP server
+    #[arg(long, env = "TRP_URL", default_value = DEFAULT_TRP_URL)]
+    pub url: String,
+}
+
+#[derive(Args, Clone)]
+pub struct HealthcheckArgs {
+    /// URL of the TRP server, derived from the config listen address if unset
+    #[arg(long)]
+    pub url: Option<String>,
+
+    /// Only check liveness instead of readiness
+    #[arg(long)]
+    pub live: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct ResolveArgs {
+    #[command(flatten)]
+    pub server: ServerArgs,
+
+    /// JSON file with the `trp.resolve` params (`tir` and `args`), `-` for stdin
+    pub request: PathBuf,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+#[derive(Args, Clone)]
+pub struct SubmitArgs {
+    #[command(flatten)]
+    pub server: ServerArgs,
+
+    /// Signed transaction CBOR
+    pub tx: String,
+
+    #[arg(long, value_enum, default_value = "hex")]
+    pub encoding: Encoding,
+}
+
+#[derive(Args, Clone)]
+pub struct UtxosArgs {
+    #[command(flatten)]
+    pub server: ServerArgs,
+
+    /// Only list utxos locked at this bech32 address
+    #[arg(long)]
+    pub address: Option<String>,
+
+    /// Only list utxos holding assets of this hex policy id
+    #[arg(long)]
+    pub policy: Option<String>,
+
+    /// Max utxos to list, the response has a `nextCursor` if there are more
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// `nextCursor` of the previous page
+    #[arg(long)]
+    pub cursor: Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct ResolveOfflineArgs {
+    /// Hydra-format utxo JSON file, as used by `hydra-node --initial-utxo`
+    #[arg(long)]
+    pub utxos: PathBuf,
+
+    /// Protocol parameters JSON file, as used by `hydra-node --ledger-protocol-parameters`
+    #[arg(long)]
+    pub pparams: PathBuf,
+
+    /// Cardano network ID
+    #[arg(long, default_value_t = 0)]
+    pub network: u8,
+
+    #[arg(long, default_value_t = 10)]
+    pub max_optimize_rounds: usize,
+
+    /// Lovelace added to the fee computed by the compiler
+    #[arg(long, default_value_t = 0)]
+    pub extra_fees: u64,
+
+    /// Keeps the compiler estimated ex units instead of evaluating the scripts
+    #[arg(long)]
+    pub skip_evaluation: bool,
+
+    /// JSON file with the `trp.resolve` params (`tir` and `args`), `-` for stdin
+    pub request: PathBuf,
+}
+
+#[derive(Args, Clone)]
+pub struct DevnetArgs {
+    /// Hydra-format utxo JSON file to seed the ledger with
+    #[arg(long)]
+    pub initial_utxo: PathBuf,
+
+    /// Protocol parameters JSON file, zero fees when unset
+    #[arg(long)]
+    pub protocol_parameters: Option<PathBuf>,
+
+    /// Cardano network ID
+    #[arg(long, default_value_t = 0)]
+    pub network: u8,
+}
+
+#[derive(Args, Clone)]
+pub struct ReplayArgs {
+    /// Current journal file, its rotated files are replayed first
+    pub journal: PathBuf,
+
+    /// RFC 3339 time to stop at, the whole journal is replayed when unset
+    #[arg(long)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+fn rpc_error(error: ErrorObjectOwned) -> anyhow::Error {
+    match error.data() {
+        Some(data) => anyhow::anyhow!("{}: {}", error.message(), data.get()),
+        None => anyhow::anyhow!("{}", error.message()),
+    }
+}
+
+fn read_json(path: &PathBuf) -> anyhow::Result<serde_json::Value> {
+    let content = if path.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin()).context("reading stdin")?
+    } else {
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?
+    };
+
+    serde_json::from_str(&content).context("decoding json")
+}
+
+fn print_json(value: &serde_json::Value) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn check_config(config: &Config) -> anyhow::Result<()> {
+    print_json(&serde_json::to_value(config)?)
+}
+
+pub fn version() -> anyhow::Result<()> {
+    println!("tx3-hydra {}", env!("CARGO_PKG_VERSION"));
+    Ok(())
+}
+
+pub async fn healthcheck(args: HealthcheckArgs, config: Option<Config>) -> anyhow::Result<()> {
+    let url = match (args.url, config) {
+        (Some(url), _) => url,
+        (None, Some(config)) => {
+            let address = config.trp.listen_address.replace("0.0.0.0", "127.0.0.1");
+            format!("http://{address}")
+        }
+        (None, None) => DEFAULT_TRP_URL.to_string(),
+    };
+
+    let path = if args.live { "healthz" } else { "readyz" };
+
+    let response = reqwest::get(format!("{}/{path}", url.trim_end_matches('/')))
+        .await
+        .context("requesting health probe")?;
+
+    if !response.status().is_success() {
+        bail!("server is unhealthy: {}", response.status());
+    }
+
+    println!("{}", response.text().await?);
+    Ok(())
+}
+
+pub async fn resolve(args: ResolveArgs) -> anyhow::Result<()> {
+    let params = read_json(&args.request)?;
+
+    let client = TrpClient::new(args.server.url);
+    let result = client.call("trp.resolve", params).await?;
+
+    print_json(&result)
+}
+
+pub async fn submit(args: SubmitArgs) -> anyhow::Result<()> {
+    let encoding = match args.encoding {
+        Encoding::Hex => "hex",
+        Encoding::Base64 => "base64",
+    };
+
+    let params = json!({
+        "tx": {
+            "payload": args.tx,
+            "encoding": encoding,
+        }
+    });
+
+    let client = TrpClient::new(args.server.url);
+    let result = client.call("trp.submit", params).await?;
+
+    print_json(&result)
+}
+
+pub async fn utxos(args: UtxosArgs) -> anyhow::Result<()> {
+    let params = json!({
+        "address": args.address,
+        "policy": args.policy,
+        "limit": args.limit,
+        "cursor": args.cursor,
+    });
+
+    let client = TrpClient::new(args.server.url);
+    let result = client.call("trp.queryUtxos", params).await?;
+
+    print_json(&result)
+}
+
+pub async fn resolve_offline(args: ResolveOfflineArgs) -> anyhow::Result<()> {
+    let utxos = hydra::read_utxo_file(&args.utxos)?;
+    let hydra_pparams = hydra::read_pparams_file(&args.pparams)?;
+    let pparams = hydra_pparams.to_tx3_pparams(args.network);
+
+    let request =
+        serde_json::from_value(read_json(&args.request)?).context("decoding resolve request")?;
+
+    let store = RwLock::new(utxos);
+    let snapshot = UtxoSnapshot(store.read().await);
+
+    let slot_config = SlotConfig::for_network(args.network);
+    let chain_point = Progress::default().to_chain_point(&slot_config)?;
+
+    let resolved = resolve::resolve(
+        request,
+        &snapshot,
+        &HashSet::new(),
+        pparams,
+        &hydra_pparams.execution_unit_prices,
+        chain_point,
+        &ResolveOptions {
+            extra_fees: args.extra_fees,
+            max_optimize_rounds: args.max_optimize_rounds,
+            evaluation: (!args.skip_evaluation).then_some(slot_config),
+        },
+    )
+    .await
+    .map_err(rpc_error)?;
+
+    let mut report = TxReport::from_cbor(&resolved.payload, Some(&snapshot.0))?;
+    report.optimize_passes = Some(resolved.passes);
+
+    print_json(&json!({
+        "tx": hex::encode(&resolved.payload),
+        "summary": report,
+    }))
+}
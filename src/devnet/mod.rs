@@ -0,0 +1,332 @@
+//! In-process ledger emulator standing in for a hydra head, so that the TRP
+//! server can run locally without a hydra-node.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{RwLock, broadcast};
+use tracing::{debug, info};
+use tx3_cardano::{ChainPoint, PParams, pallas::ledger::traverse::MultiEraTx};
+
+use crate::{
+    backend::Backend,
+    hydra::{
+        self, HealthStatus, Progress, UtxoSnapshot,
+        model::{
+            ConfirmedTx, Event, HeadStatus, HydraMessage, HydraPParams, Snapshot, Transaction,
+            TxID, Utxo, ValidationError,
+        },
+    },
+    ledger::{self, time::SlotConfig},
+};
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Config {
+    pub network: u8,
+    /// Hydra-format utxo file the devnet starts with
+    pub initial_utxo: PathBuf,
+    /// Protocol parameters file, zero fees when unset
+    #[serde(default)]
+    pub protocol_parameters: Option<PathBuf>,
+    /// Slot parameters, derived from `network` if unset
+    #[serde(default)]
+    pub slot_config: Option<SlotConfig>,
+}
+
+pub struct Devnet {
+    network: u8,
+    slot_config: SlotConfig,
+    pparams: HydraPParams,
+    utxos: RwLock<HashMap<TxID, Utxo>>,
+    progress: RwLock<Progress>,
+    snapshot_at: RwLock<Instant>,
+    hydra_channel: Arc<broadcast::Sender<Event>>,
+}
+
+impl Devnet {
+    pub fn try_new(
+        config: Config,
+        hydra_channel: Arc<broadcast::Sender<Event>>,
+    ) -> anyhow::Result<Self> {
+        let utxos = hydra::read_utxo_file(&config.initial_utxo)?;
+
+        let pparams = match &config.protocol_parameters {
+            Some(path) => hydra::read_pparams_file(path)?,
+            None => HydraPParams::default(),
+        };
+
+        info!(utxos = utxos.len(), "Devnet seeded");
+
+        let progress = Progress {
+            seq: 0,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        };
+
+        Ok(Self {
+            network: config.network,
+            slot_config: config
+                .slot_config
+                .unwrap_or_else(|| SlotConfig::for_network(config.network)),
+            pparams,
+            utxos: RwLock::new(utxos),
+            progress: RwLock::new(progress),
+            snapshot_at: RwLock::new(Instant::now()),
+            hydra_channel,
+        })
+    }
+
+    /// Devnet over the given utxos, without fees, for the tests of its users
+    #[cfg(test)]
+    pub(crate) fn seeded(utxos: HashMap<TxID, Utxo>) -> Self {
+        let (hydra_channel, _) = broadcast::channel(16);
+
+        Self {
+            network: 0,
+            slot_config: SlotConfig::for_network(0),
+            pparams: HydraPParams::default(),
+            utxos: RwLock::new(utxos),
+            progress: RwLock::new(Progress::default()),
+            snapshot_at: RwLock::new(Instant::now()),
+            hydra_channel: Arc::new(hydra_channel),
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Err(error) = self.hydra_channel.send(event) {
+            debug!(?error, "failed to send event to internal trp hydra channel");
+        }
+    }
+
+    async fn next_snapshot(&self) {
+        let mut progress = self.progress.write().await;
+        progress.seq += 1;
+        progress.timestamp = chrono::Utc::now().to_rfc3339();
+        *self.snapshot_at.write().await = Instant::now();
+    }
+}
+
+impl Backend for Devnet {
+    async fn get_pparams(&self) -> anyhow::Result<PParams> {
+        Ok(self.pparams.to_tx3_pparams(self.network))
+    }
+
+    async fn protocol_parameters(&self) -> anyhow::Result<HydraPParams> {
+        Ok(self.pparams.clone())
+    }
+
+    async fn chain_point(&self) -> anyhow::Result<ChainPoint> {
+        self.progress.read().await.to_chain_point(&self.slot_config)
+    }
+
+    fn slot_config(&self) -> SlotConfig {
+        self.slot_config
+    }
+
+    async fn read_utxos(&self) -> UtxoSnapshot<'_> {
+        UtxoSnapshot(self.utxos.read().await)
+    }
+
+    /// Validates and applies the tx, replying through the hydra channel just
+    /// like a head would
+    async fn submit(&self, hydra_message: HydraMessage) -> anyhow::Result<()> {
+        let HydraMessage::NewTx(new_tx) = hydra_message;
+
+        let cbor = hex::decode(&new_tx.cbor_hex).context("decoding tx hex")?;
+        let tx = MultiEraTx::decode(&cbor).context("decoding tx")?;
+        let tx_id = tx.hash().to_string();
+
+        let mut utxos = self.utxos.write().await;
+
+        let pparams = self.pparams.to_tx3_pparams(self.network);
+        let violations = ledger::validate(&utxos, &tx, &pparams);
+
+        let event = if violations.is_empty() {
+            ledger::apply_tx(&mut utxos, &tx)?;
+            self.next_snapshot().await;
+            info!(tx_id, "Devnet tx applied");
+            Event::TxValid {
+                tx_id: tx_id.clone(),
+            }
+        } else {
+            let reason = violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ");
+
+            info!(tx_id, reason, "Devnet tx rejected");
+            Event::TxInvalid {
+                transaction: Transaction {
+                    tx_id: tx_id.clone(),
+                },
+                validation_error: ValidationError { reason },
+            }
+        };
+
+        let confirmed = matches!(event, Event::TxValid { .. });
+        self.emit(event);
+
+        // every devnet tx gets a snapshot of its own
+        if confirmed {
+            let progress = self.progress.read().await.clone();
+            self.emit(Event::SnapshotConfirmed {
+                snapshot: Snapshot {
+                    utxo: utxos.clone(),
+                    confirmed: vec![ConfirmedTx {
+                        tx_id,
+                        cbor_hex: new_tx.cbor_hex,
+                    }],
+                },
+                seq: progress.seq,
+                timestamp: progress.timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn events(&self) -> broadcast::Receiver<Event> {
+        self.hydra_channel.subscribe()
+    }
+
+    async fn check_health(&self) -> bool {
+        true
+    }
+
+    async fn health_status(&self) -> HealthStatus {
+        HealthStatus {
+            connected: true,
+            head_status: HeadStatus::Open,
+            snapshot_seq: self.progress.read().await.seq,
+            snapshot_age_secs: Some(self.snapshot_at.read().await.elapsed().as_secs()),
+            pparams_cached: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hydra::model::NewTx,
+        ledger::{payment::build_payment, testing, txid, witness},
+    };
+
+    /// Devnet with 100 ada at the test key address
+    fn devnet() -> Devnet {
+        Devnet::seeded(HashMap::from([(
+            txid("00".repeat(32), 0),
+            testing::lovelace_utxo(&testing::key_address(), 100_000_000),
+        )]))
+    }
+
+    /// Payment from the test key address to the vendor
+    async fn payment(devnet: &Devnet, lovelace: u64) -> anyhow::Result<Vec<u8>> {
+        let pparams = devnet.get_pparams().await?;
+        let utxos = devnet.read_utxos().await;
+
+        let payment = build_payment(
+            utxos.0.iter(),
+            &testing::key_address(),
+            testing::VENDOR,
+            lovelace,
+            &pparams,
+        )?;
+
+        Ok(payment.cbor)
+    }
+
+    fn signed(cbor: &[u8]) -> anyhow::Result<Vec<u8>> {
+        witness::add_vkey_witnesses(cbor, &[testing::vkey_witness(cbor)])
+    }
+
+    async fn submit(devnet: &Devnet, cbor: Vec<u8>) -> anyhow::Result<()> {
+        devnet.submit(HydraMessage::NewTx(NewTx::new(cbor))).await
+    }
+
+    #[tokio::test]
+    async fn applies_valid_txs_in_a_snapshot_of_their_own() -> anyhow::Result<()> {
+        let devnet = devnet();
+        let mut events = devnet.events();
+
+        let cbor = signed(&payment(&devnet, 5_000_000).await?)?;
+        let hash = MultiEraTx::decode(&cbor)?.hash().to_string();
+        submit(&devnet, cbor).await?;
+
+        let Event::TxValid { tx_id } = events.recv().await? else {
+            panic!("tx not accepted");
+        };
+        assert_eq!(tx_id, hash);
+
+        let Event::SnapshotConfirmed { snapshot, seq, .. } = events.recv().await? else {
+            panic!("tx not snapshotted");
+        };
+        assert_eq!(seq, 1);
+        assert_eq!(snapshot.confirmed[0].tx_id, hash);
+
+        let utxos = devnet.read_utxos().await;
+        assert!(!utxos.0.contains_key(&txid("00".repeat(32), 0)));
+        assert!(utxos.0.contains_key(&txid(&hash, 0)));
+        let mut applied: Vec<_> = utxos.0.keys().collect();
+        let mut snapshotted: Vec<_> = snapshot.utxo.keys().collect();
+        applied.sort();
+        snapshotted.sort();
+        assert_eq!(applied, snapshotted);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_double_spends() -> anyhow::Result<()> {
+        let devnet = devnet();
+
+        let first = signed(&payment(&devnet, 5_000_000).await?)?;
+        let second = signed(&payment(&devnet, 7_000_000).await?)?;
+        submit(&devnet, first).await?;
+
+        let mut events = devnet.events();
+        let hash = MultiEraTx::decode(&second)?.hash().to_string();
+        submit(&devnet, second).await?;
+
+        let Event::TxInvalid {
+            transaction,
+            validation_error,
+        } = events.recv().await?
+        else {
+            panic!("double spend accepted");
+        };
+        assert_eq!(transaction.tx_id, hash);
+        assert!(
+            validation_error
+                .reason
+                .contains("not found in the utxo set")
+        );
+
+        // the rejected tx leaves no trace
+        assert_eq!(devnet.progress.read().await.seq, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_txs_failing_validation() -> anyhow::Result<()> {
+        let devnet = devnet();
+        let mut events = devnet.events();
+
+        submit(&devnet, payment(&devnet, 5_000_000).await?).await?;
+
+        let Event::TxInvalid {
+            validation_error, ..
+        } = events.recv().await?
+        else {
+            panic!("unsigned tx accepted");
+        };
+        assert!(validation_error.reason.contains("missing vkey witness"));
+
+        let utxos = devnet.read_utxos().await;
+        assert!(utxos.0.contains_key(&txid("00".repeat(32), 0)));
+
+        Ok(())
+    }
+}
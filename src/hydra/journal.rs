@@ -0,0 +1,251 @@
+//! Append-only record of what the adapter exchanged with the hydra node: the
+//! raw inbound WebSocket messages, the outbound [`HydraMessage`]s and the
+//! fetched protocol parameters, one timestamped JSON line each. Feeding a
+//! journal back through [`HydraAdapter::replay`] rebuilds the adapter state
+//! at any point of it.
+//!
+//! [`HydraMessage`]: super::model::HydraMessage
+//! [`HydraAdapter::replay`]: super::HydraAdapter::replay
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+fn default_max_file_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    10
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Config {
+    /// Current journal file, rotated ones get a `.1`, `.2`, ... suffix
+    pub path: PathBuf,
+    /// Size past which the file is rotated
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// Rotated files kept, the oldest are deleted
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "kind", content = "message", rename_all = "camelCase")]
+pub enum Record {
+    /// WebSocket message from the hydra node, as received
+    Inbound(String),
+    /// Message sent to the hydra node, as sent
+    Outbound(String),
+    /// Body of the protocol parameters endpoint
+    ProtocolParameters(String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Entry {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+struct Writer {
+    config: Config,
+    file: File,
+    size: u64,
+}
+
+/// Records are written by a dedicated thread, so journaling never blocks
+/// the adapter on the disk
+pub struct Journal {
+    sender: Option<mpsc::Sender<Entry>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+fn open(path: &Path) -> anyhow::Result<(File, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening journal {}", path.display()))?;
+    let size = file.metadata()?.len();
+
+    Ok((file, size))
+}
+
+impl Writer {
+    /// Shifts the rotated files by one, dropping the oldest, and starts a
+    /// new current file
+    fn rotate(&mut self) -> anyhow::Result<()> {
+        let path = &self.config.path;
+
+        if self.config.max_files == 0 {
+            fs::remove_file(path)?;
+        } else {
+            let _ = fs::remove_file(rotated(path, self.config.max_files));
+            for index in (1..self.config.max_files).rev() {
+                let from = rotated(path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated(path, index + 1))?;
+                }
+            }
+            fs::rename(path, rotated(path, 1))?;
+        }
+
+        (self.file, self.size) = open(path)?;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &Entry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.config.max_file_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl Journal {
+    pub fn open(config: Config) -> anyhow::Result<Self> {
+        let (file, size) = open(&config.path)?;
+        info!(path = %config.path.display(), "journaling hydra messages");
+
+        let mut writer = Writer { config, file, size };
+        let (sender, receiver) = mpsc::channel::<Entry>();
+
+        let worker = thread::Builder::new()
+            .name("hydra-journal".into())
+            .spawn(move || {
+                for entry in receiver {
+                    if let Err(error) = writer.append(&entry) {
+                        warn!(?error, "failed to write hydra journal");
+                    }
+                }
+            })
+            .context("starting journal writer")?;
+
+        Ok(Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Queues a record for the writer, failures are logged and don't stop
+    /// the adapter
+    pub fn write(&self, record: Record) {
+        let entry = Entry {
+            at: Utc::now(),
+            record,
+        };
+
+        let sent = self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(entry).is_ok());
+
+        if !sent {
+            warn!("hydra journal writer is gone, record dropped");
+        }
+    }
+}
+
+impl Drop for Journal {
+    /// Waits for the queued records to reach the file
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Files of a journal, oldest first
+fn files(path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = (1..)
+        .map(|index| rotated(path, index))
+        .take_while(|path| path.exists())
+        .collect();
+
+    files.reverse();
+    files.push(path.to_path_buf());
+    files
+}
+
+/// Reads the entries of a journal and of its rotated files, oldest first
+pub fn read(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for file in files(path) {
+        let reader = BufReader::new(
+            File::open(&file).with_context(|| format!("opening journal {}", file.display()))?,
+        );
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str(&line)
+                .with_context(|| format!("decoding line {} of {}", number + 1, file.display()))?;
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_rotated_records_in_order() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("hydra-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("journal.jsonl");
+
+        let journal = Journal::open(Config {
+            path: path.clone(),
+            max_file_bytes: 1,
+            max_files: 10,
+        })?;
+        for index in 0..3 {
+            journal.write(Record::Inbound(index.to_string()));
+        }
+        drop(journal);
+
+        let messages: Vec<_> = read(&path)?
+            .into_iter()
+            .map(|entry| match entry.record {
+                Record::Inbound(message) => message,
+                record => panic!("unexpected {record:?}"),
+            })
+            .collect();
+        assert_eq!(messages, ["0", "1", "2"]);
+        assert!(rotated(&path, 2).exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,21 +1,37 @@
-use anyhow::Context;
+use anyhow::{Context, bail};
+use chrono::{DateTime, Utc};
 use futures_util::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use serde::Deserialize;
-use std::{collections::HashMap, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
 use tokio::{
     net::TcpStream,
     sync::{Mutex, RwLock, RwLockReadGuard, broadcast},
 };
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, info};
-use tx3_cardano::PParams;
+use tracing::{debug, info, warn};
+use tx3_cardano::{ChainPoint, PParams};
+
+use crate::{
+    backend::Backend,
+    ledger::time::{self, SlotConfig},
+};
 
+pub mod journal;
 pub mod model;
 
+use journal::{Journal, Record};
 use model::{Event, HeadStatus, HydraMessage, HydraPParams, TxID, Utxo};
 
 pub struct UtxoSnapshot<'a>(pub RwLockReadGuard<'a, HashMap<TxID, Utxo>>);
@@ -28,13 +44,50 @@ pub struct Progress {
     pub timestamp: String,
 }
 
+impl Progress {
+    /// Chain point at the time of the last snapshot, or at the current time
+    /// if no snapshot was seen yet
+    pub fn to_chain_point(&self, slot_config: &SlotConfig) -> anyhow::Result<ChainPoint> {
+        let timestamp = if self.timestamp.is_empty() {
+            time::now_millis()
+        } else {
+            let dt = DateTime::parse_from_rfc3339(&self.timestamp)
+                .context("failed to parse snapshot timestamp")?;
+            dt.timestamp_millis() as u64
+        };
+
+        Ok(ChainPoint {
+            slot: slot_config.slot_at(timestamp),
+            hash: vec![0; 32],
+            timestamp: timestamp as u128,
+        })
+    }
+}
+
+/// Point-in-time view of the adapter state, used by the health probes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthStatus {
+    pub connected: bool,
+    pub head_status: HeadStatus,
+    pub snapshot_seq: u64,
+    /// Seconds since the last snapshot was received, `None` if none was seen yet
+    pub snapshot_age_secs: Option<u64>,
+    pub pparams_cached: bool,
+}
+
 pub struct HydraAdapter {
     config: Config,
     progress: RwLock<Progress>,
     utxos: RwLock<HashMap<TxID, Utxo>>,
+    snapshot_received_at: RwLock<Option<Instant>>,
+    pparams: RwLock<Option<HydraPParams>>,
     head_status: RwLock<HeadStatus>,
-    stream: Mutex<SplitStream<WsStream>>,
-    sink: Mutex<SplitSink<WsStream, Message>>,
+    connected: AtomicBool,
+    /// Unset when replaying a journal
+    stream: Mutex<Option<SplitStream<WsStream>>>,
+    sink: Mutex<Option<SplitSink<WsStream, Message>>>,
+    journal: Option<Journal>,
     hydra_channel: Arc<broadcast::Sender<Event>>,
 }
 
@@ -48,27 +101,91 @@ impl HydraAdapter {
 
         let (write, read) = ws_stream.split();
 
+        let journal = config.journal.clone().map(Journal::open).transpose()?;
+
         let progress = RwLock::new(Progress::default());
         let utxos = RwLock::new(HashMap::new());
-        let stream = Mutex::new(read);
-        let sink = Mutex::new(write);
+        let snapshot_received_at = RwLock::new(None);
+        let pparams = RwLock::new(None);
+        let stream = Mutex::new(Some(read));
+        let sink = Mutex::new(Some(write));
         let head_status = RwLock::new(HeadStatus::Closed);
 
         Ok(Self {
             config,
             progress,
             utxos,
+            snapshot_received_at,
+            pparams,
             stream,
             sink,
+            journal,
             head_status,
+            connected: AtomicBool::new(true),
             hydra_channel,
         })
     }
 
+    /// Adapter without a hydra node, for [`HydraAdapter::replay`]. Submits
+    /// fail and nothing is journaled.
+    pub fn offline(config: Config, hydra_channel: Arc<broadcast::Sender<Event>>) -> Self {
+        Self {
+            config,
+            progress: Default::default(),
+            utxos: Default::default(),
+            snapshot_received_at: Default::default(),
+            pparams: Default::default(),
+            stream: Mutex::new(None),
+            sink: Mutex::new(None),
+            journal: None,
+            head_status: RwLock::new(HeadStatus::Closed),
+            connected: AtomicBool::new(false),
+            hydra_channel,
+        }
+    }
+
+    /// Feeds the inbound messages and protocol parameters of a journal into
+    /// the adapter, up to `until` inclusive. Returns the number of entries
+    /// applied.
+    pub async fn replay(&self, path: &Path, until: Option<DateTime<Utc>>) -> anyhow::Result<usize> {
+        let mut applied = 0;
+
+        for entry in journal::read(path)? {
+            if until.is_some_and(|until| entry.at > until) {
+                break;
+            }
+
+            match entry.record {
+                Record::Inbound(message) => self.handle(&message).await,
+                Record::ProtocolParameters(body) => {
+                    let hydra_pparams = serde_json::from_str::<HydraPParams>(&body)
+                        .context("decoding journaled pparams")?;
+                    *self.pparams.write().await = Some(hydra_pparams);
+                }
+                Record::Outbound(message) => debug!(message, "journaled outbound message"),
+            }
+
+            applied += 1;
+        }
+
+        info!(applied, "hydra journal replayed");
+        Ok(applied)
+    }
+
+    fn journal(&self, record: Record) {
+        if let Some(journal) = &self.journal {
+            journal.write(record);
+        }
+    }
+
     pub async fn subscribe(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
         info!("Listening Hydra events");
 
         let mut stream = self.stream.lock().await;
+        let Some(stream) = stream.as_mut() else {
+            bail!("no hydra connection to subscribe to");
+        };
+
         let message_processing = async {
             while let Some(result) = stream.next().await {
                 let message = result?;
@@ -78,53 +195,24 @@ impl HydraAdapter {
                     break;
                 }
 
-                match serde_json::from_str::<Event>(message.to_text().unwrap()) {
-                    Ok(event) => match event {
-                        Event::Greetings {
-                            head_status,
-                            snapshot,
-                        } => {
-                            info!(utxos = snapshot.len(), "Greetings event");
-                            self.update_utxos(snapshot).await;
-                            *self.head_status.write().await = head_status;
-                        }
-                        Event::SnapshotConfirmed {
-                            snapshot,
-                            seq,
-                            timestamp,
-                        } => {
-                            self.update_utxos(snapshot.utxo).await;
-                            self.update_progress(seq, timestamp).await;
-                        }
-                        Event::HeadIsOpen { snapshot } => {
-                            self.update_utxos(snapshot).await;
-                            *self.head_status.write().await = HeadStatus::Open;
-                        }
-                        Event::TxInvalid { .. } | Event::TxValid { .. } => {
-                            if let Err(error) = self.hydra_channel.send(event.clone()) {
-                                debug!(
-                                    ?error,
-                                    "failed to send event to internal trp hydra channel"
-                                );
-                            }
-                        }
-                    },
-
-                    Err(_) => {
-                        let message = message.to_text().unwrap();
-                        debug!(?message, "Hydra event not supported")
-                    }
-                }
+                let message = message.to_text().unwrap();
+                self.journal(Record::Inbound(message.to_string()));
+                self.handle(message).await;
             }
 
             Ok::<(), anyhow::Error>(())
         };
 
+        let message_processing = async {
+            let result = message_processing.await;
+            self.connected.store(false, Ordering::SeqCst);
+            result
+        };
+
         let cancellation = async {
             cancellation_token.cancelled().await;
             info!("gracefully shuting down hydra");
-
-            Ok::<(), anyhow::Error>(())
+            self.close().await
         };
 
         tokio::select! {
@@ -141,23 +229,116 @@ impl HydraAdapter {
         Ok(())
     }
 
-    pub async fn submit(&self, hydra_message: HydraMessage) -> anyhow::Result<()> {
+    /// Applies a message of the hydra node to the adapter state
+    async fn handle(&self, message: &str) {
+        match serde_json::from_str::<Event>(message) {
+            Ok(event) => match event {
+                Event::Greetings {
+                    head_status,
+                    snapshot,
+                } => {
+                    info!(utxos = snapshot.len(), "Greetings event");
+                    self.update_utxos(snapshot).await;
+                    *self.head_status.write().await = head_status;
+
+                    // replays take the pparams from the journal
+                    if self.connected.load(Ordering::SeqCst)
+                        && let Err(error) = self.get_pparams().await
+                    {
+                        warn!(?error, "failed to prefetch hydra pparams");
+                    }
+                }
+                Event::SnapshotConfirmed {
+                    ref snapshot,
+                    seq,
+                    ref timestamp,
+                } => {
+                    self.update_utxos(snapshot.utxo.clone()).await;
+                    self.update_progress(seq, timestamp.clone()).await;
+                    self.forward(event);
+                }
+                Event::HeadIsOpen { ref snapshot } => {
+                    self.update_utxos(snapshot.clone()).await;
+                    *self.head_status.write().await = HeadStatus::Open;
+                    self.forward(event);
+                }
+                Event::HeadIsClosed | Event::ReadyToFanout | Event::HeadIsFinalized => {
+                    if let Some(head_status) = event.head_status() {
+                        info!(?head_status, "Head status changed");
+                        *self.head_status.write().await = head_status;
+                    }
+                    self.forward(event);
+                }
+                Event::TxInvalid { .. } | Event::TxValid { .. } => {
+                    self.forward(event);
+                }
+            },
+
+            Err(_) => {
+                debug!(?message, "Hydra event not supported")
+            }
+        }
+    }
+
+    /// Sends a WebSocket Close frame to the hydra node
+    pub async fn close(&self) -> anyhow::Result<()> {
         let mut sink = self.sink.lock().await;
-        let message_bytes = serde_json::to_vec(&hydra_message)?;
-        let message = Message::binary(message_bytes);
-        sink.send(message)
+        let Some(sink) = sink.as_mut() else {
+            return Ok(());
+        };
+        sink.close()
             .await
-            .context("failed to send message to hydra head")?;
+            .context("failed to close hydra websocket")?;
+        self.connected.store(false, Ordering::SeqCst);
         Ok(())
     }
 
-    pub async fn check_health(&self) -> bool {
-        let mut sink = self.sink.lock().await;
-        let result = sink.send(Message::Ping(Vec::new().into())).await;
-        result.is_ok()
+    pub async fn get_progress(&self) -> Progress {
+        self.progress.read().await.clone()
+    }
+
+    pub async fn update_utxos(&self, utxos: HashMap<TxID, Utxo>) {
+        let utxos_len = utxos.len();
+        *self.utxos.write().await = utxos;
+        *self.snapshot_received_at.write().await = Some(Instant::now());
+        info!(utxos = utxos_len, "Snapshot updated");
+    }
+
+    pub async fn update_progress(&self, seq: u64, timestamp: String) {
+        *self.progress.write().await = Progress { seq, timestamp };
+    }
+
+    /// Passes an event on to the trp subscribers
+    fn forward(&self, event: Event) {
+        if let Err(error) = self.hydra_channel.send(event) {
+            debug!(?error, "failed to send event to internal trp hydra channel");
+        }
+    }
+}
+
+impl Backend for HydraAdapter {
+    async fn read_utxos(&self) -> UtxoSnapshot<'_> {
+        UtxoSnapshot(self.utxos.read().await)
+    }
+
+    async fn get_pparams(&self) -> anyhow::Result<PParams> {
+        Ok(self
+            .protocol_parameters()
+            .await?
+            .to_tx3_pparams(self.config.network))
     }
 
-    pub async fn get_pparams(&self) -> anyhow::Result<PParams> {
+    /// Protocol parameters of the head. They can't change while the head is
+    /// open, so they are fetched once and then served from memory.
+    async fn protocol_parameters(&self) -> anyhow::Result<HydraPParams> {
+        if let Some(hydra_pparams) = self.pparams.read().await.as_ref() {
+            return Ok(hydra_pparams.clone());
+        }
+
+        if self.sink.lock().await.is_none() {
+            bail!("protocol parameters missing from the replayed journal");
+        }
+
         let client = reqwest::Client::new();
 
         let req = client
@@ -170,33 +351,92 @@ impl HydraAdapter {
             .await
             .context("fetching http pparams endpoint")?;
 
-        let hydra_pparams = res
-            .json::<HydraPParams>()
-            .await
-            .context("decoding pparams")?;
+        let body = res.text().await.context("reading pparams")?;
+        let hydra_pparams =
+            serde_json::from_str::<HydraPParams>(&body).context("decoding pparams")?;
+        self.journal(Record::ProtocolParameters(body));
 
-        let pparams = hydra_pparams.to_tx3_pparams(self.config.network);
+        *self.pparams.write().await = Some(hydra_pparams.clone());
 
-        Ok(pparams)
+        Ok(hydra_pparams)
     }
 
-    pub async fn get_progress(&self) -> Progress {
-        self.progress.read().await.clone()
+    async fn chain_point(&self) -> anyhow::Result<ChainPoint> {
+        self.get_progress()
+            .await
+            .to_chain_point(&self.slot_config())
     }
 
-    pub async fn update_utxos(&self, utxos: HashMap<TxID, Utxo>) {
-        let utxos_len = utxos.len();
-        *self.utxos.write().await = utxos;
-        info!(utxos = utxos_len, "Snapshot updated");
+    fn slot_config(&self) -> SlotConfig {
+        self.config
+            .slot_config
+            .unwrap_or_else(|| SlotConfig::for_network(self.config.network))
     }
 
-    pub async fn update_progress(&self, seq: u64, timestamp: String) {
-        *self.progress.write().await = Progress { seq, timestamp };
+    async fn submit(&self, hydra_message: HydraMessage) -> anyhow::Result<()> {
+        let mut sink = self.sink.lock().await;
+        let Some(sink) = sink.as_mut() else {
+            bail!("replaying a journal, submits are disabled");
+        };
+
+        let message_bytes = serde_json::to_vec(&hydra_message)?;
+        self.journal(Record::Outbound(
+            String::from_utf8_lossy(&message_bytes).into(),
+        ));
+        let message = Message::binary(message_bytes);
+        sink.send(message)
+            .await
+            .context("failed to send message to hydra head")?;
+        Ok(())
     }
 
-    pub async fn read_utxos(&self) -> UtxoSnapshot<'_> {
-        UtxoSnapshot(self.utxos.read().await)
+    fn events(&self) -> broadcast::Receiver<Event> {
+        self.hydra_channel.subscribe()
+    }
+
+    async fn check_health(&self) -> bool {
+        if !self.connected.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let mut sink = self.sink.lock().await;
+        let Some(sink) = sink.as_mut() else {
+            return false;
+        };
+        let result = sink.send(Message::Ping(Vec::new().into())).await;
+        result.is_ok()
     }
+
+    async fn health_status(&self) -> HealthStatus {
+        let progress = self.progress.read().await;
+        let snapshot_age_secs = self
+            .snapshot_received_at
+            .read()
+            .await
+            .map(|received_at| received_at.elapsed().as_secs());
+
+        HealthStatus {
+            connected: self.connected.load(Ordering::SeqCst),
+            head_status: self.head_status.read().await.clone(),
+            snapshot_seq: progress.seq,
+            snapshot_age_secs,
+            pparams_cached: self.pparams.read().await.is_some(),
+        }
+    }
+}
+
+/// Reads a Hydra-format utxo file, as accepted by `hydra-node --initial-utxo`
+pub fn read_utxo_file(path: &Path) -> anyhow::Result<HashMap<TxID, Utxo>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_str(&content).context("decoding hydra utxo file")
+}
+
+/// Reads a protocol parameters file, as accepted by `hydra-node --ledger-protocol-parameters`
+pub fn read_pparams_file(path: &Path) -> anyhow::Result<HydraPParams> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_str(&content).context("decoding hydra pparams file")
 }
 
 impl HydraPParams {
@@ -216,9 +456,15 @@ impl HydraPParams {
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     network: u8,
     ws_url: String,
     http_url: String,
+    /// Slot parameters of the L1 the head runs on, derived from `network` if unset
+    #[serde(default)]
+    slot_config: Option<SlotConfig>,
+    /// Journal of the exchanged messages, disabled if unset
+    #[serde(default)]
+    journal: Option<journal::Config>,
 }
@@ -1,18 +1,21 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, ser::SerializeStruct};
+use tracing::warn;
+
+use crate::ledger::eval::ExUnits;
 
 /// Transaction Hash # Index
 pub type TxID = String;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(tag = "tag")]
 pub enum Event {
     Greetings {
         #[serde(rename = "headStatus")]
         head_status: HeadStatus,
 
-        #[serde(alias = "snapshotUtxo")]
+        #[serde(rename = "snapshotUtxo", alias = "snapshot")]
         snapshot: HashMap<TxID, Utxo>,
     },
     SnapshotConfirmed {
@@ -21,22 +24,39 @@ pub enum Event {
         timestamp: String,
     },
     HeadIsOpen {
-        #[serde(alias = "utxo")]
+        #[serde(rename = "utxo", alias = "snapshot")]
         snapshot: HashMap<TxID, Utxo>,
     },
     TxValid {
-        #[serde(alias = "transactionId")]
+        #[serde(rename = "transactionId", alias = "tx_id")]
         tx_id: String,
     },
     TxInvalid {
         transaction: Transaction,
 
-        #[serde(alias = "validationError")]
+        #[serde(rename = "validationError", alias = "validation_error")]
         validation_error: ValidationError,
     },
+    HeadIsClosed,
+    ReadyToFanout,
+    HeadIsFinalized,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl Event {
+    /// Status the head moves to with this event, if it changes it
+    pub fn head_status(&self) -> Option<HeadStatus> {
+        match self {
+            Event::Greetings { head_status, .. } => Some(head_status.clone()),
+            Event::HeadIsOpen { .. } => Some(HeadStatus::Open),
+            Event::HeadIsClosed => Some(HeadStatus::Closed),
+            Event::ReadyToFanout => Some(HeadStatus::FanoutPossible),
+            Event::HeadIsFinalized => Some(HeadStatus::Final),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum HeadStatus {
     Idle,
     Initializing,
@@ -46,9 +66,37 @@ pub enum HeadStatus {
     Final,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Snapshot {
     pub utxo: HashMap<TxID, Utxo>,
+    /// Txs the snapshot confirms, not sent by older hydra nodes. Entries
+    /// that don't decode are skipped rather than dropping the snapshot.
+    #[serde(default, deserialize_with = "lenient_confirmed")]
+    pub confirmed: Vec<ConfirmedTx>,
+}
+
+fn lenient_confirmed<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ConfirmedTx>, D::Error> {
+    let entries = Option::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+
+    Ok(entries
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|entry| {
+            serde_json::from_value(entry)
+                .inspect_err(|error| warn!(?error, "skipping undecodable confirmed tx"))
+                .ok()
+        })
+        .collect())
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConfirmedTx {
+    #[serde(rename = "txId")]
+    pub tx_id: String,
+    #[serde(rename = "cborHex")]
+    pub cbor_hex: String,
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
@@ -67,13 +115,13 @@ impl From<HydraPParamsPlutusVersion> for u8 {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Transaction {
-    #[serde(alias = "txId")]
+    #[serde(rename = "txId", alias = "tx_id")]
     pub tx_id: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ValidationError {
     pub reason: String,
 }
@@ -91,10 +139,131 @@ pub struct HydraPParams {
 
     #[serde(rename = "costModels")]
     pub cost_models: HashMap<HydraPParamsPlutusVersion, Vec<i64>>,
+
+    #[serde(rename = "executionUnitPrices")]
+    pub execution_unit_prices: ExUnitPrices,
+
+    #[serde(rename = "maxTxExecutionUnits")]
+    pub max_tx_execution_units: ExUnits,
+
+    #[serde(rename = "maxTxSize")]
+    pub max_tx_size: u64,
+
+    #[serde(rename = "maxValueSize")]
+    pub max_value_size: u64,
+
+    #[serde(rename = "collateralPercentage")]
+    pub collateral_percentage: u64,
+
+    #[serde(rename = "maxCollateralInputs")]
+    pub max_collateral_inputs: u64,
+
+    #[serde(rename = "stakeAddressDeposit")]
+    pub stake_address_deposit: u64,
+
+    #[serde(rename = "stakePoolDeposit")]
+    pub stake_pool_deposit: u64,
+
+    #[serde(rename = "minFeeRefScriptCostPerByte")]
+    pub min_fee_ref_script_cost_per_byte: Ratio,
+}
+
+/// A head without fees, with the limits of the hydra-node default parameters
+impl Default for HydraPParams {
+    fn default() -> Self {
+        Self {
+            tx_fee_per_byte: 0,
+            tx_fee_fixed: 0,
+            utxo_cost_per_byte: 0,
+            cost_models: HashMap::new(),
+            execution_unit_prices: ExUnitPrices::default(),
+            max_tx_execution_units: ExUnits {
+                memory: 14_000_000,
+                steps: 10_000_000_000,
+            },
+            max_tx_size: 16_384,
+            max_value_size: 5_000,
+            collateral_percentage: 150,
+            max_collateral_inputs: 3,
+            stake_address_deposit: 2_000_000,
+            stake_pool_deposit: 500_000_000,
+            min_fee_ref_script_cost_per_byte: Ratio {
+                numerator: 15,
+                denominator: 1,
+            },
+        }
+    }
+}
+
+/// Lovelace per unit of memory and cpu steps of the scripts
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct ExUnitPrices {
+    #[serde(rename = "priceMemory")]
+    pub price_memory: Ratio,
+
+    #[serde(rename = "priceSteps")]
+    pub price_steps: Ratio,
+}
+
+/// Exact fraction of a price the node reports as a decimal number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ratio {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Default for Ratio {
+    fn default() -> Self {
+        Self {
+            numerator: 0,
+            denominator: 1,
+        }
+    }
+}
+
+impl Ratio {
+    /// Parses the shortest decimal form of the number, so `0.0577` is
+    /// `577/10000` rather than the nearest binary fraction
+    pub fn from_decimal(value: f64) -> Option<Self> {
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+
+        let decimal = value.to_string();
+        let (whole, fraction) = decimal.split_once('.').unwrap_or((&decimal, ""));
+
+        let denominator = 10u64.checked_pow(fraction.len() as u32)?;
+        let numerator = format!("{whole}{fraction}").parse::<u64>().ok()?;
+
+        let gcd = gcd(numerator, denominator);
+        Some(Self {
+            numerator: numerator / gcd,
+            denominator: denominator / gcd,
+        })
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+impl<'de> Deserialize<'de> for Ratio {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = f64::deserialize(deserializer)?;
+        Ratio::from_decimal(value)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid price {value}")))
+    }
 }
 
 /// Hydra head utxo data model
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Utxo {
     /// A bech-32 encoded Cardano address
     pub address: String,
@@ -126,7 +295,7 @@ pub struct Utxo {
     pub value: Value,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[allow(dead_code)]
 pub struct ReferenceScript {
     /// Base16 encoding
@@ -139,7 +308,7 @@ pub struct ReferenceScript {
     pub r#type: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum AssetValue {
     Lovelace(u64),
@@ -147,7 +316,7 @@ pub enum AssetValue {
 }
 
 /// Map of asset IDs to amounts
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Value {
     #[serde(flatten)]
     pub assets: HashMap<String, AssetValue>,
@@ -189,7 +358,7 @@ impl Serialize for HydraMessage {
 }
 
 /// Submit new tx using Websocket
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NewTx {
     pub r#type: String,
     pub description: String,
@@ -210,3 +379,119 @@ impl NewTx {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_undecodable_confirmed_txs() -> anyhow::Result<()> {
+        let message = r#"{
+            "tag": "SnapshotConfirmed",
+            "snapshot": {
+                "utxo": {},
+                "confirmed": [
+                    { "txId": "aa", "cborHex": "84a0" },
+                    { "txId": "bb" },
+                    42
+                ]
+            },
+            "seq": 1,
+            "timestamp": "2025-01-01T00:00:00Z"
+        }"#;
+
+        let Event::SnapshotConfirmed { snapshot, .. } = serde_json::from_str(message)? else {
+            panic!("not a confirmed snapshot");
+        };
+
+        assert_eq!(snapshot.confirmed.len(), 1);
+        assert_eq!(snapshot.confirmed[0].tx_id, "aa");
+
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_head_lifecycle_events() -> anyhow::Result<()> {
+        let closed: Event = serde_json::from_str(
+            r#"{
+                "tag": "HeadIsClosed",
+                "headId": "ab",
+                "snapshotNumber": 3,
+                "contestationDeadline": "2025-01-01T00:00:00Z"
+            }"#,
+        )?;
+        assert_eq!(closed.head_status(), Some(HeadStatus::Closed));
+
+        let fanout: Event = serde_json::from_str(r#"{ "tag": "ReadyToFanout", "headId": "ab" }"#)?;
+        assert_eq!(fanout.head_status(), Some(HeadStatus::FanoutPossible));
+
+        let finalized: Event =
+            serde_json::from_str(r#"{ "tag": "HeadIsFinalized", "headId": "ab", "utxo": {} }"#)?;
+        assert_eq!(finalized.head_status(), Some(HeadStatus::Final));
+
+        Ok(())
+    }
+
+    #[test]
+    fn accepts_snapshots_without_confirmed_txs() -> anyhow::Result<()> {
+        let snapshot: Snapshot = serde_json::from_str(r#"{ "utxo": {} }"#)?;
+        assert!(snapshot.confirmed.is_empty());
+
+        let snapshot: Snapshot = serde_json::from_str(r#"{ "utxo": {}, "confirmed": null }"#)?;
+        assert!(snapshot.confirmed.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_prices_as_exact_fractions() -> anyhow::Result<()> {
+        let prices: ExUnitPrices =
+            serde_json::from_str(r#"{ "priceMemory": 0.0577, "priceSteps": 7.21e-5 }"#)?;
+
+        assert_eq!(
+            prices.price_memory,
+            Ratio {
+                numerator: 577,
+                denominator: 10_000
+            }
+        );
+        assert_eq!(
+            prices.price_steps,
+            Ratio {
+                numerator: 721,
+                denominator: 10_000_000
+            }
+        );
+
+        let free: ExUnitPrices = serde_json::from_str(r#"{ "priceMemory": 0, "priceSteps": 0 }"#)?;
+        assert_eq!(free.price_memory, Ratio::default());
+
+        assert!(serde_json::from_str::<Ratio>("-1").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_the_limits_of_the_head() -> anyhow::Result<()> {
+        let pparams: HydraPParams = serde_json::from_str(include_str!(
+            "../../examples/vending-machine/chain/protocol-parameters.json"
+        ))?;
+
+        assert_eq!(pparams.max_tx_size, 16_384);
+        assert_eq!(pparams.max_value_size, 5_000);
+        assert_eq!(pparams.collateral_percentage, 150);
+        assert_eq!(pparams.max_collateral_inputs, 3);
+        assert_eq!(pparams.stake_address_deposit, 2_000_000);
+        assert_eq!(pparams.stake_pool_deposit, 500_000_000);
+        assert_eq!(
+            pparams.max_tx_execution_units,
+            ExUnits {
+                memory: 14_000_000,
+                steps: 10_000_000_000
+            }
+        );
+        assert_eq!(pparams.min_fee_ref_script_cost_per_byte.to_f64(), 15.0);
+
+        Ok(())
+    }
+}
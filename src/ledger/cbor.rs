@@ -0,0 +1,345 @@
+//! Byte-level edits of transaction CBOR. Everything that is not edited is
+//! copied verbatim, so hashes and signatures over untouched parts survive.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, bail, ensure};
+use tx3_cardano::pallas::codec::minicbor::{
+    Decoder, Encoder,
+    data::{Tag, Type},
+};
+
+use crate::hydra::model::{AssetValue, Utxo};
+
+const SET_TAG: u64 = 258;
+
+/// Raw elements of a `[body, witness_set, is_valid, auxiliary_data]` tx
+pub struct TxParts<'a> {
+    pub body: &'a [u8],
+    pub witness_set: &'a [u8],
+    pub is_valid: &'a [u8],
+    pub auxiliary_data: &'a [u8],
+}
+
+fn next_raw<'a>(decoder: &mut Decoder<'a>) -> anyhow::Result<&'a [u8]> {
+    let input = decoder.input();
+    let start = decoder.position();
+    decoder.skip()?;
+    Ok(&input[start..decoder.position()])
+}
+
+pub fn split_tx(cbor: &[u8]) -> anyhow::Result<TxParts<'_>> {
+    let mut decoder = Decoder::new(cbor);
+    ensure!(decoder.array()? == Some(4), "tx is not a 4 elements array");
+
+    Ok(TxParts {
+        body: next_raw(&mut decoder)?,
+        witness_set: next_raw(&mut decoder)?,
+        is_valid: next_raw(&mut decoder)?,
+        auxiliary_data: next_raw(&mut decoder)?,
+    })
+}
+
+pub fn join_tx(parts: &TxParts) -> Vec<u8> {
+    // array(4) header
+    let mut cbor = vec![0x84];
+    cbor.extend_from_slice(parts.body);
+    cbor.extend_from_slice(parts.witness_set);
+    cbor.extend_from_slice(parts.is_valid);
+    cbor.extend_from_slice(parts.auxiliary_data);
+    cbor
+}
+
+/// Map with unsigned integer keys and raw values, the shape of tx bodies
+/// and witness sets
+#[derive(Default)]
+pub struct CborMap(BTreeMap<u64, Vec<u8>>);
+
+impl CborMap {
+    pub fn decode(cbor: &[u8]) -> anyhow::Result<Self> {
+        let mut decoder = Decoder::new(cbor);
+        let len = decoder.map()?;
+
+        let mut entries = BTreeMap::new();
+        let mut read = 0;
+        loop {
+            match len {
+                Some(len) if read == len => break,
+                None if decoder.datatype()? == Type::Break => break,
+                _ => {}
+            }
+
+            let key = decoder.u64()?;
+            entries.insert(key, next_raw(&mut decoder)?.to_vec());
+            read += 1;
+        }
+
+        Ok(Self(entries))
+    }
+
+    pub fn get(&self, key: u64) -> Option<&[u8]> {
+        self.0.get(&key).map(Vec::as_slice)
+    }
+
+    pub fn insert(&mut self, key: u64, raw: Vec<u8>) {
+        self.0.insert(key, raw);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.map(self.0.len() as u64).unwrap();
+
+        for (key, raw) in &self.0 {
+            encoder.u64(*key).unwrap();
+            encoder.writer_mut().extend_from_slice(raw);
+        }
+
+        encoder.into_writer()
+    }
+}
+
+/// Iterates the elements of a definite or indefinite array or map header
+/// already consumed from the decoder
+pub fn for_each_item<'a>(
+    decoder: &mut Decoder<'a>,
+    len: Option<u64>,
+    mut item: impl FnMut(&mut Decoder<'a>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut read = 0;
+    loop {
+        match len {
+            Some(len) if read == len => return Ok(()),
+            None if decoder.datatype()? == Type::Break => {
+                decoder.skip()?;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        item(decoder)?;
+        read += 1;
+    }
+}
+
+/// Copies the next item of the decoder to the encoder as is
+pub fn copy_raw(decoder: &mut Decoder, encoder: &mut Encoder<Vec<u8>>) -> anyhow::Result<()> {
+    let input = decoder.input();
+    let start = decoder.position();
+    decoder.skip()?;
+    encoder
+        .writer_mut()
+        .extend_from_slice(&input[start..decoder.position()]);
+    Ok(())
+}
+
+/// Re-encodes a value with a different coin, keeping its multiasset as is
+fn replace_coin(
+    decoder: &mut Decoder,
+    encoder: &mut Encoder<Vec<u8>>,
+    coin: u64,
+) -> anyhow::Result<()> {
+    match decoder.datatype()? {
+        Type::Array => {
+            decoder.array()?;
+            decoder.skip()?;
+            encoder.array(2)?.u64(coin)?;
+            copy_raw(decoder, encoder)?;
+        }
+        _ => {
+            decoder.skip()?;
+            encoder.u64(coin)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the coin of a legacy array or post-alonzo map output
+pub fn set_output_coin(raw: &[u8], coin: u64) -> anyhow::Result<Vec<u8>> {
+    let mut decoder = Decoder::new(raw);
+    let mut encoder = Encoder::new(Vec::new());
+
+    match decoder.datatype()? {
+        Type::Map => {
+            let len = decoder.map()?.context("indefinite output map")?;
+            encoder.map(len)?;
+            for _ in 0..len {
+                let key = decoder.u64()?;
+                encoder.u64(key)?;
+                if key == 1 {
+                    replace_coin(&mut decoder, &mut encoder, coin)?;
+                } else {
+                    copy_raw(&mut decoder, &mut encoder)?;
+                }
+            }
+        }
+        Type::Array => {
+            let len = decoder.array()?.context("indefinite output array")?;
+            encoder.array(len)?;
+            for index in 0..len {
+                if index == 1 {
+                    replace_coin(&mut decoder, &mut encoder, coin)?;
+                } else {
+                    copy_raw(&mut decoder, &mut encoder)?;
+                }
+            }
+        }
+        other => bail!("unexpected output type {other}"),
+    }
+
+    Ok(encoder.into_writer())
+}
+
+/// Raw items of a definite or indefinite array, optionally wrapped in a set tag
+pub fn array_items(raw: &[u8]) -> anyhow::Result<(Vec<Vec<u8>>, bool)> {
+    let mut decoder = Decoder::new(raw);
+
+    let tagged = decoder.datatype()? == Type::Tag;
+    if tagged {
+        decoder.tag()?;
+    }
+
+    let len = decoder.array()?;
+    let mut items = Vec::new();
+    for_each_item(&mut decoder, len, |decoder| {
+        let mut item = Encoder::new(Vec::new());
+        copy_raw(decoder, &mut item)?;
+        items.push(item.into_writer());
+        Ok(())
+    })?;
+
+    Ok((items, tagged))
+}
+
+/// Array of raw items, wrapped in a set tag if `tagged`
+pub fn encode_array(items: &[Vec<u8>], tagged: bool) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new());
+    if tagged {
+        encoder.tag(Tag::new(SET_TAG)).unwrap();
+    }
+    encoder.array(items.len() as u64).unwrap();
+    for item in items {
+        encoder.writer_mut().extend_from_slice(item);
+    }
+    encoder.into_writer()
+}
+
+pub fn bytes(value: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.bytes(value).unwrap();
+    encoder.into_writer()
+}
+
+pub fn uint(value: u64) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.u64(value).unwrap();
+    encoder.into_writer()
+}
+
+pub fn encode_input(hash: &[u8], index: u64) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new());
+    encoder
+        .array(2)
+        .unwrap()
+        .bytes(hash)
+        .unwrap()
+        .u64(index)
+        .unwrap();
+    encoder.into_writer()
+}
+
+fn encode_value(encoder: &mut Encoder<Vec<u8>>, utxo: &Utxo) -> anyhow::Result<()> {
+    let mut lovelace = 0;
+    let mut multiasset: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, u64>> = BTreeMap::new();
+
+    for (policy, value) in &utxo.value.assets {
+        match value {
+            AssetValue::Lovelace(amount) => lovelace = *amount,
+            AssetValue::Multi(assets) => {
+                let policy = hex::decode(policy).context("decoding policy id")?;
+                let entry = multiasset.entry(policy).or_default();
+                for (name, amount) in assets {
+                    entry.insert(hex::decode(name).context("decoding asset name")?, *amount);
+                }
+            }
+        }
+    }
+
+    if multiasset.is_empty() {
+        encoder.u64(lovelace)?;
+        return Ok(());
+    }
+
+    encoder
+        .array(2)?
+        .u64(lovelace)?
+        .map(multiasset.len() as u64)?;
+    for (policy, assets) in multiasset {
+        encoder.bytes(&policy)?.map(assets.len() as u64)?;
+        for (name, amount) in assets {
+            encoder.bytes(&name)?.u64(amount)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a hydra utxo as a post-alonzo ledger output
+pub fn encode_output(utxo: &Utxo) -> anyhow::Result<Vec<u8>> {
+    use tx3_cardano::pallas::ledger::addresses::Address;
+
+    let address: Address = utxo.address.parse().context("decoding utxo address")?;
+
+    let datum = match (&utxo.inline_datum_raw, &utxo.datumhash) {
+        (Some(raw), _) => Some((1, hex::decode(raw).context("decoding inline datum")?)),
+        (None, Some(hash)) => Some((0, hex::decode(hash).context("decoding datum hash")?)),
+        (None, None) => None,
+    };
+
+    let script = match &utxo.reference_script {
+        Some(script) => {
+            let tag = match script.r#type.as_str() {
+                "SimpleScript" => 0,
+                "PlutusScriptV1" => 1,
+                "PlutusScriptV2" => 2,
+                "PlutusScriptV3" => 3,
+                other => bail!("unknown reference script type {other}"),
+            };
+            Some((
+                tag,
+                hex::decode(&script.cbor_hex).context("decoding script")?,
+            ))
+        }
+        None => None,
+    };
+
+    let len = 2 + datum.is_some() as u64 + script.is_some() as u64;
+
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.map(len)?;
+    encoder.u8(0)?.bytes(&address.to_vec())?;
+    encoder.u8(1)?;
+    encode_value(&mut encoder, utxo)?;
+
+    if let Some((kind, datum)) = datum {
+        encoder.u8(2)?.array(2)?.u8(kind)?;
+        match kind {
+            0 => encoder.bytes(&datum)?,
+            _ => encoder.tag(Tag::new(24))?.bytes(&datum)?,
+        };
+    }
+
+    if let Some((tag, script)) = script {
+        // the script envelope cbor is already the cbor of the script bytes
+        let mut inner = Encoder::new(Vec::new());
+        inner.array(2)?.u8(tag)?;
+        inner.writer_mut().extend_from_slice(&script);
+
+        encoder
+            .u8(3)?
+            .tag(Tag::new(24))?
+            .bytes(&inner.into_writer())?;
+    }
+
+    Ok(encoder.into_writer())
+}
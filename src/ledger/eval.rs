@@ -0,0 +1,608 @@
+//! Phase-2 evaluation of the Plutus scripts of a tx against a utxo set.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{Context, anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use tx3_cardano::{
+    PParams,
+    pallas::{
+        codec::minicbor::{Decoder, Encoder, data::Type},
+        crypto::hash::Hasher,
+        ledger::traverse::MultiEraTx,
+    },
+};
+
+use super::{
+    cbor::{self, CborMap, TxParts},
+    fee,
+    time::SlotConfig,
+    txid,
+};
+use crate::hydra::model::{ExUnitPrices, TxID, Utxo};
+
+/// Execution budget of a script run
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExUnits {
+    pub memory: u64,
+    pub steps: u64,
+}
+
+/// Mainnet `maxTxExecutionUnits`, the budget each script run starts with
+pub const MAX_TX_EX_UNITS: ExUnits = ExUnits {
+    memory: 14_000_000,
+    steps: 10_000_000_000,
+};
+
+const WITNESS_SET_REDEEMERS: u64 = 5;
+const WITNESS_SET_DATUMS: u64 = 4;
+const BODY_SCRIPT_DATA_HASH: u64 = 11;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedeemerBudget {
+    pub tag: &'static str,
+    pub index: u32,
+    pub memory: u64,
+    pub steps: u64,
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    /// The tx or its inputs can't be put in front of the evaluator
+    Setup(anyhow::Error),
+    /// A script failed, with the evaluator error and its trace
+    Script(String),
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Setup(error) => write!(f, "{error:#}"),
+            EvalError::Script(trace) => write!(f, "script evaluation failed: {trace}"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+fn redeemer_tag(tag: u8) -> &'static str {
+    match tag {
+        0 => "spend",
+        1 => "mint",
+        2 => "cert",
+        3 => "reward",
+        4 => "vote",
+        5 => "propose",
+        _ => "unknown",
+    }
+}
+
+fn redeemer_tag_id(tag: &str) -> Option<u8> {
+    (0..=5).find(|id| redeemer_tag(*id) == tag)
+}
+
+/// Decodes a `[tag, index, data, [mem, steps]]` redeemer as returned by the evaluator
+fn decode_budget(raw: &[u8]) -> anyhow::Result<RedeemerBudget> {
+    let mut decoder = Decoder::new(raw);
+    decoder.array()?;
+    let tag = decoder.u8()?;
+    let index = decoder.u32()?;
+    decoder.skip()?;
+    decoder.array()?;
+
+    Ok(RedeemerBudget {
+        tag: redeemer_tag(tag),
+        index,
+        memory: decoder.u64()?,
+        steps: decoder.u64()?,
+    })
+}
+
+fn encode_cost_models(pparams: &PParams) -> Vec<u8> {
+    let mut languages: Vec<_> = pparams.cost_models.iter().collect();
+    languages.sort_by_key(|(language, _)| **language);
+
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.map(languages.len() as u64).unwrap();
+    for (language, costs) in languages {
+        encoder.u8(*language).unwrap();
+        encoder.array(costs.len() as u64).unwrap();
+        for cost in costs {
+            encoder.i64(*cost).unwrap();
+        }
+    }
+
+    encoder.into_writer()
+}
+
+/// Pairs of input and output CBOR for every utxo the tx spends, references
+/// or puts up as collateral
+fn resolved_inputs(
+    utxos: &HashMap<TxID, Utxo>,
+    tx: &MultiEraTx,
+) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    tx.inputs()
+        .iter()
+        .chain(tx.reference_inputs().iter())
+        .chain(tx.collateral().iter())
+        .map(|input| {
+            let id = txid(input.hash(), input.index());
+            let utxo = utxos
+                .get(&id)
+                .with_context(|| format!("input {id} not in utxo set"))?;
+
+            Ok((
+                cbor::encode_input(input.hash().as_ref(), input.index()),
+                cbor::encode_output(utxo)?,
+            ))
+        })
+        .collect()
+}
+
+fn has_redeemers(cbor: &[u8]) -> anyhow::Result<bool> {
+    let parts = cbor::split_tx(cbor)?;
+    let witness_set = CborMap::decode(parts.witness_set)?;
+    Ok(witness_set.get(WITNESS_SET_REDEEMERS).is_some())
+}
+
+/// Runs every script of the tx and returns the budget each redeemer consumed
+pub fn evaluate(
+    cbor: &[u8],
+    utxos: &HashMap<TxID, Utxo>,
+    pparams: &PParams,
+    slot_config: &SlotConfig,
+) -> Result<Vec<RedeemerBudget>, EvalError> {
+    let tx = MultiEraTx::decode(cbor)
+        .context("decoding tx")
+        .map_err(EvalError::Setup)?;
+
+    if !has_redeemers(cbor).map_err(EvalError::Setup)? {
+        return Ok(vec![]);
+    }
+
+    let resolved = resolved_inputs(utxos, &tx).map_err(EvalError::Setup)?;
+    let cost_models = encode_cost_models(pparams);
+
+    let redeemers = uplc::tx::eval_phase_two_raw(
+        cbor,
+        &resolved,
+        Some(&cost_models),
+        // the evaluator takes the budget as (cpu, mem)
+        (MAX_TX_EX_UNITS.steps, MAX_TX_EX_UNITS.memory),
+        (
+            slot_config.zero_time,
+            slot_config.zero_slot,
+            slot_config.slot_length,
+        ),
+        false,
+        |_| (),
+    )
+    .map_err(|error| EvalError::Script(error.to_string()))?;
+
+    redeemers
+        .iter()
+        .map(|(raw, _)| decode_budget(raw))
+        .collect::<anyhow::Result<_>>()
+        .map_err(EvalError::Setup)
+}
+
+/// Rewrites the ex units of the redeemers, in either the legacy array or the
+/// conway map format, copying the redeemer data as is
+fn patch_redeemers(
+    raw: &[u8],
+    budgets: &HashMap<(u8, u32), (u64, u64)>,
+) -> anyhow::Result<Vec<u8>> {
+    let lookup = |tag: u8, index: u32| {
+        budgets
+            .get(&(tag, index))
+            .copied()
+            .with_context(|| format!("no budget for {} redeemer {index}", redeemer_tag(tag)))
+    };
+
+    let mut decoder = Decoder::new(raw);
+    let mut encoder = Encoder::new(Vec::new());
+
+    match decoder.datatype()? {
+        Type::Map | Type::MapIndef => {
+            let len = decoder.map()?;
+            let mut entries = Vec::new();
+            cbor::for_each_item(&mut decoder, len, |decoder| {
+                decoder.array()?;
+                let tag = decoder.u8()?;
+                let index = decoder.u32()?;
+
+                let mut value = Encoder::new(Vec::new());
+                decoder.array()?;
+                value.array(2)?;
+                cbor::copy_raw(decoder, &mut value)?;
+                decoder.skip()?;
+                let (memory, steps) = lookup(tag, index)?;
+                value.array(2)?.u64(memory)?.u64(steps)?;
+
+                entries.push((tag, index, value.into_writer()));
+                Ok(())
+            })?;
+
+            encoder.map(entries.len() as u64)?;
+            for (tag, index, value) in entries {
+                encoder.array(2)?.u8(tag)?.u32(index)?;
+                encoder.writer_mut().extend_from_slice(&value);
+            }
+        }
+        _ => {
+            let len = decoder.array()?;
+            let mut items = Vec::new();
+            cbor::for_each_item(&mut decoder, len, |decoder| {
+                let mut item = Encoder::new(Vec::new());
+                decoder.array()?;
+                let tag = decoder.u8()?;
+                let index = decoder.u32()?;
+                item.array(4)?.u8(tag)?.u32(index)?;
+                cbor::copy_raw(decoder, &mut item)?;
+                decoder.skip()?;
+                let (memory, steps) = lookup(tag, index)?;
+                item.array(2)?.u64(memory)?.u64(steps)?;
+
+                items.push(item.into_writer());
+                Ok(())
+            })?;
+
+            encoder.array(items.len() as u64)?;
+            for item in items {
+                encoder.writer_mut().extend_from_slice(&item);
+            }
+        }
+    }
+
+    Ok(encoder.into_writer())
+}
+
+/// Plutus languages whose cost model goes into the script data hash: those
+/// of witness scripts and of reference scripts on spent or referenced inputs
+fn used_languages(
+    witness_set: &CborMap,
+    utxos: &HashMap<TxID, Utxo>,
+    tx: &MultiEraTx,
+) -> BTreeSet<u8> {
+    let mut languages = BTreeSet::new();
+
+    for (key, language) in [(3, 0), (6, 1), (7, 2)] {
+        if witness_set.get(key).is_some() {
+            languages.insert(language);
+        }
+    }
+
+    let referenced = tx.inputs().into_iter().chain(tx.reference_inputs());
+    for input in referenced {
+        let script = utxos
+            .get(&txid(input.hash(), input.index()))
+            .and_then(|utxo| utxo.reference_script.as_ref());
+
+        match script.map(|script| script.r#type.as_str()) {
+            Some("PlutusScriptV1") => languages.insert(0),
+            Some("PlutusScriptV2") => languages.insert(1),
+            Some("PlutusScriptV3") => languages.insert(2),
+            _ => false,
+        };
+    }
+
+    languages
+}
+
+/// Ledger `language_views` encoding, including the PlutusV1 quirks of a
+/// bytes-wrapped key and an indefinite cost list
+fn encode_language_views(languages: &BTreeSet<u8>, pparams: &PParams) -> anyhow::Result<Vec<u8>> {
+    let mut views = Vec::new();
+
+    for language in languages {
+        let costs = pparams
+            .cost_models
+            .get(language)
+            .with_context(|| format!("missing cost model for plutus language {language}"))?;
+
+        let mut key = Encoder::new(Vec::new());
+        let mut value = Encoder::new(Vec::new());
+
+        if *language == 0 {
+            key.bytes(&[0])?;
+
+            let mut list = Encoder::new(Vec::new());
+            list.begin_array()?;
+            for cost in costs {
+                list.i64(*cost)?;
+            }
+            list.end()?;
+            value.bytes(&list.into_writer())?;
+        } else {
+            key.u8(*language)?;
+            value.array(costs.len() as u64)?;
+            for cost in costs {
+                value.i64(*cost)?;
+            }
+        }
+
+        views.push((key.into_writer(), value.into_writer()));
+    }
+
+    // canonical key order, shorter encodings first
+    views.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.map(views.len() as u64)?;
+    for (key, value) in views {
+        encoder.writer_mut().extend_from_slice(&key);
+        encoder.writer_mut().extend_from_slice(&value);
+    }
+
+    Ok(encoder.into_writer())
+}
+
+/// Sets the evaluated ex units on the redeemers of the tx and recomputes the
+/// script data hash of the body to match
+pub fn apply_budgets(
+    cbor: &[u8],
+    budgets: &[RedeemerBudget],
+    utxos: &HashMap<TxID, Utxo>,
+    pparams: &PParams,
+) -> anyhow::Result<Vec<u8>> {
+    let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+    let parts = cbor::split_tx(cbor)?;
+
+    let mut witness_set = CborMap::decode(parts.witness_set)?;
+    let Some(redeemers) = witness_set.get(WITNESS_SET_REDEEMERS) else {
+        return Ok(cbor.to_vec());
+    };
+
+    let by_pointer = budgets
+        .iter()
+        .map(|budget| {
+            let tag = redeemer_tag_id(budget.tag).context("unknown redeemer tag")?;
+            Ok(((tag, budget.index), (budget.memory, budget.steps)))
+        })
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+    let redeemers = patch_redeemers(redeemers, &by_pointer)?;
+
+    let mut preimage = redeemers.clone();
+    if let Some(datums) = witness_set.get(WITNESS_SET_DATUMS) {
+        preimage.extend_from_slice(datums);
+    }
+    let languages = used_languages(&witness_set, utxos, &tx);
+    preimage.extend(encode_language_views(&languages, pparams)?);
+
+    let script_data_hash = Hasher::<256>::hash(&preimage);
+
+    let mut body = CborMap::decode(parts.body)?;
+    ensure!(
+        body.get(BODY_SCRIPT_DATA_HASH).is_some(),
+        "tx has redeemers but no script data hash"
+    );
+    body.insert(
+        BODY_SCRIPT_DATA_HASH,
+        cbor::bytes(script_data_hash.as_ref()),
+    );
+    witness_set.insert(WITNESS_SET_REDEEMERS, redeemers);
+
+    let body = body.encode();
+    let witness_set = witness_set.encode();
+
+    Ok(cbor::join_tx(&TxParts {
+        body: &body,
+        witness_set: &witness_set,
+        is_valid: parts.is_valid,
+        auxiliary_data: parts.auxiliary_data,
+    }))
+}
+
+/// Evaluation passes before giving up on the fee and the ex units settling
+const FEE_ROUNDS: usize = 4;
+
+/// Evaluates the scripts of the tx, writes the resulting ex units back into
+/// it and raises its fee to pay for them, see [`fee::cover_fee`] for the
+/// `payer`. Scripts see the fee and the change, so a raised fee is evaluated
+/// again. Txs without redeemers are returned untouched.
+pub fn evaluate_and_apply(
+    cbor: &[u8],
+    utxos: &HashMap<TxID, Utxo>,
+    pparams: &PParams,
+    prices: &ExUnitPrices,
+    slot_config: &SlotConfig,
+    payer: Option<&str>,
+) -> Result<Vec<u8>, EvalError> {
+    let mut cbor = cbor.to_vec();
+
+    for _ in 0..FEE_ROUNDS {
+        let budgets = evaluate(&cbor, utxos, pparams, slot_config)?;
+
+        if budgets.is_empty() {
+            return Ok(cbor);
+        }
+
+        let applied = apply_budgets(&cbor, &budgets, utxos, pparams).map_err(EvalError::Setup)?;
+        let covered =
+            fee::cover_fee(&applied, utxos, pparams, prices, payer).map_err(EvalError::Setup)?;
+
+        if covered == applied {
+            return Ok(applied);
+        }
+
+        cbor = covered;
+    }
+
+    Err(EvalError::Setup(anyhow!("fee and ex units didn't settle")))
+}
+
+#[cfg(test)]
+mod tests {
+    use tx3_cardano::pallas::ledger::addresses::{
+        Address, Network, ShelleyAddress, ShelleyDelegationPart, ShelleyPaymentPart,
+    };
+
+    use super::*;
+    use crate::ledger::testing;
+
+    /// `(lam _ (lam _ (lam _ (con unit ()))))` as a flat program wrapped in
+    /// cbor bytes, the way plutus scripts are hashed and witnessed
+    const ALWAYS_SUCCEEDS: [u8; 7] = [0x46, 0x01, 0x00, 0x00, 0x22, 0x24, 0x99];
+
+    /// `Constr 0 []`, used as datum and redeemer
+    const CONSTR_0: [u8; 3] = [0xd8, 0x79, 0x80];
+
+    fn script_address() -> String {
+        let mut hasher = Hasher::<224>::new();
+        hasher.input(&[2]);
+        hasher.input(&ALWAYS_SUCCEEDS);
+
+        let address = ShelleyAddress::new(
+            Network::Testnet,
+            ShelleyPaymentPart::Script(hasher.finalize()),
+            ShelleyDelegationPart::Null,
+        );
+
+        Address::Shelley(address).to_bech32().unwrap()
+    }
+
+    /// Tx spending a utxo locked by the always succeeding PlutusV2 script to
+    /// [`testing::USER`], with a zero budget on its redeemer
+    fn spending_tx(fee: u64) -> anyhow::Result<(Vec<u8>, HashMap<TxID, Utxo>)> {
+        let input_hash = [1; 32];
+
+        let mut locked = testing::lovelace_utxo(&script_address(), 10_000_000);
+        locked.inline_datum_raw = Some(hex::encode(CONSTR_0));
+        let utxos = HashMap::from([(txid(hex::encode(input_hash), 0), locked)]);
+
+        let input = cbor::encode_input(&input_hash, 0);
+        let output = cbor::encode_output(&testing::lovelace_utxo(testing::USER, 10_000_000 - fee))?;
+
+        let mut body = Encoder::new(Vec::new());
+        body.map(4)?;
+        body.u8(0)?.array(1)?;
+        body.writer_mut().extend_from_slice(&input);
+        body.u8(1)?.array(1)?;
+        body.writer_mut().extend_from_slice(&output);
+        body.u8(2)?.u64(fee)?;
+        body.u8(11)?.bytes(&[0; 32])?;
+
+        let mut witness_set = Encoder::new(Vec::new());
+        witness_set.map(2)?;
+        witness_set.u8(5)?.array(1)?.array(4)?.u8(0)?.u32(0)?;
+        witness_set.writer_mut().extend_from_slice(&CONSTR_0);
+        witness_set.array(2)?.u64(0)?.u64(0)?;
+        witness_set.u8(6)?.array(1)?.bytes(&ALWAYS_SUCCEEDS)?;
+
+        let tx = cbor::join_tx(&TxParts {
+            body: &body.into_writer(),
+            witness_set: &witness_set.into_writer(),
+            is_valid: &[0xf5],
+            auxiliary_data: &[0xf6],
+        });
+
+        Ok((tx, utxos))
+    }
+
+    #[test]
+    fn evaluates_the_budget_of_a_script() -> anyhow::Result<()> {
+        let (tx, utxos) = spending_tx(1_000_000)?;
+
+        let budgets = evaluate(&tx, &utxos, &testing::pparams(), &SlotConfig::PREPROD)?;
+
+        // startup plus 3 applications, 3 lambdas and 4 constants at the
+        // mainnet machine costs
+        assert_eq!(budgets.len(), 1);
+        assert_eq!(budgets[0].tag, "spend");
+        assert_eq!(budgets[0].index, 0);
+        assert_eq!(budgets[0].memory, 1_100);
+        assert_eq!(budgets[0].steps, 160_100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn skips_txs_without_redeemers() -> anyhow::Result<()> {
+        let (tx, utxos) = spending_tx(1_000_000)?;
+        let parts = cbor::split_tx(&tx)?;
+        let plain = cbor::join_tx(&TxParts {
+            witness_set: &[0xa0],
+            ..parts
+        });
+
+        let budgets = evaluate(&plain, &utxos, &testing::pparams(), &SlotConfig::PREPROD)?;
+        assert!(budgets.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn applies_the_evaluated_budgets() -> anyhow::Result<()> {
+        let (tx, utxos) = spending_tx(1_000_000)?;
+        let pparams = testing::pparams();
+
+        let applied = evaluate_and_apply(
+            &tx,
+            &utxos,
+            &pparams,
+            &testing::prices(),
+            &SlotConfig::PREPROD,
+            None,
+        )?;
+        let decoded = MultiEraTx::decode(&applied)?;
+
+        let units: Vec<_> = decoded
+            .redeemers()
+            .iter()
+            .map(|redeemer| (redeemer.ex_units().mem, redeemer.ex_units().steps))
+            .collect();
+        assert_eq!(units, vec![(1_100, 160_100)]);
+
+        // the fee was already enough
+        assert_eq!(decoded.fee(), Some(1_000_000));
+        assert_ne!(cbor::split_tx(&applied)?.body, cbor::split_tx(&tx)?.body);
+
+        Ok(())
+    }
+
+    #[test]
+    fn raises_the_fee_for_the_evaluated_budgets() -> anyhow::Result<()> {
+        let (tx, utxos) = spending_tx(0)?;
+        let pparams = testing::pparams();
+        let prices = testing::prices();
+
+        let applied = evaluate_and_apply(
+            &tx,
+            &utxos,
+            &pparams,
+            &prices,
+            &SlotConfig::PREPROD,
+            Some(testing::USER),
+        )?;
+        let decoded = MultiEraTx::decode(&applied)?;
+
+        let fee = decoded.fee().unwrap_or_default();
+        assert_eq!(
+            fee,
+            fee::required_fee(&decoded, applied.len() as u64, &utxos, &pparams, &prices)
+        );
+        assert!(fee >= fee::script_fee(&decoded, &prices) + pparams.min_fee_constant);
+        assert_eq!(decoded.outputs()[0].value().coin(), 10_000_000 - fee);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fails_without_a_change_output_for_the_fee() -> anyhow::Result<()> {
+        let (tx, utxos) = spending_tx(0)?;
+
+        // the only input is the script's, so no output is change by default
+        let result = evaluate_and_apply(
+            &tx,
+            &utxos,
+            &testing::pparams(),
+            &testing::prices(),
+            &SlotConfig::PREPROD,
+            None,
+        );
+        assert!(matches!(result, Err(EvalError::Setup(_))));
+
+        Ok(())
+    }
+}
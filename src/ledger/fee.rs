@@ -0,0 +1,258 @@
+//! Fees of txs edited after the compiler priced them. The fee covers the
+//! size of the signed tx and the ex units of its redeemers, and is raised
+//! out of a change output when it falls short.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, bail, ensure};
+use tx3_cardano::{PParams, pallas::ledger::traverse::MultiEraTx};
+
+use super::{
+    cbor::{self, CborMap, TxParts},
+    min_fee, min_utxo_lovelace, payment_key_hash, txid,
+};
+use crate::hydra::model::{ExUnitPrices, TxID, Utxo};
+
+const BODY_OUTPUTS: u64 = 1;
+const BODY_FEE: u64 = 2;
+
+/// Serialized size of a `[vkey, signature]` witness
+pub const VKEY_WITNESS_SIZE: u64 = 101;
+
+/// Fee passes before giving up on the size converging
+const FEE_ROUNDS: usize = 4;
+
+/// Lovelace the ex units of the redeemers cost at the head prices, rounded
+/// up like the ledger does
+pub fn script_fee(tx: &MultiEraTx, prices: &ExUnitPrices) -> u64 {
+    let (memory, steps) = tx
+        .redeemers()
+        .iter()
+        .map(|redeemer| redeemer.ex_units())
+        .fold((0u128, 0u128), |(memory, steps), units| {
+            (memory + units.mem as u128, steps + units.steps as u128)
+        });
+
+    let memory_price = prices.price_memory;
+    let steps_price = prices.price_steps;
+
+    let numerator = memory * memory_price.numerator as u128 * steps_price.denominator as u128
+        + steps * steps_price.numerator as u128 * memory_price.denominator as u128;
+
+    numerator.div_ceil(memory_price.denominator as u128 * steps_price.denominator as u128) as u64
+}
+
+/// Distinct keys that have to sign the tx, the owners of its key-hash
+/// inputs and its required signers
+pub fn vkey_signers(tx: &MultiEraTx, utxos: &HashMap<TxID, Utxo>) -> u64 {
+    let mut signers: Vec<_> = tx
+        .inputs()
+        .iter()
+        .filter_map(|input| utxos.get(&txid(input.hash(), input.index())))
+        .filter_map(payment_key_hash)
+        .collect();
+    signers.extend(
+        tx.required_signers()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .copied(),
+    );
+    signers.sort();
+    signers.dedup();
+
+    signers.len() as u64
+}
+
+/// Fee of the tx once signed, for a serialized size without the signatures
+pub fn required_fee(
+    tx: &MultiEraTx,
+    size: u64,
+    utxos: &HashMap<TxID, Utxo>,
+    pparams: &PParams,
+    prices: &ExUnitPrices,
+) -> u64 {
+    let witnesses_size = vkey_signers(tx, utxos) * VKEY_WITNESS_SIZE;
+    min_fee(size + witnesses_size, pparams) + script_fee(tx, prices)
+}
+
+/// Raises the fee of the tx to [`required_fee`], taking the difference from
+/// its change output: the largest output paying back to `payer`, or to the
+/// key address of one of its inputs if unset. Txs that pay enough already
+/// are returned untouched.
+pub fn cover_fee(
+    cbor: &[u8],
+    utxos: &HashMap<TxID, Utxo>,
+    pparams: &PParams,
+    prices: &ExUnitPrices,
+    payer: Option<&str>,
+) -> anyhow::Result<Vec<u8>> {
+    let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+    let fee = tx.fee().unwrap_or_default();
+
+    let mut required = required_fee(&tx, cbor.len() as u64, utxos, pparams, prices);
+    if required <= fee {
+        return Ok(cbor.to_vec());
+    }
+
+    let payers: HashSet<String> = match payer {
+        Some(payer) => HashSet::from([payer.to_string()]),
+        None => tx
+            .inputs()
+            .iter()
+            .filter_map(|input| utxos.get(&txid(input.hash(), input.index())))
+            .filter(|utxo| payment_key_hash(utxo).is_some())
+            .map(|utxo| utxo.address.clone())
+            .collect(),
+    };
+
+    let (change, coin) = tx
+        .outputs()
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| {
+            output
+                .address()
+                .is_ok_and(|address| payers.contains(&address.to_string()))
+        })
+        .map(|(index, output)| (index, output.value().coin()))
+        .max_by_key(|(_, coin)| *coin)
+        .context("tx has no change output to raise its fee from")?;
+
+    let parts = cbor::split_tx(cbor)?;
+    let mut body = CborMap::decode(parts.body)?;
+    let (mut outputs, tagged) =
+        cbor::array_items(body.get(BODY_OUTPUTS).context("tx without outputs")?)?;
+    let original = outputs[change].clone();
+
+    for _ in 0..FEE_ROUNDS {
+        let change_coin = coin
+            .checked_sub(required - fee)
+            .with_context(|| format!("change output can't cover a fee of {required}"))?;
+
+        outputs[change] = cbor::set_output_coin(&original, change_coin)?;
+        ensure!(
+            change_coin >= min_utxo_lovelace(outputs[change].len() as u64, pparams),
+            "change output would be below the min utxo with a fee of {required}"
+        );
+
+        body.insert(BODY_OUTPUTS, cbor::encode_array(&outputs, tagged));
+        body.insert(BODY_FEE, cbor::uint(required));
+
+        let body_bytes = body.encode();
+        let patched = cbor::join_tx(&TxParts {
+            body: &body_bytes,
+            witness_set: parts.witness_set,
+            is_valid: parts.is_valid,
+            auxiliary_data: parts.auxiliary_data,
+        });
+
+        let again = required_fee(&tx, patched.len() as u64, utxos, pparams, prices);
+        if again <= required {
+            return Ok(patched);
+        }
+
+        required = again;
+    }
+
+    bail!("fee didn't converge")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{payment::build_payment, testing};
+
+    /// Unsigned payment of 5 ada from the user to the vendor, built without fees
+    fn unpriced_payment() -> anyhow::Result<(Vec<u8>, HashMap<TxID, Utxo>)> {
+        let utxos = HashMap::from([(
+            txid("00".repeat(32), 0),
+            testing::lovelace_utxo(testing::USER, 100_000_000),
+        )]);
+
+        let free = PParams {
+            min_fee_coefficient: 0,
+            min_fee_constant: 0,
+            ..testing::pparams()
+        };
+        let payment = build_payment(&utxos, testing::USER, testing::VENDOR, 5_000_000, &free)?;
+
+        Ok((payment.cbor, utxos))
+    }
+
+    #[test]
+    fn counts_the_signers_of_key_inputs() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let tx = MultiEraTx::decode(&cbor)?;
+
+        assert_eq!(vkey_signers(&tx, &utxos), 1);
+        assert_eq!(script_fee(&tx, &testing::prices()), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn raises_the_fee_out_of_the_change() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let pparams = testing::pparams();
+        let prices = testing::prices();
+
+        let covered = cover_fee(&cbor, &utxos, &pparams, &prices, None)?;
+        let tx = MultiEraTx::decode(&covered)?;
+
+        let fee = tx.fee().unwrap_or_default();
+        assert_eq!(
+            fee,
+            required_fee(&tx, covered.len() as u64, &utxos, &pparams, &prices)
+        );
+
+        let outputs = tx.outputs();
+        assert_eq!(outputs[0].value().coin(), 5_000_000);
+        assert_eq!(outputs[1].value().coin(), 95_000_000 - fee);
+
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_priced_txs_untouched() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let pparams = testing::pparams();
+        let prices = testing::prices();
+
+        let covered = cover_fee(&cbor, &utxos, &pparams, &prices, None)?;
+        assert_eq!(
+            cover_fee(&covered, &utxos, &pparams, &prices, None)?,
+            covered
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn takes_the_fee_from_the_payer() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let pparams = testing::pparams();
+        let prices = testing::prices();
+
+        let covered = cover_fee(&cbor, &utxos, &pparams, &prices, Some(testing::VENDOR))?;
+        let tx = MultiEraTx::decode(&covered)?;
+
+        let fee = tx.fee().unwrap_or_default();
+        assert_eq!(tx.outputs()[0].value().coin(), 5_000_000 - fee);
+        assert_eq!(tx.outputs()[1].value().coin(), 95_000_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fails_when_the_change_is_too_small() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+
+        let pricey = PParams {
+            min_fee_constant: 100_000_000,
+            ..testing::pparams()
+        };
+        assert!(cover_fee(&cbor, &utxos, &pricey, &testing::prices(), None).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Bookkeeping of Hydra-format utxo sets outside of a hydra node, shared by
+//! the offline backends.
+
+use std::{collections::HashMap, fmt::Display};
+
+use anyhow::{Context, bail};
+use tx3_cardano::pallas::{
+    codec::minicbor,
+    crypto::hash::{Hash, Hasher},
+    ledger::{
+        primitives::conway::{DatumOption, ScriptRef},
+        traverse::{MultiEraOutput, MultiEraTx},
+    },
+};
+
+use crate::hydra::model::{AssetValue, ReferenceScript, TxID, Utxo, Value};
+
+pub mod cbor;
+pub mod eval;
+pub mod fee;
+pub mod payment;
+pub mod sponsor;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod time;
+mod validate;
+pub mod witness;
+
+pub use validate::{Balance, Violation, min_fee, min_utxo_lovelace, payment_key_hash, validate};
+
+pub fn txid(hash: impl Display, index: u64) -> TxID {
+    format!("{hash}#{index}")
+}
+
+fn value_from_output(output: &MultiEraOutput) -> Value {
+    let value = output.value();
+
+    let mut assets = HashMap::new();
+    assets.insert("lovelace".to_string(), AssetValue::Lovelace(value.coin()));
+
+    for policy_assets in value.assets() {
+        let by_name = policy_assets
+            .assets()
+            .iter()
+            .map(|asset| (hex::encode(asset.name()), asset.any_coin() as u64))
+            .collect();
+
+        assets.insert(
+            policy_assets.policy().to_string(),
+            AssetValue::Multi(by_name),
+        );
+    }
+
+    Value { assets }
+}
+
+fn reference_script_from_output(
+    output: &MultiEraOutput,
+) -> anyhow::Result<Option<ReferenceScript>> {
+    let Some(script) = output.script_ref() else {
+        return Ok(None);
+    };
+
+    let (r#type, cbor) = match script {
+        ScriptRef::NativeScript(x) => ("SimpleScript", x.raw_cbor().to_vec()),
+        ScriptRef::PlutusV1Script(x) => ("PlutusScriptV1", minicbor::to_vec(&x.0)?),
+        ScriptRef::PlutusV2Script(x) => ("PlutusScriptV2", minicbor::to_vec(&x.0)?),
+        ScriptRef::PlutusV3Script(x) => ("PlutusScriptV3", minicbor::to_vec(&x.0)?),
+    };
+
+    Ok(Some(ReferenceScript {
+        cbor_hex: hex::encode(cbor),
+        description: String::new(),
+        r#type: r#type.to_string(),
+    }))
+}
+
+/// Ledger hash of a reference script, over its language tag and script bytes
+pub fn script_hash(script: &ReferenceScript) -> anyhow::Result<Hash<28>> {
+    let cbor = hex::decode(&script.cbor_hex).context("decoding script")?;
+
+    let (tag, bytes) = match script.r#type.as_str() {
+        "SimpleScript" => (0, cbor),
+        // plutus envelopes hold the cbor of the script bytes
+        other => {
+            let tag = match other {
+                "PlutusScriptV1" => 1,
+                "PlutusScriptV2" => 2,
+                "PlutusScriptV3" => 3,
+                other => bail!("unknown reference script type {other}"),
+            };
+            let bytes = minicbor::Decoder::new(&cbor)
+                .bytes()
+                .context("decoding plutus script bytes")?;
+            (tag, bytes.to_vec())
+        }
+    };
+
+    let mut hasher = Hasher::<224>::new();
+    hasher.input(&[tag]);
+    hasher.input(&bytes);
+    Ok(hasher.finalize())
+}
+
+/// Maps a ledger output into the utxo shape served by hydra nodes
+pub fn utxo_from_output(output: &MultiEraOutput) -> anyhow::Result<Utxo> {
+    let address = output
+        .address()
+        .context("decoding output address")?
+        .to_string();
+
+    let (datumhash, inline_datum_raw) = match output.datum() {
+        Some(DatumOption::Hash(hash)) => (Some(hash.to_string()), None),
+        Some(DatumOption::Data(data)) => (None, Some(hex::encode(data.0.raw_cbor()))),
+        None => (None, None),
+    };
+
+    Ok(Utxo {
+        address,
+        datum: None,
+        datumhash,
+        inline_datum: None,
+        inline_datum_hash: None,
+        inline_datum_raw,
+        reference_script: reference_script_from_output(output)?,
+        value: value_from_output(output),
+    })
+}
+
+/// Consumes the inputs of a tx and adds its outputs to the utxo set. Phase-2
+/// failed txs consume their collateral instead.
+pub fn apply_tx(utxos: &mut HashMap<TxID, Utxo>, tx: &MultiEraTx) -> anyhow::Result<()> {
+    let produced = tx
+        .produces()
+        .iter()
+        .map(|(index, output)| {
+            let utxo = utxo_from_output(output)?;
+            Ok((txid(tx.hash(), *index as u64), utxo))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for input in tx.consumes() {
+        utxos.remove(&txid(input.hash(), input.index()));
+    }
+
+    utxos.extend(produced);
+
+    Ok(())
+}
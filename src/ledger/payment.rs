@@ -0,0 +1,243 @@
+//! Plain lovelace payments built from scratch, for server owned funds.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, bail};
+use tx3_cardano::{PParams, pallas::codec::minicbor::Encoder};
+
+use super::{
+    cbor::{self, TxParts},
+    min_fee, min_utxo_lovelace,
+};
+use crate::hydra::model::{AssetValue, TxID, Utxo, Value};
+
+/// Serialized size of the single `[vkey, signature]` witness, with its set header
+const WITNESS_SET_SIZE: u64 = 105;
+
+/// Fee passes before giving up on the size converging
+const FEE_ROUNDS: usize = 4;
+
+pub struct Payment {
+    pub cbor: Vec<u8>,
+}
+
+fn lovelace_output(address: &str, lovelace: u64) -> anyhow::Result<Vec<u8>> {
+    let mut assets = HashMap::new();
+    assets.insert("lovelace".to_string(), AssetValue::Lovelace(lovelace));
+
+    cbor::encode_output(&Utxo {
+        address: address.to_string(),
+        datum: None,
+        datumhash: None,
+        inline_datum: None,
+        inline_datum_hash: None,
+        inline_datum_raw: None,
+        reference_script: None,
+        value: Value { assets },
+    })
+}
+
+fn lovelace(utxo: &Utxo) -> u64 {
+    match utxo.value.assets.get("lovelace") {
+        Some(AssetValue::Lovelace(amount)) => *amount,
+        _ => 0,
+    }
+}
+
+fn encode_body(inputs: &[(Vec<u8>, u64)], outputs: &[Vec<u8>], fee: u64) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.map(3).unwrap();
+
+    encoder.u8(0).unwrap().array(inputs.len() as u64).unwrap();
+    for (hash, index) in inputs {
+        encoder
+            .writer_mut()
+            .extend_from_slice(&cbor::encode_input(hash, *index));
+    }
+
+    encoder.u8(1).unwrap().array(outputs.len() as u64).unwrap();
+    for output in outputs {
+        encoder.writer_mut().extend_from_slice(output);
+    }
+
+    encoder.u8(2).unwrap().u64(fee).unwrap();
+    encoder.into_writer()
+}
+
+/// Unsigned tx paying `amount` to `to` from lovelace-only utxos of `from`,
+/// largest first, with the change going back to `from`
+pub fn build_payment<'a>(
+    available: impl IntoIterator<Item = (&'a TxID, &'a Utxo)>,
+    from: &str,
+    to: &str,
+    amount: u64,
+    pparams: &PParams,
+) -> anyhow::Result<Payment> {
+    let mut candidates: Vec<_> = available
+        .into_iter()
+        .filter(|(_, utxo)| utxo.address == from)
+        .filter(|(_, utxo)| {
+            utxo.value
+                .assets
+                .values()
+                .all(|value| matches!(value, AssetValue::Lovelace(_)))
+        })
+        .collect();
+    candidates.sort_by_key(|(_, utxo)| std::cmp::Reverse(lovelace(utxo)));
+
+    let payment = lovelace_output(to, amount)?;
+    let min_amount = min_utxo_lovelace(payment.len() as u64, pparams);
+    if amount < min_amount {
+        bail!("amount is below the min utxo of {min_amount}");
+    }
+
+    let mut fee = 0;
+
+    for _ in 0..FEE_ROUNDS {
+        // room for a change output of at least the min utxo
+        let change_min = min_utxo_lovelace(lovelace_output(from, u64::MAX)?.len() as u64, pparams);
+        let target = amount + fee + change_min;
+
+        let mut selected = Vec::new();
+        let mut total = 0;
+        for (id, utxo) in &candidates {
+            if total >= target {
+                break;
+            }
+            selected.push(*id);
+            total += lovelace(utxo);
+        }
+
+        if total < target {
+            bail!("not enough funds at {from}, {total} available for {target}");
+        }
+
+        let mut inputs = selected
+            .iter()
+            .map(|id| {
+                let (hash, index) = id.split_once('#').context("malformed utxo id")?;
+                Ok((
+                    hex::decode(hash).context("decoding utxo hash")?,
+                    index.parse().context("decoding utxo index")?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        inputs.sort();
+
+        let outputs = [
+            payment.clone(),
+            lovelace_output(from, total - amount - fee)?,
+        ];
+        let body = encode_body(&inputs, &outputs, fee);
+
+        // empty witness set, valid, no auxiliary data
+        let cbor = cbor::join_tx(&TxParts {
+            body: &body,
+            witness_set: &[0xa0],
+            is_valid: &[0xf5],
+            auxiliary_data: &[0xf6],
+        });
+
+        let required = min_fee(cbor.len() as u64 + WITNESS_SET_SIZE, pparams);
+        if required <= fee {
+            return Ok(Payment { cbor });
+        }
+
+        fee = required;
+    }
+
+    bail!("payment fee didn't converge")
+}
+
+#[cfg(test)]
+mod tests {
+    use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
+
+    use super::*;
+    use crate::ledger::{testing, txid, witness};
+
+    fn utxos(lovelace: &[u64]) -> HashMap<TxID, Utxo> {
+        lovelace
+            .iter()
+            .enumerate()
+            .map(|(index, lovelace)| {
+                (
+                    txid("00".repeat(32), index as u64),
+                    testing::lovelace_utxo(&testing::key_address(), *lovelace),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pays_the_fee_of_the_signed_tx() -> anyhow::Result<()> {
+        let pparams = testing::pparams();
+        let payment = build_payment(
+            &utxos(&[100_000_000]),
+            &testing::key_address(),
+            testing::VENDOR,
+            5_000_000,
+            &pparams,
+        )?;
+
+        let tx = MultiEraTx::decode(&payment.cbor)?;
+        let fee = tx.fee().unwrap_or_default();
+        assert_eq!(
+            fee,
+            min_fee(payment.cbor.len() as u64 + WITNESS_SET_SIZE, &pparams)
+        );
+
+        let outputs = tx.outputs();
+        assert_eq!(outputs[0].value().coin(), 5_000_000);
+        assert_eq!(outputs[1].value().coin(), 95_000_000 - fee);
+
+        let signed =
+            witness::add_vkey_witnesses(&payment.cbor, &[testing::vkey_witness(&payment.cbor)])?;
+        assert!(min_fee(signed.len() as u64, &pparams) <= fee);
+
+        Ok(())
+    }
+
+    #[test]
+    fn spends_the_largest_utxos_first() -> anyhow::Result<()> {
+        let payment = build_payment(
+            &utxos(&[3_000_000, 20_000_000, 10_000_000]),
+            &testing::key_address(),
+            testing::VENDOR,
+            15_000_000,
+            &testing::pparams(),
+        )?;
+
+        let tx = MultiEraTx::decode(&payment.cbor)?;
+        let spent: Vec<_> = tx.inputs().iter().map(|input| input.index()).collect();
+        assert_eq!(spent, [1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fails_without_enough_funds() {
+        let payment = build_payment(
+            &utxos(&[3_000_000, 2_000_000]),
+            &testing::key_address(),
+            testing::VENDOR,
+            4_000_000,
+            &testing::pparams(),
+        );
+
+        assert!(payment.is_err());
+    }
+
+    #[test]
+    fn refuses_amounts_below_the_min_utxo() {
+        let payment = build_payment(
+            &utxos(&[100_000_000]),
+            &testing::key_address(),
+            testing::VENDOR,
+            1,
+            &testing::pparams(),
+        );
+
+        assert!(payment.is_err());
+    }
+}
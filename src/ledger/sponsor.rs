@@ -0,0 +1,332 @@
+//! Fee sponsorship. A resolved tx gets an extra input from the sponsor that
+//! pays its fee and tops up outputs below the min utxo, with the rest going
+//! back to the sponsor as change.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, bail, ensure};
+use tx3_cardano::{
+    PParams,
+    pallas::ledger::{
+        primitives::conway::RedeemerTag,
+        traverse::{Era, MultiEraOutput, MultiEraTx},
+    },
+};
+
+use super::{
+    cbor::{self, CborMap, TxParts},
+    fee, min_fee, min_utxo_lovelace,
+};
+use crate::hydra::model::{AssetValue, ExUnitPrices, TxID, Utxo};
+
+const BODY_INPUTS: u64 = 0;
+const BODY_OUTPUTS: u64 = 1;
+const BODY_FEE: u64 = 2;
+
+/// Fee passes before giving up on the size converging
+const FEE_ROUNDS: usize = 4;
+
+pub struct Sponsored {
+    pub cbor: Vec<u8>,
+    /// Lovelace the sponsor pays, fee and output top-ups
+    pub cost: u64,
+    /// Largest top-up of an output that doesn't pay back to the sponsor
+    pub largest_top_up: u64,
+}
+
+/// Output top-up so that it holds at least the min utxo, with the new raw output
+fn top_up(raw: &[u8], pparams: &PParams) -> anyhow::Result<Option<(u64, Vec<u8>)>> {
+    let output = MultiEraOutput::decode(Era::Conway, raw).context("decoding output")?;
+    let coin = output.value().coin();
+
+    let mut required = min_utxo_lovelace(raw.len() as u64, pparams);
+    if coin >= required {
+        return Ok(None);
+    }
+
+    // a bigger coin can take more bytes, which raises the min again
+    loop {
+        let patched = cbor::set_output_coin(raw, required)?;
+        let again = min_utxo_lovelace(patched.len() as u64, pparams);
+        if again <= required {
+            return Ok(Some((required - coin, patched)));
+        }
+        required = again;
+    }
+}
+
+fn pays_to(raw: &[u8], address: &str) -> bool {
+    MultiEraOutput::decode(Era::Conway, raw)
+        .ok()
+        .and_then(|output| output.address().ok())
+        .is_some_and(|output_address| output_address.to_string() == address)
+}
+
+fn change_output(sponsor: &Utxo, lovelace: u64) -> anyhow::Result<Vec<u8>> {
+    let mut change = sponsor.clone();
+    change.datum = None;
+    change.datumhash = None;
+    change.inline_datum = None;
+    change.inline_datum_hash = None;
+    change.inline_datum_raw = None;
+    change.reference_script = None;
+    change
+        .value
+        .assets
+        .insert("lovelace".to_string(), AssetValue::Lovelace(lovelace));
+
+    cbor::encode_output(&change)
+}
+
+fn sponsor_lovelace(sponsor: &Utxo) -> u64 {
+    match sponsor.value.assets.get("lovelace") {
+        Some(AssetValue::Lovelace(amount)) => *amount,
+        _ => 0,
+    }
+}
+
+/// Adds the sponsor utxo to the tx and rebalances it so that the sponsor
+/// pays the fee and any min utxo shortfall of the outputs
+pub fn sponsor_tx(
+    cbor: &[u8],
+    utxos: &HashMap<TxID, Utxo>,
+    sponsor_hash: &[u8],
+    sponsor_index: u64,
+    sponsor: &Utxo,
+    pparams: &PParams,
+    prices: &ExUnitPrices,
+) -> anyhow::Result<Sponsored> {
+    let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+
+    // a new input shifts the index of the inputs after it
+    ensure!(
+        !tx.redeemers()
+            .iter()
+            .any(|redeemer| redeemer.tag() == RedeemerTag::Spend),
+        "txs spending script inputs can't be sponsored"
+    );
+
+    let parts = cbor::split_tx(cbor)?;
+    let mut body = CborMap::decode(parts.body)?;
+
+    let previous_fee = tx.fee().unwrap_or_default();
+
+    let (mut inputs, tagged) =
+        cbor::array_items(body.get(BODY_INPUTS).context("tx without inputs")?)?;
+    inputs.push(cbor::encode_input(sponsor_hash, sponsor_index));
+    inputs.sort();
+    body.insert(BODY_INPUTS, cbor::encode_array(&inputs, tagged));
+
+    let (outputs, outputs_tagged) =
+        cbor::array_items(body.get(BODY_OUTPUTS).context("tx without outputs")?)?;
+
+    let mut top_ups = 0;
+    let mut largest_top_up = 0;
+    let mut outputs = outputs
+        .into_iter()
+        .map(|raw| match top_up(&raw, pparams)? {
+            Some((amount, patched)) => {
+                top_ups += amount;
+                if !pays_to(&raw, &sponsor.address) {
+                    largest_top_up = largest_top_up.max(amount);
+                }
+                Ok(patched)
+            }
+            None => Ok(raw),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let available = sponsor_lovelace(sponsor) + previous_fee;
+    // plus the sponsor
+    let witnesses_size = (fee::vkey_signers(&tx, utxos) + 1) * fee::VKEY_WITNESS_SIZE;
+    let script_fee = fee::script_fee(&tx, prices);
+    outputs.push(Vec::new());
+    let change_index = outputs.len() - 1;
+
+    let mut fee = 0;
+    for _ in 0..FEE_ROUNDS {
+        let change = available
+            .checked_sub(fee + top_ups)
+            .context("sponsor utxo can't cover the tx")?;
+
+        outputs[change_index] = change_output(sponsor, change)?;
+        body.insert(BODY_OUTPUTS, cbor::encode_array(&outputs, outputs_tagged));
+        body.insert(BODY_FEE, cbor::uint(fee));
+
+        let body_bytes = body.encode();
+        let tx_bytes = cbor::join_tx(&TxParts {
+            body: &body_bytes,
+            witness_set: parts.witness_set,
+            is_valid: parts.is_valid,
+            auxiliary_data: parts.auxiliary_data,
+        });
+
+        let required = min_fee(tx_bytes.len() as u64 + witnesses_size, pparams) + script_fee;
+        if required <= fee {
+            let change_size = outputs[change_index].len() as u64;
+            ensure!(
+                change >= min_utxo_lovelace(change_size, pparams),
+                "sponsor change would be below the min utxo"
+            );
+
+            return Ok(Sponsored {
+                cbor: tx_bytes,
+                cost: (fee + top_ups).saturating_sub(previous_fee),
+                largest_top_up,
+            });
+        }
+
+        fee = required;
+    }
+
+    bail!("sponsored fee didn't converge")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{payment::build_payment, testing, txid};
+
+    const SPONSOR_HASH: [u8; 32] = [0x11; 32];
+    const SPONSOR_LOVELACE: u64 = 50_000_000;
+
+    /// Payment of 5 ada from the user, built without fees or min utxo as for sponsored
+    /// resolves, next to a sponsor utxo at the vendor address
+    fn unpriced_payment() -> anyhow::Result<(Vec<u8>, HashMap<TxID, Utxo>)> {
+        unpriced_payment_to(testing::USER, 5_000_000)
+    }
+
+    fn unpriced_payment_to(
+        to: &str,
+        amount: u64,
+    ) -> anyhow::Result<(Vec<u8>, HashMap<TxID, Utxo>)> {
+        let mut utxos = HashMap::from([(
+            txid("00".repeat(32), 0),
+            testing::lovelace_utxo(testing::USER, 10_000_000),
+        )]);
+
+        let free = PParams {
+            min_fee_coefficient: 0,
+            min_fee_constant: 0,
+            coins_per_utxo_byte: 0,
+            ..testing::pparams()
+        };
+        let payment = build_payment(&utxos, testing::USER, to, amount, &free)?;
+
+        utxos.insert(
+            txid(hex::encode(SPONSOR_HASH), 0),
+            testing::lovelace_utxo(testing::VENDOR, SPONSOR_LOVELACE),
+        );
+
+        Ok((payment.cbor, utxos))
+    }
+
+    fn with_witness_set(cbor: &[u8], witness_set: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let parts = cbor::split_tx(cbor)?;
+        Ok(cbor::join_tx(&TxParts {
+            witness_set,
+            ..parts
+        }))
+    }
+
+    fn sponsor(cbor: &[u8], utxos: &HashMap<TxID, Utxo>) -> anyhow::Result<Sponsored> {
+        let sponsor = &utxos[&txid(hex::encode(SPONSOR_HASH), 0)];
+        sponsor_tx(
+            cbor,
+            utxos,
+            &SPONSOR_HASH,
+            0,
+            sponsor,
+            &testing::pparams(),
+            &testing::prices(),
+        )
+    }
+
+    #[test]
+    fn pays_the_fee_from_the_sponsor() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let pparams = testing::pparams();
+
+        let sponsored = sponsor(&cbor, &utxos)?;
+        let tx = MultiEraTx::decode(&sponsored.cbor)?;
+
+        let fee = tx.fee().unwrap_or_default();
+        // the user and the sponsor sign
+        let size = sponsored.cbor.len() as u64 + 2 * fee::VKEY_WITNESS_SIZE;
+        assert_eq!(fee, min_fee(size, &pparams));
+        assert_eq!(sponsored.cost, fee);
+
+        assert_eq!(tx.inputs().len(), 2);
+        let outputs = tx.outputs();
+        let change = outputs.last().unwrap();
+        assert_eq!(change.value().coin(), SPONSOR_LOVELACE - fee);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prices_the_ex_units_of_the_redeemers() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let pparams = testing::pparams();
+
+        // {5: [[mint, 0, Constr 0 [], [1_000_000, 500_000_000]]]}
+        let witness_set = hex::decode("a10581840100d87980821a000f42401a1dcd6500")?;
+        let cbor = with_witness_set(&cbor, &witness_set)?;
+
+        let sponsored = sponsor(&cbor, &utxos)?;
+        let tx = MultiEraTx::decode(&sponsored.cbor)?;
+
+        let size = sponsored.cbor.len() as u64 + 2 * fee::VKEY_WITNESS_SIZE;
+        // 1_000_000 * 577 / 10_000 + 500_000_000 * 721 / 10_000_000
+        assert_eq!(fee::script_fee(&tx, &testing::prices()), 93_750);
+        assert_eq!(tx.fee(), Some(min_fee(size, &pparams) + 93_750));
+
+        Ok(())
+    }
+
+    #[test]
+    fn charges_nothing_for_ex_units_on_free_heads() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+        let pparams = testing::pparams();
+
+        let witness_set = hex::decode("a10581840100d87980821a000f42401a1dcd6500")?;
+        let cbor = with_witness_set(&cbor, &witness_set)?;
+
+        let sponsor = &utxos[&txid(hex::encode(SPONSOR_HASH), 0)];
+        let free = ExUnitPrices::default();
+        let sponsored = sponsor_tx(&cbor, &utxos, &SPONSOR_HASH, 0, sponsor, &pparams, &free)?;
+        let tx = MultiEraTx::decode(&sponsored.cbor)?;
+
+        let size = sponsored.cbor.len() as u64 + 2 * fee::VKEY_WITNESS_SIZE;
+        assert_eq!(tx.fee(), Some(min_fee(size, &pparams)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_top_ups_of_outputs_the_sponsor_doesnt_own() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment_to(testing::USER, 100_000)?;
+        let sponsored = sponsor(&cbor, &utxos)?;
+        assert!(sponsored.largest_top_up > 0);
+        assert!(sponsored.cost > sponsored.largest_top_up);
+
+        let (cbor, utxos) = unpriced_payment_to(testing::VENDOR, 100_000)?;
+        let sponsored = sponsor(&cbor, &utxos)?;
+        assert_eq!(sponsored.largest_top_up, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_script_spends() -> anyhow::Result<()> {
+        let (cbor, utxos) = unpriced_payment()?;
+
+        // {5: [[spend, 0, Constr 0 [], [0, 0]]]}
+        let witness_set = hex::decode("a10581840000d87980820000")?;
+        let cbor = with_witness_set(&cbor, &witness_set)?;
+
+        assert!(sponsor(&cbor, &utxos).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Fixtures shared by the tests of the ledger and of its users.
+
+use std::collections::HashMap;
+
+use tx3_cardano::{
+    PParams,
+    pallas::{
+        crypto::{hash::Hasher, key::ed25519::SecretKey},
+        ledger::addresses::{
+            Address, Network, ShelleyAddress, ShelleyDelegationPart, ShelleyPaymentPart,
+        },
+    },
+};
+
+use super::witness::{self, VKeyWitness};
+use crate::hydra::model::{AssetValue, ExUnitPrices, HydraPParams, Ratio, Utxo, Value};
+
+/// Key addresses of the vending machine example
+pub const USER: &str = "addr_test1vz5yzy8fttld8yprtzhsz5kuwk46xs9npnfdh3ajaggm5ccyg00d6";
+pub const VENDOR: &str = "addr_test1vpg24ht6y8p6500k56hh9q0994rdvn2xulnul7a6w0yx4mg68vswg";
+
+/// Cost models of the vending machine example head, which runs without
+/// fees, with the mainnet fee and min utxo parameters on top
+pub fn pparams() -> PParams {
+    let hydra: HydraPParams = serde_json::from_str(include_str!(
+        "../../examples/vending-machine/chain/protocol-parameters.json"
+    ))
+    .unwrap();
+
+    PParams {
+        min_fee_coefficient: 44,
+        min_fee_constant: 155_381,
+        coins_per_utxo_byte: 4_310,
+        ..hydra.to_tx3_pparams(0)
+    }
+}
+
+/// Mainnet prices of the ex units, the example head doesn't charge them
+pub fn prices() -> ExUnitPrices {
+    ExUnitPrices {
+        price_memory: Ratio {
+            numerator: 577,
+            denominator: 10_000,
+        },
+        price_steps: Ratio {
+            numerator: 721,
+            denominator: 10_000_000,
+        },
+    }
+}
+
+pub fn lovelace_utxo(address: &str, lovelace: u64) -> Utxo {
+    let mut assets = HashMap::new();
+    assets.insert("lovelace".to_string(), AssetValue::Lovelace(lovelace));
+
+    Utxo {
+        address: address.to_string(),
+        datum: None,
+        datumhash: None,
+        inline_datum: None,
+        inline_datum_hash: None,
+        inline_datum_raw: None,
+        reference_script: None,
+        value: Value { assets },
+    }
+}
+
+/// Key of [`key_address`], for the tests that sign
+pub fn signing_key() -> SecretKey {
+    SecretKey::from([7; 32])
+}
+
+/// Testnet enterprise address of [`signing_key`]
+pub fn key_address() -> String {
+    let key_hash = Hasher::<224>::hash(signing_key().public_key().as_ref());
+    let address = ShelleyAddress::new(
+        Network::Testnet,
+        ShelleyPaymentPart::Key(key_hash),
+        ShelleyDelegationPart::Null,
+    );
+
+    Address::Shelley(address).to_bech32().unwrap()
+}
+
+/// Witness of [`signing_key`] over the body of the tx
+pub fn vkey_witness(cbor: &[u8]) -> VKeyWitness {
+    let body_hash = witness::body_hash(cbor).unwrap();
+    let key = signing_key();
+
+    VKeyWitness {
+        vkey: key.public_key().as_ref().to_vec(),
+        signature: key.sign(&body_hash[..]).as_ref().to_vec(),
+    }
+}
@@ -0,0 +1,85 @@
+//! Slot arithmetic. Heads follow the slots of the L1 they are opened on, so
+//! head time converts to slots with the shelley parameters of that network.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SlotConfig {
+    /// Posix time of `zero_slot`, in milliseconds
+    pub zero_time: u64,
+    pub zero_slot: u64,
+    /// Slot length in milliseconds
+    pub slot_length: u32,
+}
+
+impl SlotConfig {
+    pub const MAINNET: Self = Self {
+        zero_time: 1_596_059_091_000,
+        zero_slot: 4_492_800,
+        slot_length: 1000,
+    };
+
+    pub const PREPROD: Self = Self {
+        zero_time: 1_655_769_600_000,
+        zero_slot: 86_400,
+        slot_length: 1000,
+    };
+
+    /// Mainnet for network 1, preprod for any testnet. The network id
+    /// can't tell preprod from preview, so heads opened on preview or on a
+    /// custom network have to set `hydra.slot_config`.
+    pub fn for_network(network: u8) -> Self {
+        match network {
+            1 => Self::MAINNET,
+            _ => Self::PREPROD,
+        }
+    }
+
+    /// Slot containing the given posix time in milliseconds, times before
+    /// the zero slot are clamped to it
+    pub fn slot_at(&self, time: u64) -> u64 {
+        let elapsed = time.saturating_sub(self.zero_time);
+        self.zero_slot + elapsed / self.slot_length as u64
+    }
+
+    /// Posix time in milliseconds at which the slot starts
+    pub fn time_of(&self, slot: u64) -> u64 {
+        let elapsed = slot.saturating_sub(self.zero_slot);
+        self.zero_time + elapsed * self.slot_length as u64
+    }
+}
+
+pub fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_between_slots_and_time() {
+        let preprod = SlotConfig::PREPROD;
+
+        assert_eq!(preprod.slot_at(1_655_769_600_000), 86_400);
+        assert_eq!(preprod.time_of(86_400), 1_655_769_600_000);
+
+        // slots are a second long, times inside one fall to its start
+        assert_eq!(preprod.slot_at(1_655_769_601_999), 86_401);
+        assert_eq!(preprod.time_of(86_401), 1_655_769_601_000);
+    }
+
+    #[test]
+    fn clamps_to_the_zero_slot() {
+        let mainnet = SlotConfig::MAINNET;
+
+        assert_eq!(mainnet.slot_at(0), mainnet.zero_slot);
+        assert_eq!(mainnet.time_of(0), mainnet.zero_time);
+    }
+
+    #[test]
+    fn picks_the_config_of_the_network() {
+        assert_eq!(SlotConfig::for_network(1), SlotConfig::MAINNET);
+        assert_eq!(SlotConfig::for_network(0), SlotConfig::PREPROD);
+    }
+}
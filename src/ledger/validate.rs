@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+use serde::Serialize;
+use tx3_cardano::{
+    PParams,
+    pallas::{
+        crypto::hash::{Hash, Hasher},
+        ledger::{
+            addresses::{Address, ShelleyPaymentPart},
+            traverse::{Era, MultiEraOutput, MultiEraPolicyAssets, MultiEraTx},
+        },
+    },
+};
+
+use crate::hydra::model::{AssetValue, TxID, Utxo};
+
+use super::txid;
+
+const LOVELACE: &str = "lovelace";
+
+/// Multi-asset amounts keyed by `lovelace` or by the concatenated policy id
+/// and asset name, both hex encoded
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Balance(BTreeMap<String, i128>);
+
+impl Balance {
+    pub fn add(&mut self, unit: String, amount: i128) {
+        let entry = self.0.entry(unit.clone()).or_default();
+        *entry += amount;
+
+        if *entry == 0 {
+            self.0.remove(&unit);
+        }
+    }
+
+    pub fn lovelace(&self) -> i128 {
+        self.0.get(LOVELACE).copied().unwrap_or_default()
+    }
+
+    pub fn add_lovelace(&mut self, amount: i128) {
+        self.add(LOVELACE.to_string(), amount);
+    }
+
+    pub fn add_utxo(&mut self, utxo: &Utxo) {
+        for (policy, value) in &utxo.value.assets {
+            match value {
+                AssetValue::Lovelace(amount) => self.add_lovelace(*amount as i128),
+                AssetValue::Multi(assets) => {
+                    for (name, amount) in assets {
+                        self.add(format!("{policy}{name}"), *amount as i128);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn add_assets(&mut self, assets: &[MultiEraPolicyAssets]) {
+        for policy_assets in assets {
+            for asset in policy_assets.assets() {
+                let unit = format!("{}{}", policy_assets.policy(), hex::encode(asset.name()));
+                self.add(unit, asset.any_coin());
+            }
+        }
+    }
+
+    pub fn add_output(&mut self, output: &MultiEraOutput) {
+        let value = output.value();
+        self.add_lovelace(value.coin() as i128);
+        self.add_assets(&value.assets());
+    }
+}
+
+/// Reasons for the local ledger to reject a transaction
+#[derive(Debug, Serialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Violation {
+    UnsupportedEra {
+        era: String,
+    },
+    MissingInput {
+        input: TxID,
+    },
+    MissingReferenceInput {
+        input: TxID,
+    },
+    ValueNotConserved {
+        consumed: Balance,
+        produced: Balance,
+    },
+    FeeTooSmall {
+        required: u64,
+        provided: u64,
+    },
+    OutputTooSmall {
+        index: usize,
+        required: u64,
+        provided: u64,
+    },
+    MissingWitness {
+        key_hash: String,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::UnsupportedEra { era } => {
+                write!(f, "unsupported era {era}, expected Conway")
+            }
+            Violation::MissingInput { input } => {
+                write!(f, "input {input} not found in the utxo set")
+            }
+            Violation::MissingReferenceInput { input } => {
+                write!(f, "reference input {input} not found in the utxo set")
+            }
+            Violation::ValueNotConserved { consumed, produced } => write!(
+                f,
+                "value not conserved, consumed {consumed:?} but produced {produced:?}"
+            ),
+            Violation::FeeTooSmall { required, provided } => {
+                write!(f, "fee too small, required {required} but got {provided}")
+            }
+            Violation::OutputTooSmall {
+                index,
+                required,
+                provided,
+            } => write!(
+                f,
+                "output {index} below min utxo, required {required} but got {provided}"
+            ),
+            Violation::MissingWitness { key_hash } => {
+                write!(f, "missing vkey witness for {key_hash}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Ledger overhead counted on top of the serialized output for the min utxo
+const OUTPUT_OVERHEAD_BYTES: u64 = 160;
+
+/// Lovelace an output of the given serialized size must hold
+pub fn min_utxo_lovelace(output_size: u64, pparams: &PParams) -> u64 {
+    (OUTPUT_OVERHEAD_BYTES + output_size) * pparams.coins_per_utxo_byte
+}
+
+/// Linear fee of a tx of the given serialized size
+pub fn min_fee(tx_size: u64, pparams: &PParams) -> u64 {
+    pparams.min_fee_coefficient * tx_size + pparams.min_fee_constant
+}
+
+pub fn payment_key_hash(utxo: &Utxo) -> Option<Hash<28>> {
+    match Address::from_bech32(&utxo.address).ok()? {
+        Address::Shelley(address) => match address.payment() {
+            ShelleyPaymentPart::Key(hash) => Some(*hash),
+            ShelleyPaymentPart::Script(_) => None,
+        },
+        _ => None,
+    }
+}
+
+/// Phase-1 checks of a transaction against a utxo set. Returns every
+/// violation found, an empty list means the transaction is valid.
+///
+/// Covered: (reference) inputs exist, value is preserved (mint included),
+/// the linear min fee is covered, outputs meet the min utxo, and vkey
+/// witnesses cover the required signers and spent key-hash inputs.
+pub fn validate(utxos: &HashMap<TxID, Utxo>, tx: &MultiEraTx, pparams: &PParams) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    if tx.era() != Era::Conway {
+        violations.push(Violation::UnsupportedEra {
+            era: format!("{:?}", tx.era()),
+        });
+        return violations;
+    }
+
+    let mut required_signers: BTreeSet<Hash<28>> = tx
+        .required_signers()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .copied()
+        .collect();
+
+    let mut consumed = Balance::default();
+    let mut inputs_complete = true;
+    for input in tx.inputs() {
+        let input = txid(input.hash(), input.index());
+        match utxos.get(&input) {
+            Some(utxo) => {
+                consumed.add_utxo(utxo);
+                required_signers.extend(payment_key_hash(utxo));
+            }
+            None => {
+                inputs_complete = false;
+                violations.push(Violation::MissingInput { input });
+            }
+        }
+    }
+    consumed.add_assets(&tx.mints());
+
+    for input in tx.reference_inputs() {
+        let input = txid(input.hash(), input.index());
+        if !utxos.contains_key(&input) {
+            violations.push(Violation::MissingReferenceInput { input });
+        }
+    }
+
+    let fee = tx.fee().unwrap_or_default();
+
+    let mut produced = Balance::default();
+    for (index, output) in tx.outputs().iter().enumerate() {
+        produced.add_output(output);
+
+        let size = output.encode().len() as u64;
+        let required = min_utxo_lovelace(size, pparams);
+        let provided = output.value().coin();
+        if provided < required {
+            violations.push(Violation::OutputTooSmall {
+                index,
+                required,
+                provided,
+            });
+        }
+    }
+    produced.add_lovelace(fee as i128);
+
+    // a missing input already explains the imbalance
+    if inputs_complete && consumed != produced {
+        violations.push(Violation::ValueNotConserved { consumed, produced });
+    }
+
+    let size = tx.encode().len() as u64;
+    let required = min_fee(size, pparams);
+    if fee < required {
+        violations.push(Violation::FeeTooSmall {
+            required,
+            provided: fee,
+        });
+    }
+
+    let witnessed: BTreeSet<Hash<28>> = tx
+        .vkey_witnesses()
+        .iter()
+        .map(|witness| Hasher::<224>::hash(&witness.vkey))
+        .collect();
+
+    for key_hash in required_signers.difference(&witnessed) {
+        violations.push(Violation::MissingWitness {
+            key_hash: key_hash.to_string(),
+        });
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{payment::build_payment, testing, witness};
+
+    fn utxos(lovelace: u64) -> HashMap<TxID, Utxo> {
+        HashMap::from([(
+            txid("00".repeat(32), 0),
+            testing::lovelace_utxo(&testing::key_address(), lovelace),
+        )])
+    }
+
+    /// Payment of 5 ada from the test key to the vendor, signed if asked
+    fn payment(pparams: &PParams, signed: bool) -> anyhow::Result<Vec<u8>> {
+        let cbor = build_payment(
+            &utxos(100_000_000),
+            &testing::key_address(),
+            testing::VENDOR,
+            5_000_000,
+            pparams,
+        )?
+        .cbor;
+
+        if !signed {
+            return Ok(cbor);
+        }
+
+        witness::add_vkey_witnesses(&cbor, &[testing::vkey_witness(&cbor)])
+    }
+
+    #[test]
+    fn accepts_a_signed_payment() -> anyhow::Result<()> {
+        let pparams = testing::pparams();
+        let cbor = payment(&pparams, true)?;
+        let tx = MultiEraTx::decode(&cbor)?;
+
+        let violations = validate(&utxos(100_000_000), &tx, &pparams);
+        assert!(violations.is_empty(), "{violations:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn requires_the_witness_of_key_inputs() -> anyhow::Result<()> {
+        let pparams = testing::pparams();
+        let cbor = payment(&pparams, false)?;
+        let tx = MultiEraTx::decode(&cbor)?;
+
+        let violations = validate(&utxos(100_000_000), &tx, &pparams);
+        assert!(matches!(violations[..], [Violation::MissingWitness { .. }]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_missing_inputs_alone() -> anyhow::Result<()> {
+        let pparams = testing::pparams();
+        let cbor = payment(&pparams, true)?;
+        let tx = MultiEraTx::decode(&cbor)?;
+
+        // no value check without the inputs, and no signer to expect
+        let violations = validate(&HashMap::new(), &tx, &pparams);
+        assert!(matches!(violations[..], [Violation::MissingInput { .. }]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_unbalanced_value() -> anyhow::Result<()> {
+        let pparams = testing::pparams();
+        let cbor = payment(&pparams, true)?;
+        let tx = MultiEraTx::decode(&cbor)?;
+
+        let violations = validate(&utxos(200_000_000), &tx, &pparams);
+        assert!(matches!(
+            violations[..],
+            [Violation::ValueNotConserved { .. }]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_fees_below_the_min_fee() -> anyhow::Result<()> {
+        let free = PParams {
+            min_fee_coefficient: 0,
+            min_fee_constant: 0,
+            ..testing::pparams()
+        };
+        let cbor = payment(&free, true)?;
+        let tx = MultiEraTx::decode(&cbor)?;
+
+        let violations = validate(&utxos(100_000_000), &tx, &testing::pparams());
+        assert!(matches!(
+            violations[..],
+            [Violation::FeeTooSmall { provided: 0, .. }]
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Detached vkey witnesses. Signatures are checked against the hash of the
+//! body bytes as sent, and merged without re-encoding the body, so the tx id
+//! is the one the signers saw.
+
+use std::collections::HashSet;
+
+use anyhow::{Context, bail, ensure};
+use tx3_cardano::pallas::{
+    codec::minicbor::{
+        Decoder, Encoder,
+        data::{Tag, Type},
+    },
+    crypto::{
+        hash::{Hash, Hasher},
+        key::ed25519::{PublicKey, Signature},
+    },
+};
+
+use super::cbor::{self, CborMap, TxParts};
+
+const WITNESS_SET_VKEYS: u64 = 0;
+const SET_TAG: u64 = 258;
+
+/// Vkey of a witness set item along with the raw item
+type RawVKeyItem = (Vec<u8>, Vec<u8>);
+
+#[derive(Debug, Clone)]
+pub struct VKeyWitness {
+    pub vkey: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl VKeyWitness {
+    /// Checks the signature is the vkey signing the body hash
+    pub fn verify(&self, body_hash: &Hash<32>) -> anyhow::Result<()> {
+        let vkey: [u8; PublicKey::SIZE] = self
+            .vkey
+            .as_slice()
+            .try_into()
+            .context("vkey must be 32 bytes")?;
+
+        let signature: [u8; Signature::SIZE] = self
+            .signature
+            .as_slice()
+            .try_into()
+            .context("signature must be 64 bytes")?;
+
+        let vkey = PublicKey::from(vkey);
+        ensure!(
+            vkey.verify(&body_hash[..], &Signature::from(signature)),
+            "signature doesn't match the tx body"
+        );
+
+        Ok(())
+    }
+}
+
+/// Blake2b-256 of the raw body, the tx id and what vkey witnesses sign
+pub fn body_hash(cbor: &[u8]) -> anyhow::Result<Hash<32>> {
+    let parts = cbor::split_tx(cbor)?;
+    Ok(Hasher::<256>::hash(parts.body))
+}
+
+/// Decodes the `[vkey, signature]` items of a witness set vkey entry,
+/// returning them raw along with whether the entry was a tagged set
+fn vkey_items(raw: &[u8]) -> anyhow::Result<(Vec<RawVKeyItem>, bool)> {
+    let mut decoder = Decoder::new(raw);
+
+    let tagged = decoder.datatype()? == Type::Tag;
+    if tagged {
+        decoder.tag()?;
+    }
+
+    let len = decoder.array()?;
+    let mut items = Vec::new();
+    cbor::for_each_item(&mut decoder, len, |decoder| {
+        let input = decoder.input();
+        let start = decoder.position();
+        decoder.array()?;
+        let vkey = decoder.bytes()?.to_vec();
+        decoder.skip()?;
+        items.push((vkey, input[start..decoder.position()].to_vec()));
+        Ok(())
+    })?;
+
+    Ok((items, tagged))
+}
+
+/// Adds vkey witnesses to the tx, skipping vkeys that already signed it
+pub fn add_vkey_witnesses(cbor: &[u8], witnesses: &[VKeyWitness]) -> anyhow::Result<Vec<u8>> {
+    let parts = cbor::split_tx(cbor)?;
+    let mut witness_set = CborMap::decode(parts.witness_set)?;
+
+    let (mut items, tagged) = match witness_set.get(WITNESS_SET_VKEYS) {
+        Some(raw) => vkey_items(raw)?,
+        None => (Vec::new(), false),
+    };
+
+    let mut signed: HashSet<Vec<u8>> = items.iter().map(|(vkey, _)| vkey.clone()).collect();
+
+    for witness in witnesses {
+        if !signed.insert(witness.vkey.clone()) {
+            continue;
+        }
+
+        let mut item = Encoder::new(Vec::new());
+        item.array(2)?
+            .bytes(&witness.vkey)?
+            .bytes(&witness.signature)?;
+        items.push((witness.vkey.clone(), item.into_writer()));
+    }
+
+    let mut encoder = Encoder::new(Vec::new());
+    if tagged {
+        encoder.tag(Tag::new(SET_TAG))?;
+    }
+    encoder.array(items.len() as u64)?;
+    for (_, item) in items {
+        encoder.writer_mut().extend_from_slice(&item);
+    }
+
+    witness_set.insert(WITNESS_SET_VKEYS, encoder.into_writer());
+    let witness_set = witness_set.encode();
+
+    Ok(cbor::join_tx(&TxParts {
+        body: parts.body,
+        witness_set: &witness_set,
+        is_valid: parts.is_valid,
+        auxiliary_data: parts.auxiliary_data,
+    }))
+}
+
+/// Verifies every witness against the body and merges them into the tx
+pub fn verify_and_add(cbor: &[u8], witnesses: &[VKeyWitness]) -> anyhow::Result<Vec<u8>> {
+    let body_hash = body_hash(cbor)?;
+
+    for (index, witness) in witnesses.iter().enumerate() {
+        if let Err(error) = witness.verify(&body_hash) {
+            bail!("witness {index}: {error:#}");
+        }
+    }
+
+    add_vkey_witnesses(cbor, witnesses)
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+pub mod backend;
+pub mod cli;
+pub mod devnet;
+pub mod hydra;
+pub mod ledger;
+pub mod mock;
+pub mod trp;
+pub mod wallet;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Config {
+    pub trp: trp::Config,
+    /// Hydra head to serve, required unless running a devnet
+    #[serde(default)]
+    pub hydra: Option<hydra::Config>,
+    /// Serves an in-process ledger emulator instead of a hydra head
+    #[serde(default)]
+    pub devnet: Option<devnet::Config>,
+}
+impl Config {
+    /// Merges the config file, `/etc/tx3hydra/config.toml`, `TRP_HYDRA_*` env
+    /// vars and command-line overrides, in increasing priority.
+    pub fn new(path: Option<&Path>, listen_address: Option<&str>) -> anyhow::Result<Self> {
+        let path = path.unwrap_or(Path::new("config.toml"));
+
+        let config: Config = config::Config::builder()
+            .add_source(config::File::from(path).required(false))
+            .add_source(config::File::with_name("/etc/tx3hydra/config.toml").required(false))
+            .add_source(config::Environment::with_prefix("TRP_HYDRA").separator("_"))
+            .set_override_option("trp.listen_address", listen_address)?
+            .build()?
+            .try_deserialize()?;
+
+        Ok(config)
+    }
+}
+
+/// Token cancelled on Ctrl+C, `SIGTERM` or `SIGHUP`
+pub fn cancellation_token() -> CancellationToken {
+    let cancel = CancellationToken::new();
+
+    let cancel_cloned = cancel.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        debug!("shutdown signal received");
+        cancel_cloned.cancel();
+    });
+
+    cancel
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    let mut sighup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.expect("failed to listen for Ctrl+C"),
+        _ = sigterm.recv() => {},
+        _ = sighup.recv() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to listen for Ctrl+C");
+}
@@ -1,84 +1,114 @@
-use std::{env, sync::Arc};
+use std::sync::Arc;
 
-use serde::Deserialize;
+use clap::Parser;
 use tokio_util::sync::CancellationToken;
-use tracing::{Level, debug};
+use tracing::Level;
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
-mod hydra;
-mod trp;
+use anyhow::bail;
+use tx3_hydra::{
+    Config,
+    cli::{self, Cli, Command, ReplayArgs},
+    devnet::{self, Devnet},
+    hydra, trp,
+};
 
 #[tokio::main()]
 async fn main() -> anyhow::Result<()> {
-    let env_filter = EnvFilter::builder()
-        .with_default_directive(Level::INFO.into())
-        .with_env_var("RUST_LOG")
-        .from_env_lossy();
+    let cli = Cli::parse();
+
+    let env_filter = EnvFilter::builder().with_default_directive(Level::INFO.into());
+    let env_filter = match &cli.log_level {
+        Some(directives) => env_filter.parse_lossy(directives),
+        None => env_filter.with_env_var("RUST_LOG").from_env_lossy(),
+    };
 
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(fmt::layer().with_writer(std::io::stderr))
         .with(env_filter)
         .init();
 
-    let config = Config::new()?;
+    let load_config = || Config::new(cli.config.as_deref(), cli.listen_address.as_deref());
+
+    match cli.command.clone().unwrap_or(Command::Serve) {
+        Command::Serve => serve(load_config()?).await,
+        Command::CheckConfig => cli::check_config(&load_config()?),
+        Command::Healthcheck(args) => cli::healthcheck(args, load_config().ok()).await,
+        Command::Version => cli::version(),
+        Command::Resolve(args) => cli::resolve(args).await,
+        Command::Submit(args) => cli::submit(args).await,
+        Command::Utxos(args) => cli::utxos(args).await,
+        Command::ResolveOffline(args) => cli::resolve_offline(args).await,
+        Command::Devnet(args) => {
+            let mut config = load_config()?;
+            config.devnet = Some(devnet::Config {
+                network: args.network,
+                initial_utxo: args.initial_utxo,
+                protocol_parameters: args.protocol_parameters,
+                slot_config: None,
+            });
+            serve(config).await
+        }
+        Command::Replay(args) => replay(load_config()?, args).await,
+    }
+}
 
-    let cancellation_token = cancellation_token();
+async fn serve(config: Config) -> anyhow::Result<()> {
+    let cancellation_token = tx3_hydra::cancellation_token();
 
-    let (hydra_channel, _) = tokio::sync::broadcast::channel::<hydra::model::Event>(1);
+    let (hydra_channel, _) = tokio::sync::broadcast::channel::<hydra::model::Event>(64);
 
     let hydra_channel = Arc::new(hydra_channel);
 
-    let hydra_adapter = Arc::new(
-        hydra::HydraAdapter::try_new(config.hydra.clone(), Arc::clone(&hydra_channel)).await?,
-    );
+    if let Some(devnet_config) = config.devnet.clone() {
+        let devnet = Devnet::try_new(devnet_config, Arc::clone(&hydra_channel))?;
+
+        return trp::run(config.trp.clone(), Arc::new(devnet), cancellation_token).await;
+    }
+
+    let Some(hydra_config) = config.hydra.clone() else {
+        bail!("missing [hydra] config section");
+    };
+
+    let hydra_adapter =
+        Arc::new(hydra::HydraAdapter::try_new(hydra_config, Arc::clone(&hydra_channel)).await?);
 
-    let hydra_subscribe = hydra_adapter.subscribe(cancellation_token.clone());
-    let trp_server = trp::run(
-        config.trp.clone(),
-        Arc::clone(&hydra_adapter),
-        Arc::clone(&hydra_channel),
-        cancellation_token.clone(),
-    );
+    // the hydra connection outlives the trp server so that pending submits
+    // can still observe their outcome while the server drains
+    let hydra_cancellation_token = CancellationToken::new();
+
+    let hydra_subscribe = hydra_adapter.subscribe(hydra_cancellation_token.clone());
+    let trp_server = async {
+        let result = trp::run(
+            config.trp.clone(),
+            Arc::clone(&hydra_adapter),
+            cancellation_token.clone(),
+        )
+        .await;
+
+        hydra_cancellation_token.cancel();
+        result
+    };
 
     tokio::try_join!(hydra_subscribe, trp_server)?;
 
     Ok(())
 }
 
-#[derive(Deserialize, Clone)]
-pub struct Config {
-    trp: trp::Config,
-    hydra: hydra::Config,
-}
-impl Config {
-    pub fn new() -> anyhow::Result<Self> {
-        let config: Config = config::Config::builder()
-            .add_source(
-                config::File::with_name(
-                    &env::var("TRP_HYDRA_CONFIG").unwrap_or("config.toml".into()),
-                )
-                .required(false),
-            )
-            .add_source(config::File::with_name("/etc/tx3hydra/config.toml").required(false))
-            .add_source(config::Environment::with_prefix("TRP_HYDRA").separator("_"))
-            .build()?
-            .try_deserialize()?;
-
-        Ok(config)
-    }
-}
+async fn replay(config: Config, args: ReplayArgs) -> anyhow::Result<()> {
+    let cancellation_token = tx3_hydra::cancellation_token();
+
+    let Some(hydra_config) = config.hydra.clone() else {
+        bail!("missing [hydra] config section");
+    };
 
-fn cancellation_token() -> CancellationToken {
-    let cancel = CancellationToken::new();
+    let (hydra_channel, _) = tokio::sync::broadcast::channel::<hydra::model::Event>(64);
 
-    let cancel_cloned = cancel.clone();
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("failed to listen for Ctrl+C");
-        debug!("shutdown signal received");
-        cancel_cloned.cancel();
-    });
+    let hydra_adapter = Arc::new(hydra::HydraAdapter::offline(
+        hydra_config,
+        Arc::new(hydra_channel),
+    ));
+    hydra_adapter.replay(&args.journal, args.until).await?;
 
-    cancel
+    trp::run(config.trp.clone(), hydra_adapter, cancellation_token).await
 }
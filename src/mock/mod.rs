@@ -0,0 +1,570 @@
+//! Scriptable stand-in for a hydra-node, so that the adapter and the TRP
+//! methods can be exercised without a real head.
+//!
+//! It serves the WebSocket API on `/` plus the `/protocol-parameters` and
+//! `/snapshot/utxo` HTTP endpoints on the same address, like hydra-node does.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+    routing::get,
+};
+use futures_util::{SinkExt, StreamExt, stream::SplitSink};
+use serde::Deserialize;
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, RwLock, broadcast},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
+
+use crate::{
+    hydra::{
+        self,
+        model::{
+            ConfirmedTx, Event, HeadStatus, NewTx, Snapshot, Transaction, TxID, Utxo,
+            ValidationError,
+        },
+    },
+    ledger,
+};
+
+fn default_listen_address() -> String {
+    "127.0.0.1:4001".into()
+}
+
+fn default_head_status() -> HeadStatus {
+    HeadStatus::Open
+}
+
+fn default_confirm() -> bool {
+    true
+}
+
+/// Scripted reply to a `NewTx`
+#[derive(Deserialize, Clone)]
+pub struct Step {
+    /// Reply `TxInvalid` with this reason instead of `TxValid`
+    #[serde(default)]
+    pub invalid: Option<String>,
+    /// Emit `SnapshotConfirmed` after `TxValid`
+    #[serde(default = "default_confirm")]
+    pub confirm: bool,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            invalid: None,
+            confirm: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Scenario {
+    #[serde(default = "default_listen_address")]
+    pub listen_address: String,
+    #[serde(default = "default_head_status")]
+    pub head_status: HeadStatus,
+    /// Hydra-format utxo file the head starts with
+    #[serde(default)]
+    pub initial_utxo: Option<PathBuf>,
+    /// Protocol parameters file served on `/protocol-parameters`. Without
+    /// one there are no cost models, which the tx3 compiler can't resolve with.
+    #[serde(default)]
+    pub protocol_parameters: Option<PathBuf>,
+    /// Replies to the submitted txs, in order
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    /// Reply once the scripted steps run out
+    #[serde(default)]
+    pub default_step: Step,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            listen_address: default_listen_address(),
+            head_status: default_head_status(),
+            initial_utxo: None,
+            protocol_parameters: None,
+            steps: Vec::new(),
+            default_step: Step::default(),
+        }
+    }
+}
+
+impl Scenario {
+    /// Loads a scenario file, paths in it are relative to the file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut scenario: Scenario = config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()?
+            .try_deserialize()
+            .context("decoding scenario")?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        scenario.initial_utxo = scenario.initial_utxo.map(|x| base.join(x));
+        scenario.protocol_parameters = scenario.protocol_parameters.map(|x| base.join(x));
+
+        Ok(scenario)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "tag")]
+enum ClientInput {
+    NewTx { transaction: NewTx },
+}
+
+struct MockState {
+    head_status: HeadStatus,
+    pparams: serde_json::Value,
+    /// Last confirmed snapshot
+    utxos: RwLock<HashMap<TxID, Utxo>>,
+    /// Snapshot plus the txs accepted since, like the local ledger of a node
+    ledger: RwLock<HashMap<TxID, Utxo>>,
+    seq: Mutex<u64>,
+    steps: Mutex<VecDeque<Step>>,
+    default_step: Step,
+    events: broadcast::Sender<Event>,
+}
+
+impl MockState {
+    fn try_new(scenario: Scenario) -> anyhow::Result<Self> {
+        let utxos = match &scenario.initial_utxo {
+            Some(path) => hydra::read_utxo_file(path)?,
+            None => HashMap::new(),
+        };
+
+        let pparams = match &scenario.protocol_parameters {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                serde_json::from_str(&content).context("decoding protocol parameters")?
+            }
+            None => serde_json::json!({
+                "txFeeFixed": 0,
+                "txFeePerByte": 0,
+                "utxoCostPerByte": 0,
+                "costModels": {},
+                "executionUnitPrices": { "priceMemory": 0, "priceSteps": 0 },
+                "maxTxExecutionUnits": { "memory": 14_000_000, "steps": 10_000_000_000u64 },
+                "maxTxSize": 16_384,
+                "maxValueSize": 5_000,
+                "collateralPercentage": 150,
+                "maxCollateralInputs": 3,
+                "stakeAddressDeposit": 2_000_000,
+                "stakePoolDeposit": 500_000_000,
+                "minFeeRefScriptCostPerByte": 15,
+            }),
+        };
+
+        let (events, _) = broadcast::channel(64);
+
+        Ok(Self {
+            head_status: scenario.head_status,
+            pparams,
+            ledger: RwLock::new(utxos.clone()),
+            utxos: RwLock::new(utxos),
+            seq: Mutex::new(0),
+            steps: Mutex::new(scenario.steps.into()),
+            default_step: scenario.default_step,
+            events,
+        })
+    }
+
+    async fn greetings(&self) -> Event {
+        Event::Greetings {
+            head_status: self.head_status.clone(),
+            snapshot: self.utxos.read().await.clone(),
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        // no connected clients is not an error for the mock
+        let _ = self.events.send(event);
+    }
+
+    fn reject(&self, tx_id: String, reason: String) {
+        info!(tx_id, reason, "replying TxInvalid");
+        self.emit(Event::TxInvalid {
+            transaction: Transaction { tx_id },
+            validation_error: ValidationError { reason },
+        });
+    }
+
+    async fn handle_input(&self, input: &[u8]) {
+        let transaction = match serde_json::from_slice::<ClientInput>(input) {
+            Ok(ClientInput::NewTx { transaction }) => transaction,
+            Err(error) => {
+                warn!(?error, "unsupported client input");
+                return;
+            }
+        };
+
+        // without a body there is no tx id to reply with
+        let cbor = match hex::decode(&transaction.cbor_hex) {
+            Ok(cbor) => cbor,
+            Err(error) => {
+                self.reject(String::new(), format!("DecoderFailure: {error}"));
+                return;
+            }
+        };
+
+        let tx = match MultiEraTx::decode(&cbor) {
+            Ok(tx) => tx,
+            Err(error) => {
+                self.reject(String::new(), format!("DecoderFailure: {error}"));
+                return;
+            }
+        };
+
+        let tx_id = tx.hash().to_string();
+
+        let step = self
+            .steps
+            .lock()
+            .await
+            .pop_front()
+            .unwrap_or_else(|| self.default_step.clone());
+
+        if let Some(reason) = step.invalid {
+            self.reject(tx_id, reason);
+            return;
+        }
+
+        // a node replies TxValid once the tx is in its local ledger
+        if let Err(error) = ledger::apply_tx(&mut *self.ledger.write().await, &tx) {
+            self.reject(tx_id, format!("{error:#}"));
+            return;
+        }
+
+        info!(tx_id, "replying TxValid");
+        self.emit(Event::TxValid { tx_id });
+
+        if step.confirm {
+            self.confirm(&tx, transaction.cbor_hex).await;
+        }
+    }
+
+    async fn confirm(&self, tx: &MultiEraTx<'_>, cbor_hex: String) {
+        let ledger = self.ledger.read().await.clone();
+        *self.utxos.write().await = ledger.clone();
+
+        let mut seq = self.seq.lock().await;
+        *seq += 1;
+
+        self.emit(Event::SnapshotConfirmed {
+            snapshot: Snapshot {
+                utxo: ledger,
+                confirmed: vec![ConfirmedTx {
+                    tx_id: tx.hash().to_string(),
+                    cbor_hex,
+                }],
+            },
+            seq: *seq,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        });
+    }
+}
+
+async fn send_event(sink: &mut SplitSink<WebSocket, Message>, event: &Event) -> anyhow::Result<()> {
+    let json = serde_json::to_string(event)?;
+    sink.send(Message::Text(json.into())).await?;
+    Ok(())
+}
+
+async fn handle_socket(socket: WebSocket, state: Arc<MockState>) {
+    let (mut sink, mut stream) = socket.split();
+    let mut events = state.events.subscribe();
+
+    if send_event(&mut sink, &state.greetings().await)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => state.handle_input(text.as_bytes()).await,
+                Some(Ok(Message::Binary(bytes))) => state.handle_input(&bytes).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if send_event(&mut sink, &event).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "mock client lagging behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+async fn websocket(ws: WebSocketUpgrade, State(state): State<Arc<MockState>>) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+async fn protocol_parameters(State(state): State<Arc<MockState>>) -> Json<serde_json::Value> {
+    Json(state.pparams.clone())
+}
+
+async fn snapshot_utxo(State(state): State<Arc<MockState>>) -> Json<HashMap<TxID, Utxo>> {
+    Json(state.utxos.read().await.clone())
+}
+
+/// Serves the mock hydra-node on an already bound listener until cancelled
+pub async fn serve(
+    listener: TcpListener,
+    scenario: Scenario,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let state = Arc::new(MockState::try_new(scenario)?);
+
+    let app = Router::new()
+        .route("/", get(websocket))
+        .route("/protocol-parameters", get(protocol_parameters))
+        .route("/snapshot/utxo", get(snapshot_utxo))
+        .with_state(state);
+
+    info!(address = ?listener.local_addr()?, "mock hydra-node running");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(cancellation_token.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        hydra::{HydraAdapter, model::NewTx},
+        ledger::{testing, txid},
+        trp,
+    };
+
+    /// Hex of the v1beta0 TIR of `examples/mock/pay.tx3`
+    const PAY_TIR: &str = include_str!("../../examples/mock/pay.tir");
+
+    async fn rpc(
+        client: &reqwest::Client,
+        url: &str,
+        method: &str,
+        params: serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let response = client
+            .post(url)
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Resolves a payment from the key address and signs it
+    async fn signed_payment(
+        client: &reqwest::Client,
+        url: &str,
+        lovelace: u64,
+    ) -> anyhow::Result<serde_json::Value> {
+        let resolved = rpc(
+            client,
+            url,
+            "trp.resolve",
+            json!({
+                "tir": { "content": PAY_TIR.trim(), "encoding": "hex", "version": "v1beta0" },
+                "args": {
+                    "quantity": lovelace,
+                    "sender": testing::key_address(),
+                    "receiver": testing::VENDOR,
+                },
+            }),
+        )
+        .await?;
+
+        let tx = resolved["result"]["tx"]
+            .as_str()
+            .with_context(|| format!("resolve failed: {resolved}"))?;
+        let witness = testing::vkey_witness(&hex::decode(tx)?);
+
+        Ok(json!({
+            "tx": { "payload": tx, "encoding": "hex" },
+            "witnesses": [{
+                "key": { "payload": hex::encode(witness.vkey), "encoding": "hex" },
+                "signature": { "payload": hex::encode(witness.signature), "encoding": "hex" },
+            }],
+        }))
+    }
+
+    #[tokio::test]
+    async fn resolves_and_submits_through_the_adapter() -> anyhow::Result<()> {
+        let utxo_file = std::env::temp_dir().join(format!("mock-utxo-{}.json", std::process::id()));
+        let utxos = HashMap::from([(
+            txid("00".repeat(32), 0),
+            testing::lovelace_utxo(&testing::key_address(), 100_000_000),
+        )]);
+        std::fs::write(&utxo_file, serde_json::to_string(&utxos)?)?;
+
+        let scenario = Scenario {
+            initial_utxo: Some(utxo_file.clone()),
+            // the compiler needs cost models even for txs without scripts
+            protocol_parameters: Some(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("examples/vending-machine/chain/protocol-parameters.json"),
+            ),
+            steps: vec![
+                Step::default(),
+                Step {
+                    invalid: Some("ValueNotConservedUTxO".into()),
+                    confirm: false,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let cancellation_token = CancellationToken::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mock_address = listener.local_addr()?;
+        tokio::spawn(serve(listener, scenario, cancellation_token.clone()));
+
+        let (channel, _) = broadcast::channel(64);
+        let adapter = Arc::new(
+            HydraAdapter::try_new(
+                serde_json::from_value(json!({
+                    "network": 0,
+                    "ws_url": format!("ws://{mock_address}"),
+                    "http_url": format!("http://{mock_address}"),
+                }))?,
+                Arc::new(channel),
+            )
+            .await?,
+        );
+        tokio::spawn({
+            let adapter = Arc::clone(&adapter);
+            let cancellation_token = cancellation_token.clone();
+            async move { adapter.subscribe(cancellation_token).await }
+        });
+
+        // the server binds its own listener, so a free port is picked up front
+        let trp_address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        tokio::spawn(trp::run(
+            serde_json::from_value(json!({ "listen_address": trp_address.to_string() }))?,
+            Arc::clone(&adapter),
+            cancellation_token.clone(),
+        ));
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{trp_address}");
+
+        // ready once the greetings and the pparams made it to the adapter
+        let mut ready = false;
+        for _ in 0..50 {
+            let response = client.get(format!("{url}/readyz")).send().await;
+            if response.is_ok_and(|response| response.status().is_success()) {
+                ready = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(ready, "trp server never got ready");
+
+        let accepted = rpc(
+            &client,
+            &url,
+            "trp.submit",
+            signed_payment(&client, &url, 5_000_000).await?,
+        )
+        .await?;
+        let hash = accepted["result"]["hash"]
+            .as_str()
+            .with_context(|| format!("submit failed: {accepted}"))?
+            .to_string();
+
+        // the confirmed snapshot reaches the adapter after the TxValid
+        let mut paid = Vec::new();
+        for _ in 0..50 {
+            let response = rpc(
+                &client,
+                &url,
+                "trp.queryUtxos",
+                json!({ "address": testing::VENDOR }),
+            )
+            .await?;
+            paid = response["result"]["utxos"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            if !paid.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(paid.len(), 1);
+        assert!(adapter.read_utxos().await.0.contains_key(&txid(&hash, 0)));
+
+        let rejected = rpc(
+            &client,
+            &url,
+            "trp.submit",
+            signed_payment(&client, &url, 1_000_000).await?,
+        )
+        .await?;
+        assert_eq!(rejected["error"]["message"], "invalid transaction");
+        assert_eq!(rejected["error"]["data"], "ValueNotConservedUTxO");
+
+        cancellation_token.cancel();
+        std::fs::remove_file(utxo_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_undecodable_txs() -> anyhow::Result<()> {
+        let state = MockState::try_new(Scenario::default())?;
+        let mut events = state.events.subscribe();
+
+        let input = json!({ "tag": "NewTx", "transaction": NewTx::new(vec![0xff]) });
+        state.handle_input(input.to_string().as_bytes()).await;
+
+        let Event::TxInvalid {
+            validation_error, ..
+        } = events.recv().await?
+        else {
+            panic!("undecodable tx not rejected");
+        };
+        assert!(validation_error.reason.starts_with("DecoderFailure"));
+
+        Ok(())
+    }
+}
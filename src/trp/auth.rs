@@ -0,0 +1,48 @@
+//! Caller identification. API keys are read from the HTTP request and handed
+//! to the methods through the request extensions.
+
+use jsonrpsee::{
+    Extensions,
+    server::HttpRequest,
+    types::{ErrorObject, ErrorObjectOwned},
+};
+
+/// JSON-RPC error code for calls the caller is not allowed to make
+pub const UNAUTHORIZED_CODE: i32 = -32001;
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// API key the request was made with
+#[derive(Debug, Clone)]
+pub struct ApiKey(pub String);
+
+/// Tags the request with its API key, from `x-api-key` or a bearer
+/// `authorization` header
+pub fn tag_api_key(mut request: HttpRequest) -> HttpRequest {
+    let headers = request.headers();
+
+    let key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(http::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        })
+        .map(|key| ApiKey(key.trim().to_string()));
+
+    if let Some(key) = key {
+        request.extensions_mut().insert(key);
+    }
+
+    request
+}
+
+pub fn api_key(extensions: &Extensions) -> Option<&String> {
+    extensions.get::<ApiKey>().map(|key| &key.0)
+}
+
+pub fn unauthorized(message: &str) -> ErrorObjectOwned {
+    ErrorObject::owned(UNAUTHORIZED_CODE, message.to_string(), None::<String>)
+}
@@ -0,0 +1,430 @@
+//! Blockfrost-compatible REST facade over the head, for wallets and SDKs
+//! that only speak Blockfrost. Only the endpoints needed to build and
+//! submit transactions are served, in the Blockfrost response shapes.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use jsonrpsee::types::{ErrorCode, ErrorObjectOwned};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::{
+    backend::Backend,
+    hydra::model::{AssetValue, TxID, Utxo},
+    ledger,
+    trp::{Context, ListenerConfig, methods::submit::submit_and_wait},
+};
+
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Hydra heads have no blocks, snapshots stand in for them
+const NO_BLOCK_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Error body in the Blockfrost format
+#[derive(Serialize)]
+struct ApiError {
+    status_code: u16,
+    error: String,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status_code: status.as_u16(),
+            error: status.canonical_reason().unwrap_or_default().to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn internal(error: anyhow::Error) -> Self {
+        error!(?error);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{error:#}"))
+    }
+}
+
+impl From<ErrorObjectOwned> for ApiError {
+    fn from(error: ErrorObjectOwned) -> Self {
+        let status = match ErrorCode::from(error.code()) {
+            ErrorCode::InvalidParams | ErrorCode::InvalidRequest | ErrorCode::ParseError => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::ServerIsBusy => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let message = match error.data() {
+            Some(data) => format!("{}: {}", error.message(), data.get()),
+            None => error.message().to_string(),
+        };
+
+        Self::new(status, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status =
+            StatusCode::from_u16(self.status_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, Json(self)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+fn default_count() -> usize {
+    MAX_PAGE_SIZE
+}
+
+fn default_page() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+struct Pagination {
+    #[serde(default = "default_count")]
+    count: usize,
+    #[serde(default = "default_page")]
+    page: usize,
+    #[serde(default)]
+    order: Order,
+}
+
+#[derive(Serialize)]
+struct Amount {
+    unit: String,
+    quantity: String,
+}
+
+/// Lovelace first, then `policy ++ asset name` units in order
+fn amounts(utxo: &Utxo) -> Vec<Amount> {
+    let mut lovelace = 0;
+    let mut assets = BTreeMap::new();
+
+    for (policy, value) in &utxo.value.assets {
+        match value {
+            AssetValue::Lovelace(amount) => lovelace = *amount,
+            AssetValue::Multi(by_name) => {
+                for (name, amount) in by_name {
+                    assets.insert(format!("{policy}{name}"), *amount);
+                }
+            }
+        }
+    }
+
+    std::iter::once(("lovelace".to_string(), lovelace))
+        .chain(assets)
+        .map(|(unit, quantity)| Amount {
+            unit,
+            quantity: quantity.to_string(),
+        })
+        .collect()
+}
+
+fn reference_script_hash(utxo: &Utxo) -> Result<Option<String>, ApiError> {
+    utxo.reference_script
+        .as_ref()
+        .map(|script| Ok(ledger::script_hash(script)?.to_string()))
+        .transpose()
+        .map_err(ApiError::internal)
+}
+
+fn split_ref(r#ref: &TxID) -> (&str, u64) {
+    let (hash, index) = r#ref.split_once('#').unwrap_or((r#ref.as_str(), "0"));
+    (hash, index.parse().unwrap_or_default())
+}
+
+#[derive(Serialize)]
+struct AddressUtxo {
+    address: String,
+    tx_hash: String,
+    tx_index: u64,
+    output_index: u64,
+    amount: Vec<Amount>,
+    block: String,
+    data_hash: Option<String>,
+    inline_datum: Option<String>,
+    reference_script_hash: Option<String>,
+}
+
+async fn address_utxos<B: Backend>(
+    State(context): State<Arc<Context<B>>>,
+    Path(address): Path<String>,
+    Query(pagination): Query<Pagination>,
+) -> ApiResult<Vec<AddressUtxo>> {
+    if pagination.count == 0 || pagination.count > MAX_PAGE_SIZE || pagination.page == 0 {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("count must be between 1 and {MAX_PAGE_SIZE} and page at least 1"),
+        ));
+    }
+
+    let snapshot = context.backend.read_utxos().await;
+
+    let mut matching: Vec<_> = snapshot
+        .0
+        .iter()
+        .filter(|(_, utxo)| utxo.address == address)
+        .collect();
+
+    matching.sort_unstable_by(|(a, _), (b, _)| split_ref(a).cmp(&split_ref(b)));
+    if pagination.order == Order::Desc {
+        matching.reverse();
+    }
+
+    let utxos = matching
+        .into_iter()
+        .skip((pagination.page - 1) * pagination.count)
+        .take(pagination.count)
+        .map(|(r#ref, utxo)| {
+            let (tx_hash, index) = split_ref(r#ref);
+
+            Ok(AddressUtxo {
+                address: utxo.address.clone(),
+                tx_hash: tx_hash.to_string(),
+                tx_index: index,
+                output_index: index,
+                amount: amounts(utxo),
+                block: NO_BLOCK_HASH.to_string(),
+                data_hash: utxo
+                    .datumhash
+                    .clone()
+                    .or_else(|| utxo.inline_datum_hash.clone()),
+                inline_datum: utxo.inline_datum_raw.clone(),
+                reference_script_hash: reference_script_hash(utxo)?,
+            })
+        })
+        .collect::<Result<_, ApiError>>()?;
+
+    Ok(Json(utxos))
+}
+
+#[derive(Serialize)]
+struct TxOutput {
+    address: String,
+    amount: Vec<Amount>,
+    output_index: u64,
+    data_hash: Option<String>,
+    inline_datum: Option<String>,
+    collateral: bool,
+    reference_script_hash: Option<String>,
+    consumed_by_tx: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TxUtxos {
+    hash: String,
+    inputs: Vec<serde_json::Value>,
+    outputs: Vec<TxOutput>,
+}
+
+/// Outputs of the tx that are still unspent in the snapshot. The head keeps
+/// no tx history, so inputs and spent outputs are unknown.
+async fn tx_utxos<B: Backend>(
+    State(context): State<Arc<Context<B>>>,
+    Path(hash): Path<String>,
+) -> ApiResult<TxUtxos> {
+    let snapshot = context.backend.read_utxos().await;
+
+    let mut outputs = snapshot
+        .0
+        .iter()
+        .filter(|(r#ref, _)| split_ref(r#ref).0 == hash)
+        .map(|(r#ref, utxo)| {
+            Ok(TxOutput {
+                address: utxo.address.clone(),
+                amount: amounts(utxo),
+                output_index: split_ref(r#ref).1,
+                data_hash: utxo
+                    .datumhash
+                    .clone()
+                    .or_else(|| utxo.inline_datum_hash.clone()),
+                inline_datum: utxo.inline_datum_raw.clone(),
+                collateral: false,
+                reference_script_hash: reference_script_hash(utxo)?,
+                consumed_by_tx: None,
+            })
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+
+    if outputs.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "The requested component has not been found.",
+        ));
+    }
+
+    outputs.sort_unstable_by_key(|output| output.output_index);
+
+    Ok(Json(TxUtxos {
+        hash,
+        inputs: Vec::new(),
+        outputs,
+    }))
+}
+
+/// Takes the raw tx CBOR, as `application/cbor`, and submits it like
+/// `trp.submit`
+async fn submit_tx<B: Backend>(
+    State(context): State<Arc<Context<B>>>,
+    body: Bytes,
+) -> ApiResult<String> {
+    context.ensure_accepting()?;
+
+    let submits = context.submits.clone();
+    let response = submits
+        .track_future(async { submit_and_wait(&context, body.to_vec()).await })
+        .await
+        .map_err(ErrorObjectOwned::from)?;
+
+    let hash = response
+        .get("hash")
+        .and_then(|hash| hash.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(Json(hash))
+}
+
+#[derive(Serialize)]
+struct ProtocolParameters {
+    epoch: u64,
+    min_fee_a: u64,
+    min_fee_b: u64,
+    max_tx_size: u64,
+    max_val_size: String,
+    key_deposit: String,
+    pool_deposit: String,
+    price_mem: f64,
+    price_step: f64,
+    max_tx_ex_mem: String,
+    max_tx_ex_steps: String,
+    collateral_percent: u64,
+    max_collateral_inputs: u64,
+    coins_per_utxo_size: String,
+    coins_per_utxo_word: String,
+    min_fee_ref_script_cost_per_byte: f64,
+    cost_models: BTreeMap<String, Vec<i64>>,
+    cost_models_raw: BTreeMap<String, Vec<i64>>,
+}
+
+async fn latest_parameters<B: Backend>(
+    State(context): State<Arc<Context<B>>>,
+) -> ApiResult<ProtocolParameters> {
+    let pparams = context
+        .backend
+        .get_pparams()
+        .await
+        .map_err(ApiError::internal)?;
+    let head = context
+        .backend
+        .protocol_parameters()
+        .await
+        .map_err(ApiError::internal)?;
+
+    let cost_models: BTreeMap<_, _> = pparams
+        .cost_models
+        .iter()
+        .map(|(version, model)| (format!("PlutusV{}", version + 1), model.clone()))
+        .collect();
+
+    Ok(Json(ProtocolParameters {
+        epoch: 0,
+        min_fee_a: pparams.min_fee_coefficient,
+        min_fee_b: pparams.min_fee_constant,
+        max_tx_size: head.max_tx_size,
+        max_val_size: head.max_value_size.to_string(),
+        key_deposit: head.stake_address_deposit.to_string(),
+        pool_deposit: head.stake_pool_deposit.to_string(),
+        price_mem: head.execution_unit_prices.price_memory.to_f64(),
+        price_step: head.execution_unit_prices.price_steps.to_f64(),
+        max_tx_ex_mem: head.max_tx_execution_units.memory.to_string(),
+        max_tx_ex_steps: head.max_tx_execution_units.steps.to_string(),
+        collateral_percent: head.collateral_percentage,
+        max_collateral_inputs: head.max_collateral_inputs,
+        coins_per_utxo_size: pparams.coins_per_utxo_byte.to_string(),
+        coins_per_utxo_word: (pparams.coins_per_utxo_byte * 8).to_string(),
+        min_fee_ref_script_cost_per_byte: head.min_fee_ref_script_cost_per_byte.to_f64(),
+        cost_models: cost_models.clone(),
+        cost_models_raw: cost_models,
+    }))
+}
+
+#[derive(Serialize)]
+struct Block {
+    time: u64,
+    height: u64,
+    hash: String,
+    slot: u64,
+    epoch: u64,
+    epoch_slot: u64,
+    slot_leader: String,
+    size: u64,
+    tx_count: u64,
+    confirmations: u64,
+}
+
+/// The last snapshot, as a block
+async fn latest_block<B: Backend>(State(context): State<Arc<Context<B>>>) -> ApiResult<Block> {
+    let chain_point = context
+        .backend
+        .chain_point()
+        .await
+        .map_err(ApiError::internal)?;
+    let health = context.backend.health_status().await;
+
+    Ok(Json(Block {
+        time: (chain_point.timestamp / 1000) as u64,
+        height: health.snapshot_seq,
+        hash: hex::encode(&chain_point.hash),
+        slot: chain_point.slot,
+        epoch: 0,
+        epoch_slot: chain_point.slot,
+        slot_leader: "hydra".to_string(),
+        size: 0,
+        tx_count: 0,
+        confirmations: 0,
+    }))
+}
+
+/// Serves the facade until cancelled
+pub(crate) async fn serve<B: Backend>(
+    config: &ListenerConfig,
+    context: Arc<Context<B>>,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/addresses/{address}/utxos", get(address_utxos::<B>))
+        .route("/txs/{hash}/utxos", get(tx_utxos::<B>))
+        .route("/tx/submit", post(submit_tx::<B>))
+        .route("/epochs/latest/parameters", get(latest_parameters::<B>))
+        .route("/blocks/latest", get(latest_block::<B>))
+        .with_state(context);
+
+    let listener = TcpListener::bind(&config.listen_address).await?;
+    info!(address = config.listen_address, "blockfrost facade running");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(cancellation_token.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
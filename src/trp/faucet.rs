@@ -0,0 +1,109 @@
+//! Faucet for development heads. Pays lovelace from a configured wallet to
+//! any address that asks, at most once per cooldown. The inputs of each
+//! payment are reserved like those of resolved txs, so they come back once
+//! the head rejects the payment or the reservation expires.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail, ensure};
+use serde::{Deserialize, Serialize};
+use tx3_cardano::PParams;
+
+use super::reservations::Reservations;
+use crate::{
+    hydra::model::{TxID, Utxo},
+    ledger::{payment::build_payment, witness},
+    wallet::Wallets,
+};
+
+fn default_cooldown_secs() -> u64 {
+    3_600
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Config {
+    /// Name of the wallet the faucet pays from
+    pub wallet: String,
+    /// Address of the faucet utxos, its payment key must be the wallet's
+    pub address: String,
+    /// Max lovelace a single request may ask for
+    pub max_amount: u64,
+    /// Seconds an address has to wait between requests
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+pub struct Faucet {
+    config: Config,
+    /// Last payment to each address
+    paid_at: Mutex<HashMap<String, Instant>>,
+}
+
+impl Faucet {
+    pub fn try_new(config: Config, wallets: &Wallets) -> anyhow::Result<Self> {
+        let wallet = wallets
+            .get(&config.wallet)
+            .with_context(|| format!("faucet wallet {} is not configured", config.wallet))?;
+
+        wallet
+            .ensure_owns(&config.address)
+            .with_context(|| format!("checking faucet address of wallet {}", config.wallet))?;
+
+        Ok(Self {
+            config,
+            paid_at: Default::default(),
+        })
+    }
+
+    /// Builds and signs a payment of `amount` to `address`. The address
+    /// cooldown starts here, so a failed submit still counts as a request.
+    pub fn pay(
+        &self,
+        address: &str,
+        amount: u64,
+        wallets: &Wallets,
+        utxos: &HashMap<TxID, Utxo>,
+        reservations: &Reservations,
+        pparams: &PParams,
+    ) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            amount <= self.config.max_amount,
+            "amount is over the {} limit",
+            self.config.max_amount
+        );
+
+        // held until the payment is reserved, so concurrent requests don't
+        // pick the same inputs
+        let mut paid_at = self.paid_at.lock().unwrap();
+
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        paid_at.retain(|_, paid_at| paid_at.elapsed() < cooldown);
+
+        if let Some(paid_at) = paid_at.get(address) {
+            let wait = cooldown.saturating_sub(paid_at.elapsed());
+            bail!("{address} has to wait {}s", wait.as_secs() + 1);
+        }
+
+        let reserved = reservations.reserved();
+        let available = utxos.iter().filter(|(id, _)| !reserved.contains(*id));
+
+        let payment = build_payment(available, &self.config.address, address, amount, pparams)?;
+
+        // checked on startup
+        let wallet = wallets.get(&self.config.wallet).unwrap();
+        let body_hash = witness::body_hash(&payment.cbor)?;
+        let signed = witness::add_vkey_witnesses(&payment.cbor, &[wallet.witness(&body_hash)])?;
+
+        ensure!(
+            reservations.try_reserve(&signed),
+            "faucet utxos are taken by another tx, retry"
+        );
+        paid_at.insert(address.to_string(), Instant::now());
+
+        Ok(signed)
+    }
+}
@@ -0,0 +1,407 @@
+//! Bounded record of the txs the server has seen, from its own submits and
+//! from the head events, so their outcome can be looked up afterwards.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+use tracing::debug;
+use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
+
+use crate::{
+    hydra::model::{Event, TxID, Utxo},
+    ledger::{self, time},
+};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TxStatus {
+    /// Sent to the head, no outcome yet
+    Submitted,
+    /// Accepted by the head (`TxValid`)
+    Valid,
+    /// Rejected locally or by the head (`TxInvalid`)
+    Invalid,
+    /// Included in a confirmed snapshot
+    Confirmed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TxRecord {
+    pub hash: String,
+    /// Hex cbor, unknown for txs only seen through `TxValid` or `TxInvalid`
+    pub cbor: Option<String>,
+    pub status: TxStatus,
+    /// Validation error of invalid txs
+    pub reason: Option<String>,
+    /// Unix millis the tx was submitted through this server
+    pub submitted_at: Option<u64>,
+    /// Unix millis the tx was first seen
+    pub seen_at: u64,
+    /// Seq of the snapshot that confirmed the tx
+    pub snapshot_seq: Option<u64>,
+    /// Addresses of the spent inputs and of the outputs
+    pub addresses: BTreeSet<String>,
+}
+
+impl TxRecord {
+    fn new(hash: &str) -> Self {
+        Self {
+            hash: hash.to_string(),
+            cbor: None,
+            status: TxStatus::Submitted,
+            reason: None,
+            submitted_at: None,
+            seen_at: time::now_millis(),
+            snapshot_seq: None,
+            addresses: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    txs: HashMap<String, TxRecord>,
+    /// Hashes in the order they were first seen, oldest first
+    order: VecDeque<String>,
+    /// Utxos of the last confirmed snapshot, to resolve the inputs of the
+    /// txs the next one confirms
+    utxos: HashMap<TxID, Utxo>,
+}
+
+impl State {
+    /// Record of the tx, added if new. The oldest ones are dropped past the
+    /// capacity.
+    fn record(&mut self, hash: &str, capacity: usize) -> &mut TxRecord {
+        if !self.txs.contains_key(hash) {
+            while self.order.len() >= capacity {
+                let Some(oldest) = self.order.pop_front() else {
+                    break;
+                };
+                self.txs.remove(&oldest);
+            }
+
+            self.order.push_back(hash.to_string());
+        }
+
+        self.txs
+            .entry(hash.to_string())
+            .or_insert_with(|| TxRecord::new(hash))
+    }
+}
+
+/// Addresses of the outputs of the tx and of the inputs found in `utxos`
+fn addresses(tx: &MultiEraTx, utxos: &HashMap<TxID, Utxo>) -> BTreeSet<String> {
+    let spent = tx
+        .consumes()
+        .iter()
+        .filter_map(|input| utxos.get(&ledger::txid(input.hash(), input.index())))
+        .map(|utxo| utxo.address.clone())
+        .collect::<Vec<_>>();
+
+    let produced = tx
+        .produces()
+        .iter()
+        .filter_map(|(_, output)| output.address().ok())
+        .filter_map(|address| address.to_bech32().ok())
+        .collect::<Vec<_>>();
+
+    spent.into_iter().chain(produced).collect()
+}
+
+pub struct History {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl History {
+    /// A zero capacity disables the history
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Default::default(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Sets the utxos the inputs of the next confirmed txs are resolved
+    /// against, for the snapshot current at startup. Later snapshots and
+    /// head openings replace them.
+    pub fn seed(&self, utxos: HashMap<TxID, Utxo>) {
+        if !self.enabled() {
+            return;
+        }
+
+        self.state.lock().unwrap().utxos = utxos;
+    }
+
+    /// Starts the record of a tx submitted through this server, its inputs
+    /// are resolved against the given snapshot
+    pub fn submitted(&self, tx: &MultiEraTx, cbor: &[u8], utxos: &HashMap<TxID, Utxo>) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let record = state.record(&tx.hash().to_string(), self.capacity);
+
+        record.cbor = Some(hex::encode(cbor));
+        record.submitted_at = Some(time::now_millis());
+        record.addresses.extend(addresses(tx, utxos));
+    }
+
+    /// Marks a tx as invalid, for txs rejected or lost before reaching the head
+    pub fn rejected(&self, hash: &str, reason: String) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let record = state.record(hash, self.capacity);
+
+        record.status = TxStatus::Invalid;
+        record.reason = Some(reason);
+    }
+
+    /// Applies a head event to the records
+    pub fn observe(&self, event: &Event) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        match event {
+            Event::TxValid { tx_id } => {
+                let record = state.record(tx_id, self.capacity);
+                if record.status == TxStatus::Submitted {
+                    record.status = TxStatus::Valid;
+                }
+            }
+            Event::TxInvalid {
+                transaction,
+                validation_error,
+            } => {
+                let record = state.record(&transaction.tx_id, self.capacity);
+                record.status = TxStatus::Invalid;
+                record.reason = Some(validation_error.reason.clone());
+            }
+            Event::SnapshotConfirmed { snapshot, seq, .. } => {
+                let mut utxos = std::mem::take(&mut state.utxos);
+
+                for confirmed in &snapshot.confirmed {
+                    let Ok(cbor) = hex::decode(&confirmed.cbor_hex) else {
+                        debug!(tx_id = confirmed.tx_id, "confirmed tx with invalid hex");
+                        continue;
+                    };
+
+                    let tx = MultiEraTx::decode(&cbor).ok();
+                    let addresses = tx
+                        .as_ref()
+                        .map(|tx| addresses(tx, &utxos))
+                        .unwrap_or_default();
+
+                    let record = state.record(&confirmed.tx_id, self.capacity);
+                    record.status = TxStatus::Confirmed;
+                    record.snapshot_seq = Some(*seq);
+                    record.cbor = Some(confirmed.cbor_hex.clone());
+                    record.addresses.extend(addresses);
+
+                    // later txs of the snapshot may spend the outputs of this one
+                    if let Some(tx) = &tx {
+                        let _ = ledger::apply_tx(&mut utxos, tx);
+                    }
+                }
+
+                state.utxos = snapshot.utxo.clone();
+            }
+            Event::HeadIsOpen { snapshot } => state.utxos = snapshot.clone(),
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<TxRecord> {
+        self.state.lock().unwrap().txs.get(hash).cloned()
+    }
+
+    /// Txs touching the address first seen within `[from, to]`, newest first
+    pub fn by_address(&self, address: &str, from: u64, to: u64, limit: usize) -> Vec<TxRecord> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .order
+            .iter()
+            .rev()
+            .filter_map(|hash| state.txs.get(hash))
+            .filter(|record| (from..=to).contains(&record.seen_at))
+            .filter(|record| record.addresses.contains(address))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hydra::model::{ConfirmedTx, Snapshot, Transaction, ValidationError},
+        ledger::{payment::build_payment, testing},
+    };
+
+    fn valid(hash: &str) -> Event {
+        Event::TxValid {
+            tx_id: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn evicts_the_oldest_txs() {
+        let history = History::new(2);
+
+        for hash in ["a", "b", "a", "c"] {
+            history.observe(&valid(hash));
+        }
+
+        assert!(history.get("a").is_none());
+        assert!(history.get("b").is_some());
+        assert!(history.get("c").is_some());
+    }
+
+    #[test]
+    fn keeps_seen_txs_in_place() {
+        let history = History::new(2);
+
+        for hash in ["a", "b", "b"] {
+            history.observe(&valid(hash));
+        }
+
+        assert!(history.get("a").is_some());
+        assert!(history.get("b").is_some());
+    }
+
+    #[test]
+    fn records_rejections() {
+        let history = History::new(10);
+
+        history.observe(&valid("a"));
+        history.observe(&Event::TxInvalid {
+            transaction: Transaction {
+                tx_id: "a".to_string(),
+            },
+            validation_error: ValidationError {
+                reason: "bad".to_string(),
+            },
+        });
+
+        let record = history.get("a").unwrap();
+        assert_eq!(record.status, TxStatus::Invalid);
+        assert_eq!(record.reason.as_deref(), Some("bad"));
+    }
+
+    #[test]
+    fn confirms_txs_and_indexes_their_addresses() -> anyhow::Result<()> {
+        let history = History::new(10);
+
+        let utxos = HashMap::from([(
+            ledger::txid("00".repeat(32), 0),
+            testing::lovelace_utxo(testing::USER, 100_000_000),
+        )]);
+        let cbor = build_payment(
+            &utxos,
+            testing::USER,
+            testing::VENDOR,
+            5_000_000,
+            &testing::pparams(),
+        )?
+        .cbor;
+        let hash = MultiEraTx::decode(&cbor)?.hash().to_string();
+
+        history.observe(&Event::SnapshotConfirmed {
+            snapshot: Snapshot {
+                utxo: utxos,
+                confirmed: vec![],
+            },
+            seq: 1,
+            timestamp: String::new(),
+        });
+        history.observe(&Event::SnapshotConfirmed {
+            snapshot: Snapshot {
+                utxo: HashMap::new(),
+                confirmed: vec![ConfirmedTx {
+                    tx_id: hash.clone(),
+                    cbor_hex: hex::encode(&cbor),
+                }],
+            },
+            seq: 2,
+            timestamp: String::new(),
+        });
+
+        let record = history.get(&hash).unwrap();
+        assert_eq!(record.status, TxStatus::Confirmed);
+        assert_eq!(record.snapshot_seq, Some(2));
+
+        for address in [testing::USER, testing::VENDOR] {
+            let txs = history.by_address(address, 0, u64::MAX, 10);
+            assert_eq!(txs.len(), 1);
+            assert_eq!(txs[0].hash, hash);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_first_snapshot_inputs_from_the_seed() -> anyhow::Result<()> {
+        let utxos = HashMap::from([(
+            ledger::txid("00".repeat(32), 0),
+            testing::lovelace_utxo(testing::USER, 100_000_000),
+        )]);
+        let cbor = build_payment(
+            &utxos,
+            testing::USER,
+            testing::VENDOR,
+            5_000_000,
+            &testing::pparams(),
+        )?
+        .cbor;
+        let hash = MultiEraTx::decode(&cbor)?.hash().to_string();
+
+        let confirmed = Event::SnapshotConfirmed {
+            snapshot: Snapshot {
+                utxo: HashMap::new(),
+                confirmed: vec![ConfirmedTx {
+                    tx_id: hash.clone(),
+                    cbor_hex: hex::encode(&cbor),
+                }],
+            },
+            seq: 1,
+            timestamp: String::new(),
+        };
+
+        let seeded = History::new(10);
+        seeded.seed(utxos.clone());
+        seeded.observe(&confirmed);
+        assert_eq!(seeded.by_address(testing::USER, 0, u64::MAX, 10).len(), 1);
+
+        let opened = History::new(10);
+        opened.observe(&Event::HeadIsOpen { snapshot: utxos });
+        opened.observe(&confirmed);
+        assert_eq!(opened.by_address(testing::USER, 0, u64::MAX, 10).len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn records_nothing_without_capacity() {
+        let history = History::new(0);
+
+        history.observe(&valid("a"));
+        assert!(history.get("a").is_none());
+    }
+}
@@ -0,0 +1,423 @@
+//! Kupo-compatible HTTP API over the head, for indexer-based services that
+//! query Kupo patterns. Matches are served from the snapshot through the
+//! [`UtxoSnapshot`](crate::hydra::UtxoSnapshot) filters. The head keeps no
+//! spent outputs, so every match is unspent.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use tx3_cardano::pallas::{crypto::hash::Hasher, ledger::addresses::Address};
+
+use crate::{
+    backend::Backend,
+    hydra::model::{AssetValue, TxID, Utxo},
+    ledger,
+    trp::{Context, ListenerConfig},
+};
+
+/// Error body in the Kupo format
+#[derive(Serialize)]
+struct Hint {
+    hint: String,
+}
+
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn bad_request(hint: impl ToString) -> Self {
+        Self(StatusCode::BAD_REQUEST, hint.to_string())
+    }
+
+    fn internal(error: anyhow::Error) -> Self {
+        error!(?error);
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{error:#}"))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(Hint { hint: self.1 })).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Kupo match patterns, credentials are hex key or script hashes
+enum Pattern {
+    /// `*`
+    Any,
+    /// Bech32 or base58 address
+    Address(Vec<u8>),
+    /// `{payment}/{delegation}`, either may be `*`
+    Credentials {
+        payment: Option<Vec<u8>>,
+        delegation: Option<Vec<u8>>,
+    },
+    /// `{policy}.*`
+    Policy(Vec<u8>),
+    /// `{policy}.{asset name}`
+    Asset(Vec<u8>, Vec<u8>),
+    /// `{output index}@{tx hash}`
+    OutputRef(TxID),
+    /// `*@{tx hash}`
+    Transaction(String),
+}
+
+fn hex_part(part: &str, len: Option<usize>) -> Result<Vec<u8>, ApiError> {
+    let bytes =
+        hex::decode(part).map_err(|_| ApiError::bad_request(format!("invalid hex {part}")))?;
+
+    if len.is_some_and(|len| bytes.len() != len) {
+        return Err(ApiError::bad_request(format!("invalid length of {part}")));
+    }
+
+    Ok(bytes)
+}
+
+fn credential(part: &str) -> Result<Option<Vec<u8>>, ApiError> {
+    match part {
+        "*" => Ok(None),
+        part => hex_part(part, Some(28)).map(Some),
+    }
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = ApiError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        if pattern == "*" || pattern == "*/*" {
+            return Ok(Self::Any);
+        }
+
+        if let Some((index, hash)) = pattern.split_once('@') {
+            hex_part(hash, Some(32))?;
+
+            return match index {
+                "*" => Ok(Self::Transaction(hash.to_string())),
+                index => {
+                    let index = index
+                        .parse::<u64>()
+                        .map_err(|_| ApiError::bad_request(format!("invalid index {index}")))?;
+                    Ok(Self::OutputRef(ledger::txid(hash, index)))
+                }
+            };
+        }
+
+        if let Some((policy, name)) = pattern.split_once('.') {
+            let policy = hex_part(policy, Some(28))?;
+
+            return match name {
+                "*" => Ok(Self::Policy(policy)),
+                name => Ok(Self::Asset(policy, hex_part(name, None)?)),
+            };
+        }
+
+        if let Some((payment, delegation)) = pattern.split_once('/') {
+            return Ok(Self::Credentials {
+                payment: credential(payment)?,
+                delegation: credential(delegation)?,
+            });
+        }
+
+        let address = pattern
+            .parse::<Address>()
+            .map_err(|_| ApiError::bad_request(format!("invalid pattern {pattern}")))?;
+
+        Ok(Self::Address(address.to_vec()))
+    }
+}
+
+#[derive(Deserialize)]
+struct MatchesQuery {
+    /// Kupo flags, given without a value
+    spent: Option<String>,
+    unspent: Option<String>,
+    policy_id: Option<String>,
+    asset_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Value {
+    coins: u64,
+    /// `policy.asset name` to quantity
+    assets: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct Point {
+    slot_no: u64,
+    header_hash: String,
+}
+
+#[derive(Serialize)]
+struct Match {
+    transaction_index: u64,
+    transaction_id: String,
+    output_index: u64,
+    address: String,
+    value: Value,
+    datum_hash: Option<String>,
+    datum_type: Option<&'static str>,
+    script_hash: Option<String>,
+    created_at: Point,
+    spent_at: Option<Point>,
+}
+
+fn value(utxo: &Utxo) -> Value {
+    let mut coins = 0;
+    let mut assets = BTreeMap::new();
+
+    for (policy, value) in &utxo.value.assets {
+        match value {
+            AssetValue::Lovelace(amount) => coins = *amount,
+            AssetValue::Multi(by_name) => {
+                for (name, amount) in by_name {
+                    let unit = match name.as_str() {
+                        "" => policy.clone(),
+                        name => format!("{policy}.{name}"),
+                    };
+                    assets.insert(unit, *amount);
+                }
+            }
+        }
+    }
+
+    Value { coins, assets }
+}
+
+fn inline_datum_hash(utxo: &Utxo) -> Option<String> {
+    let cbor = hex::decode(utxo.inline_datum_raw.as_ref()?).ok()?;
+    Some(Hasher::<256>::hash(&cbor).to_string())
+}
+
+/// Utxos created before the last snapshot are reported at its point, the
+/// head doesn't keep the point of each one
+async fn matches<B: Backend>(
+    State(context): State<Arc<Context<B>>>,
+    Path(pattern): Path<String>,
+    Query(query): Query<MatchesQuery>,
+) -> ApiResult<Vec<Match>> {
+    let pattern = pattern.parse::<Pattern>()?;
+
+    if query.spent.is_some() && query.unspent.is_some() {
+        return Err(ApiError::bad_request("spent and unspent are exclusive"));
+    }
+
+    if query.asset_name.is_some() && query.policy_id.is_none() {
+        return Err(ApiError::bad_request("asset_name needs a policy_id"));
+    }
+
+    let policy = query
+        .policy_id
+        .as_deref()
+        .map(|policy| hex_part(policy, Some(28)))
+        .transpose()?;
+    let asset_name = query
+        .asset_name
+        .as_deref()
+        .map(|name| hex_part(name, None))
+        .transpose()?;
+
+    // unsupported on purpose: the snapshot drops spent outputs and the tx
+    // history keeps tx records, not the outputs they spent
+    if query.spent.is_some() {
+        return Ok(Json(Vec::new()));
+    }
+
+    let chain_point = context
+        .backend
+        .chain_point()
+        .await
+        .map_err(ApiError::internal)?;
+
+    let snapshot = context.backend.read_utxos().await;
+
+    let mut refs = match &pattern {
+        Pattern::Any => snapshot.0.keys().cloned().collect(),
+        Pattern::Address(address) => snapshot.get_utxo_by_address(address),
+        Pattern::Credentials {
+            payment,
+            delegation,
+        } => snapshot.get_utxo_by_credentials(payment.as_deref(), delegation.as_deref()),
+        Pattern::Policy(policy) => snapshot.get_utxo_by_asset_policy(policy),
+        Pattern::Asset(policy, name) => snapshot.get_utxo_by_asset(policy, name),
+        Pattern::OutputRef(r#ref) => snapshot
+            .0
+            .contains_key(r#ref)
+            .then(|| r#ref.clone())
+            .into_iter()
+            .collect(),
+        Pattern::Transaction(hash) => snapshot
+            .0
+            .keys()
+            .filter(|r#ref| r#ref.split_once('#').is_some_and(|(tx, _)| tx == hash))
+            .cloned()
+            .collect(),
+    };
+
+    let filtered = match (&policy, &asset_name) {
+        (Some(policy), Some(name)) => Some(snapshot.get_utxo_by_asset(policy, name)),
+        (Some(policy), None) => Some(snapshot.get_utxo_by_asset_policy(policy)),
+        _ => None,
+    };
+    if let Some(filtered) = filtered {
+        refs.retain(|r#ref| filtered.contains(r#ref));
+    }
+
+    refs.sort_unstable();
+
+    let matches = refs
+        .iter()
+        .filter_map(|r#ref| Some((r#ref, snapshot.0.get(r#ref)?)))
+        .map(|(r#ref, utxo)| {
+            let (hash, index) = r#ref.split_once('#').unwrap_or((r#ref.as_str(), "0"));
+
+            let (datum_hash, datum_type) = match (&utxo.datumhash, inline_datum_hash(utxo)) {
+                (_, Some(hash)) => (Some(hash), Some("inline")),
+                (Some(hash), None) => (Some(hash.clone()), Some("hash")),
+                (None, None) => (None, None),
+            };
+
+            let script_hash = utxo
+                .reference_script
+                .as_ref()
+                .map(|script| ledger::script_hash(script).map(|hash| hash.to_string()))
+                .transpose()
+                .map_err(ApiError::internal)?;
+
+            Ok(Match {
+                transaction_index: 0,
+                transaction_id: hash.to_string(),
+                output_index: index.parse().unwrap_or_default(),
+                address: utxo.address.clone(),
+                value: value(utxo),
+                datum_hash,
+                datum_type,
+                script_hash,
+                created_at: Point {
+                    slot_no: chain_point.slot,
+                    header_hash: hex::encode(&chain_point.hash),
+                },
+                spent_at: None,
+            })
+        })
+        .collect::<Result<_, ApiError>>()?;
+
+    Ok(Json(matches))
+}
+
+#[derive(Serialize)]
+struct Datum {
+    datum: String,
+}
+
+/// Inline datums of the snapshot, null if no utxo holds one with the hash
+async fn datum<B: Backend>(
+    State(context): State<Arc<Context<B>>>,
+    Path(hash): Path<String>,
+) -> ApiResult<Option<Datum>> {
+    hex_part(&hash, Some(32))?;
+
+    let snapshot = context.backend.read_utxos().await;
+
+    let datum = snapshot
+        .0
+        .values()
+        .find(|utxo| inline_datum_hash(utxo).is_some_and(|datum_hash| datum_hash == hash))
+        .and_then(|utxo| utxo.inline_datum_raw.clone())
+        .map(|datum| Datum { datum });
+
+    Ok(Json(datum))
+}
+
+/// Serves the api until cancelled
+pub(crate) async fn serve<B: Backend>(
+    config: &ListenerConfig,
+    context: Arc<Context<B>>,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/matches/{*pattern}", get(matches::<B>))
+        .route("/datums/{hash}", get(datum::<B>))
+        .with_state(context);
+
+    let listener = TcpListener::bind(&config.listen_address).await?;
+    info!(address = config.listen_address, "kupo api running");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(cancellation_token.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing;
+
+    const HASH: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90";
+    const POLICY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn parses_wildcards() {
+        assert!(matches!("*".parse::<Pattern>(), Ok(Pattern::Any)));
+        assert!(matches!("*/*".parse::<Pattern>(), Ok(Pattern::Any)));
+    }
+
+    #[test]
+    fn parses_output_refs() {
+        let pattern = format!("3@{HASH}").parse::<Pattern>();
+        assert!(matches!(pattern, Ok(Pattern::OutputRef(r#ref)) if r#ref == format!("{HASH}#3")));
+
+        let pattern = format!("*@{HASH}").parse::<Pattern>();
+        assert!(matches!(pattern, Ok(Pattern::Transaction(hash)) if hash == HASH));
+
+        assert!(format!("x@{HASH}").parse::<Pattern>().is_err());
+        assert!("0@abcd".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn parses_assets() {
+        let pattern = format!("{POLICY}.*").parse::<Pattern>();
+        assert!(matches!(pattern, Ok(Pattern::Policy(policy)) if hex::encode(&policy) == POLICY));
+
+        let pattern = format!("{POLICY}.4d494c4b").parse::<Pattern>();
+        assert!(matches!(pattern, Ok(Pattern::Asset(_, name)) if name == b"MILK"));
+
+        assert!("abcd.*".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn parses_credentials() {
+        let pattern = format!("{POLICY}/*").parse::<Pattern>();
+        assert!(matches!(
+            pattern,
+            Ok(Pattern::Credentials {
+                payment: Some(_),
+                delegation: None
+            })
+        ));
+
+        assert!("abcd/*".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn parses_addresses() {
+        assert!(matches!(
+            testing::USER.parse::<Pattern>(),
+            Ok(Pattern::Address(_))
+        ));
+        assert!("not-an-address".parse::<Pattern>().is_err());
+    }
+}
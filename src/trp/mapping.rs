@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use anyhow::Context;
+use serde::Serialize;
 use tx3_cardano::pallas::{
     codec::{minicbor, utils::KeyValuePairs},
     ledger::{
@@ -42,16 +43,16 @@ fn map_assets(value: &hydra::model::Value) -> tx3_resolver::CanonicalAssets {
         .fold(init, |acc, x| acc + x)
 }
 
-fn map_big_int(x: &BigInt) -> Expression {
+fn big_int_value(x: &BigInt) -> i128 {
     match x {
-        BigInt::Int(x) => Expression::Number((*x).into()),
+        BigInt::Int(x) => (*x).into(),
         BigInt::BigUInt(bounded_bytes) => {
             // Convert bytes to big-endian integer
             let mut result = 0i128;
             for &byte in bounded_bytes.iter() {
                 result = (result << 8) | (byte as i128);
             }
-            Expression::Number(result)
+            result
         }
         BigInt::BigNInt(bounded_bytes) => {
             // Convert bytes to big-endian integer and negate
@@ -59,11 +60,15 @@ fn map_big_int(x: &BigInt) -> Expression {
             for &byte in bounded_bytes.iter() {
                 result = (result << 8) | (byte as i128);
             }
-            Expression::Number(-result)
+            -result
         }
     }
 }
 
+fn map_big_int(x: &BigInt) -> Expression {
+    Expression::Number(big_int_value(x))
+}
+
 fn map_constr(x: &Constr<PlutusData>) -> Expression {
     Expression::Struct(StructExpr {
         constructor: x.constructor_value().unwrap_or_default() as usize,
@@ -93,6 +98,67 @@ fn map_plutus_data(datum: &PlutusData) -> Expression {
     }
 }
 
+/// Plutus data in the detailed schema of `cardano-cli`
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DatumJson {
+    Constr {
+        constructor: u64,
+        fields: Vec<DatumJson>,
+    },
+    Map {
+        map: Vec<DatumEntry>,
+    },
+    Int {
+        int: i128,
+    },
+    Bytes {
+        bytes: String,
+    },
+    List {
+        list: Vec<DatumJson>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DatumEntry {
+    pub k: DatumJson,
+    pub v: DatumJson,
+}
+
+pub fn plutus_data_json(datum: &PlutusData) -> DatumJson {
+    match datum {
+        PlutusData::Constr(x) => DatumJson::Constr {
+            constructor: x.constructor_value().unwrap_or_default(),
+            fields: x.fields.iter().map(plutus_data_json).collect(),
+        },
+        PlutusData::Map(x) => DatumJson::Map {
+            map: x
+                .iter()
+                .map(|(k, v)| DatumEntry {
+                    k: plutus_data_json(k),
+                    v: plutus_data_json(v),
+                })
+                .collect(),
+        },
+        PlutusData::BigInt(x) => DatumJson::Int {
+            int: big_int_value(x),
+        },
+        PlutusData::BoundedBytes(x) => DatumJson::Bytes {
+            bytes: hex::encode(x.to_vec()),
+        },
+        PlutusData::Array(x) => DatumJson::List {
+            list: x.iter().map(plutus_data_json).collect(),
+        },
+    }
+}
+
+/// Decodes the raw CBOR of an inline datum
+pub fn decode_datum_json(raw: &[u8]) -> anyhow::Result<DatumJson> {
+    let datum = minicbor::decode::<PlutusData>(raw).context("decoding plutus data")?;
+    Ok(plutus_data_json(&datum))
+}
+
 fn map_datum(utxo: &Utxo) -> Result<Option<Expression>, anyhow::Error> {
     if let Some(datum) = &utxo.inline_datum_raw {
         let datum = hex::decode(datum).context("failed to decode hydra utxo hex cbor datum raw")?;
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    backend::Backend,
+    ledger::eval::{self, RedeemerBudget},
+    trp::Context,
+};
+
+use super::{resolve::eval_error, submit::TrpSubmitRequest};
+
+#[derive(Serialize)]
+pub struct TrpEvaluateResponse {
+    pub redeemers: Vec<RedeemerBudget>,
+}
+
+/// Runs the scripts of a tx against the current snapshot without submitting
+/// it. Failures carry the evaluator trace as error data.
+pub async fn execute<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "trp.evaluate", "Received TRP request.");
+
+    context.ensure_accepting()?;
+
+    let request = params.parse::<TrpSubmitRequest>().map_err(|error| {
+        error!(?error);
+        ErrorObject::owned(
+            ErrorCode::InvalidParams.code(),
+            "invalid params",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let raw = request.tx.decode("tx")?;
+
+    let pparams = context.backend.get_pparams().await.map_err(|error| {
+        error!(?error);
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to get pparams",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let slot_config = context.backend.slot_config();
+
+    let redeemers = {
+        let utxos = context.backend.read_utxos().await;
+        eval::evaluate(&raw, &utxos.0, &pparams, &slot_config).map_err(eval_error)?
+    };
+
+    serde_json::to_value(TrpEvaluateResponse { redeemers }).map_err(|error| {
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to encode response",
+            Some(error.to_string()),
+        )
+    })
+}
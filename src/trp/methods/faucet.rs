@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params};
+use serde::Deserialize;
+use tracing::{debug, error, info};
+
+use crate::{backend::Backend, trp::Context};
+
+use super::submit::submit_and_wait;
+
+#[derive(Deserialize)]
+pub struct FaucetRequest {
+    /// Bech32 address to fund
+    pub address: String,
+    /// Lovelace to send
+    pub amount: u64,
+}
+
+/// Pays lovelace from the faucet wallet to the requested address and waits
+/// for the head to accept it, like `trp.submit`
+pub async fn request<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "faucet.request", "Received TRP request.");
+
+    context.ensure_accepting()?;
+
+    let Some(faucet) = &context.faucet else {
+        return Err(ErrorObject::from(ErrorCode::MethodNotFound));
+    };
+
+    let request = params.parse::<FaucetRequest>().map_err(|error| {
+        ErrorObject::owned(
+            ErrorCode::InvalidParams.code(),
+            "invalid params",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let pparams = context.backend.get_pparams().await.map_err(|error| {
+        error!(?error);
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to get pparams",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let raw = {
+        let utxos = context.backend.read_utxos().await;
+
+        faucet
+            .pay(
+                &request.address,
+                request.amount,
+                &context.wallets,
+                &utxos.0,
+                &context.reservations,
+                &pparams,
+            )
+            .map_err(|error| {
+                debug!(?error, "faucet request refused");
+                ErrorObject::owned(
+                    ErrorCode::InvalidRequest.code(),
+                    "faucet request refused",
+                    Some(format!("{error:#}")),
+                )
+            })?
+    };
+
+    Ok(submit_and_wait(&context, raw).await?)
+}
@@ -1,9 +1,82 @@
 use std::sync::Arc;
 
-use jsonrpsee::types::ErrorObjectOwned;
+use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned};
+use serde::Serialize;
 
-use crate::trp::Context;
+use crate::{
+    backend::Backend,
+    hydra::{HealthStatus, model::HeadStatus},
+    trp::Context,
+};
 
-pub async fn execute(context: Arc<Context>) -> Result<bool, ErrorObjectOwned> {
-    Ok(context.hydra_adapter.check_health().await)
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthResponse {
+    pub live: bool,
+    pub ready: bool,
+    #[serde(flatten)]
+    pub status: HealthStatus,
+}
+
+async fn check<B: Backend>(context: &Context<B>) -> HealthResponse {
+    let live = context.backend.check_health().await;
+    let status = context.backend.health_status().await;
+
+    let snapshot_fresh = match (
+        status.snapshot_age_secs,
+        context.config.readiness_max_snapshot_age_secs,
+    ) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(age), Some(max_age)) => age <= max_age,
+    };
+
+    let ready = live
+        && !context.shutdown.is_cancelled()
+        && status.connected
+        && status.head_status == HeadStatus::Open
+        && snapshot_fresh
+        && status.pparams_cached;
+
+    HealthResponse {
+        live,
+        ready,
+        status,
+    }
+}
+
+pub async fn execute<B: Backend>(
+    context: Arc<Context<B>>,
+) -> Result<HealthResponse, ErrorObjectOwned> {
+    Ok(check(&context).await)
+}
+
+/// Backs the `GET /healthz` probe, fails when the hydra connection is gone
+pub async fn live<B: Backend>(context: Arc<Context<B>>) -> Result<bool, ErrorObjectOwned> {
+    if !context.backend.check_health().await {
+        return Err(ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "hydra connection is down",
+            None::<String>,
+        ));
+    }
+
+    Ok(true)
+}
+
+/// Backs the `GET /readyz` probe, fails until the head can serve requests
+pub async fn ready<B: Backend>(
+    context: Arc<Context<B>>,
+) -> Result<HealthResponse, ErrorObjectOwned> {
+    let response = check(&context).await;
+
+    if !response.ready {
+        return Err(ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "not ready",
+            Some(response),
+        ));
+    }
+
+    Ok(response)
 }
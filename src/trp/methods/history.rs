@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use jsonrpsee::types::{ErrorObjectOwned, Params};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{page_size, parse_params};
+use crate::{
+    backend::Backend,
+    trp::{Context, history::TxRecord},
+};
+
+#[derive(Deserialize)]
+pub struct GetTxRequest {
+    /// Hex tx hash
+    pub hash: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GetTxResponse {
+    /// Null if the tx wasn't seen or was dropped from the history
+    pub tx: Option<TxRecord>,
+}
+
+pub async fn get_tx<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<GetTxResponse, ErrorObjectOwned> {
+    info!(method = "trp.getTx", "Received TRP request.");
+
+    let request = parse_params::<GetTxRequest>(&params)?;
+
+    Ok(GetTxResponse {
+        tx: context.history.get(&request.hash.to_lowercase()),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ListTxsRequest {
+    /// Bech32 address spent from or paid to
+    pub address: String,
+    /// Unix millis, txs first seen before it are left out
+    #[serde(default)]
+    pub from: Option<u64>,
+    /// Unix millis, txs first seen after it are left out
+    #[serde(default)]
+    pub to: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ListTxsResponse {
+    /// Newest first
+    pub txs: Vec<TxRecord>,
+}
+
+pub async fn list_txs<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<ListTxsResponse, ErrorObjectOwned> {
+    info!(method = "trp.listTxs", "Received TRP request.");
+
+    let request = parse_params::<ListTxsRequest>(&params)?;
+
+    let limit = page_size(request.limit)?;
+
+    let txs = context.history.by_address(
+        &request.address,
+        request.from.unwrap_or(0),
+        request.to.unwrap_or(u64::MAX),
+        limit,
+    );
+
+    Ok(ListTxsResponse { txs })
+}
@@ -1,9 +1,44 @@
-use serde::Deserialize;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params};
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::error;
 
+pub mod evaluate;
+pub mod faucet;
 pub mod health;
+pub mod history;
+pub mod ogmios;
+pub mod query;
 pub mod resolve;
 pub mod submit;
 
+/// Page size of the paginated methods when the request sets no limit
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// Largest page the paginated methods serve
+pub const MAX_PAGE_SIZE: usize = 1_000;
+
+pub fn invalid_params(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        ErrorCode::InvalidParams.code(),
+        "invalid params",
+        Some(error.to_string()),
+    )
+}
+
+pub fn parse_params<T: DeserializeOwned>(params: &Params<'_>) -> Result<T, ErrorObjectOwned> {
+    params.parse::<T>().map_err(invalid_params)
+}
+
+/// Page size for the requested limit, [`DEFAULT_PAGE_SIZE`] if unset
+pub fn page_size(limit: Option<usize>) -> Result<usize, ErrorObjectOwned> {
+    match limit.unwrap_or(DEFAULT_PAGE_SIZE) {
+        limit @ 1..=MAX_PAGE_SIZE => Ok(limit),
+        _ => Err(invalid_params(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        ))),
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub enum Encoding {
     #[serde(rename = "hex")]
@@ -11,3 +46,37 @@ pub enum Encoding {
     #[serde(rename = "base64")]
     Base64,
 }
+
+/// Binary value as sent over TRP
+#[derive(Deserialize)]
+pub struct BytesEnvelope {
+    pub encoding: Encoding,
+    pub payload: String,
+}
+
+impl BytesEnvelope {
+    /// Decodes the payload, `what` names it in the error message
+    pub fn decode(&self, what: &str) -> Result<Vec<u8>, ErrorObjectOwned> {
+        let (encoding, decoded) = match self.encoding {
+            Encoding::Hex => (
+                "hex",
+                hex::decode(&self.payload).map_err(|error| error.to_string()),
+            ),
+            Encoding::Base64 => (
+                "base64",
+                BASE64_STANDARD
+                    .decode(&self.payload)
+                    .map_err(|error| error.to_string()),
+            ),
+        };
+
+        decoded.map_err(|error| {
+            error!(?error);
+            ErrorObject::owned(
+                ErrorCode::ParseError.code(),
+                format!("invalid {what} {encoding} encoding"),
+                Some(error),
+            )
+        })
+    }
+}
@@ -0,0 +1,471 @@
+//! Ogmios-compatible methods served from head state, for tooling built on
+//! the Ogmios v6 JSON-RPC. Shapes follow Ogmios, errors carry its codes.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use super::{invalid_params, parse_params};
+use crate::{
+    backend::Backend,
+    hydra::model::{AssetValue, Ratio, TxID, Utxo},
+    ledger::{
+        Violation,
+        eval::{self, EvalError},
+    },
+    trp::Context,
+};
+
+use super::submit::{SubmitError, submit_and_wait};
+
+/// Hydra ledger failure names and the Ogmios submit error they map to
+const SUBMIT_FAILURES: &[(&str, i32, &str)] = &[
+    ("InvalidWitnessesUTXOW", 3100, "invalid signatories"),
+    ("MissingVKeyWitnessesUTXOW", 3101, "missing signatories"),
+    ("MissingScriptWitnessesUTXOW", 3102, "missing scripts"),
+    (
+        "ScriptWitnessNotValidatingUTXOW",
+        3103,
+        "failing native script",
+    ),
+    ("ExtraneousScriptWitnessesUTXOW", 3104, "extraneous scripts"),
+    ("MissingRedeemers", 3109, "missing redeemers"),
+    ("ExtraRedeemers", 3110, "extraneous redeemers"),
+    ("MissingRequiredDatums", 3111, "missing datums"),
+    ("NotAllowedSupplementalDatums", 3112, "extraneous datums"),
+    (
+        "PPViewHashesDontMatch",
+        3113,
+        "script integrity hash mismatch",
+    ),
+    ("UnspendableUTxONoDatumHash", 3114, "orphan script inputs"),
+    ("BadInputsUTxO", 3117, "unknown output references"),
+    (
+        "OutsideValidityIntervalUTxO",
+        3118,
+        "outside of validity interval",
+    ),
+    ("MaxTxSizeUTxO", 3119, "transaction too large"),
+    ("OutputTooBigUTxO", 3120, "value too large"),
+    ("InputSetEmptyUTxO", 3121, "empty input set"),
+    ("FeeTooSmallUTxO", 3122, "transaction fee too small"),
+    ("ValueNotConservedUTxO", 3123, "value not conserved"),
+    ("WrongNetwork", 3124, "network mismatch"),
+    ("OutputTooSmallUTxO", 3125, "insufficiently funded outputs"),
+    ("InsufficientCollateral", 3128, "insufficient collateral"),
+    ("ScriptsNotPaidUTxO", 3129, "collateral locked by script"),
+    (
+        "TooManyCollateralInputs",
+        3131,
+        "too many collateral inputs",
+    ),
+    ("NoCollateralInputs", 3132, "missing collateral inputs"),
+    ("CollateralContainsNonADA", 3133, "non-ada collateral"),
+    ("ExUnitsTooBigUTxO", 3134, "execution units too large"),
+    (
+        "IncorrectTotalCollateralField",
+        3135,
+        "total collateral mismatch",
+    ),
+    ("ValidationTagMismatch", 3010, "some scripts failed"),
+];
+
+const UNKNOWN_FAILURE: (i32, &str) = (3000, "transaction rejected");
+const SCRIPT_FAILURE: (i32, &str) = (3010, "some scripts failed");
+
+fn internal_error(message: &str, error: anyhow::Error) -> ErrorObjectOwned {
+    error!(?error);
+    ErrorObject::owned(
+        ErrorCode::InternalError.code(),
+        message,
+        Some(format!("{error:#}")),
+    )
+}
+
+#[derive(Deserialize, Serialize)]
+struct TransactionId {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct OutputReference {
+    transaction: TransactionId,
+    index: u64,
+}
+
+#[derive(Serialize)]
+struct Script {
+    language: &'static str,
+    cbor: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OgmiosUtxo {
+    transaction: TransactionId,
+    index: u64,
+    address: String,
+    value: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    datum_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    datum: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script: Option<Script>,
+}
+
+impl OgmiosUtxo {
+    fn new(r#ref: &TxID, utxo: &Utxo) -> Self {
+        let (id, index) = r#ref.split_once('#').unwrap_or((r#ref.as_str(), "0"));
+
+        let value = utxo
+            .value
+            .assets
+            .iter()
+            .map(|(policy, value)| match value {
+                AssetValue::Lovelace(amount) => (
+                    "ada".to_string(),
+                    BTreeMap::from([("lovelace".to_string(), *amount)]),
+                ),
+                AssetValue::Multi(by_name) => (
+                    policy.clone(),
+                    by_name
+                        .iter()
+                        .map(|(name, amount)| (name.clone(), *amount))
+                        .collect(),
+                ),
+            })
+            .collect();
+
+        let script = utxo.reference_script.as_ref().map(|script| Script {
+            language: match script.r#type.as_str() {
+                "PlutusScriptV1" => "plutus:v1",
+                "PlutusScriptV2" => "plutus:v2",
+                "PlutusScriptV3" => "plutus:v3",
+                _ => "native",
+            },
+            cbor: script.cbor_hex.clone(),
+        });
+
+        Self {
+            transaction: TransactionId { id: id.to_string() },
+            index: index.parse().unwrap_or_default(),
+            address: utxo.address.clone(),
+            value,
+            datum_hash: utxo.datumhash.clone(),
+            datum: utxo.inline_datum_raw.clone(),
+            script,
+        }
+    }
+}
+
+/// Any of the filters, or none for the whole utxo set
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct QueryUtxoRequest {
+    #[serde(default)]
+    addresses: Option<Vec<String>>,
+    #[serde(default)]
+    output_references: Option<Vec<OutputReference>>,
+}
+
+pub async fn query_utxo<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "queryLedgerState/utxo", "Received Ogmios request.");
+
+    let request = if params.is_object() {
+        parse_params::<QueryUtxoRequest>(&params)?
+    } else {
+        QueryUtxoRequest::default()
+    };
+
+    let refs = request.output_references.map(|refs| {
+        refs.into_iter()
+            .map(|r#ref| format!("{}#{}", r#ref.transaction.id, r#ref.index))
+            .collect::<Vec<_>>()
+    });
+
+    let snapshot = context.backend.read_utxos().await;
+
+    let utxos: Vec<_> = snapshot
+        .0
+        .iter()
+        .filter(|(r#ref, _)| refs.as_ref().is_none_or(|refs| refs.contains(*r#ref)))
+        .filter(|(_, utxo)| {
+            request
+                .addresses
+                .as_ref()
+                .is_none_or(|addresses| addresses.contains(&utxo.address))
+        })
+        .map(|(r#ref, utxo)| OgmiosUtxo::new(r#ref, utxo))
+        .collect();
+
+    Ok(json!(utxos))
+}
+
+fn lovelace(amount: u64) -> serde_json::Value {
+    json!({ "ada": { "lovelace": amount } })
+}
+
+fn ratio(ratio: Ratio) -> String {
+    format!("{}/{}", ratio.numerator, ratio.denominator)
+}
+
+pub async fn protocol_parameters<B: Backend>(
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(
+        method = "queryLedgerState/protocolParameters",
+        "Received Ogmios request."
+    );
+
+    let pparams = context
+        .backend
+        .get_pparams()
+        .await
+        .map_err(|error| internal_error("failed to get pparams", error))?;
+    let head = context
+        .backend
+        .protocol_parameters()
+        .await
+        .map_err(|error| internal_error("failed to get pparams", error))?;
+
+    let cost_models: BTreeMap<_, _> = pparams
+        .cost_models
+        .iter()
+        .map(|(version, model)| (format!("plutus:v{}", version + 1), model.clone()))
+        .collect();
+
+    Ok(json!({
+        "minFeeCoefficient": pparams.min_fee_coefficient,
+        "minFeeConstant": lovelace(pparams.min_fee_constant),
+        "minFeeReferenceScripts": {
+            "range": 25_600,
+            "base": head.min_fee_ref_script_cost_per_byte.to_f64(),
+            "multiplier": 1.2,
+        },
+        "minUtxoDepositCoefficient": pparams.coins_per_utxo_byte,
+        "minUtxoDepositConstant": lovelace(0),
+        "maxTransactionSize": { "bytes": head.max_tx_size },
+        "maxValueSize": { "bytes": head.max_value_size },
+        "stakeCredentialDeposit": lovelace(head.stake_address_deposit),
+        "stakePoolDeposit": lovelace(head.stake_pool_deposit),
+        "scriptExecutionPrices": {
+            "memory": ratio(head.execution_unit_prices.price_memory),
+            "cpu": ratio(head.execution_unit_prices.price_steps),
+        },
+        "maxExecutionUnitsPerTransaction": {
+            "memory": head.max_tx_execution_units.memory,
+            "cpu": head.max_tx_execution_units.steps,
+        },
+        "collateralPercentage": head.collateral_percentage,
+        "maxCollateralInputs": head.max_collateral_inputs,
+        "plutusCostModels": cost_models,
+    }))
+}
+
+/// The last snapshot, as a chain tip
+pub async fn tip<B: Backend>(
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "queryNetwork/tip", "Received Ogmios request.");
+
+    let chain_point = context
+        .backend
+        .chain_point()
+        .await
+        .map_err(|error| internal_error("failed to get chain point", error))?;
+
+    Ok(json!({
+        "slot": chain_point.slot,
+        "id": hex::encode(&chain_point.hash),
+    }))
+}
+
+#[derive(Deserialize)]
+struct Transaction {
+    cbor: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionRequest {
+    transaction: Transaction,
+    /// Only accepted empty, the head snapshot is the whole utxo set
+    #[serde(default)]
+    additional_utxo: Vec<serde_json::Value>,
+}
+
+fn parse_transaction(params: &Params<'_>) -> Result<Vec<u8>, ErrorObjectOwned> {
+    let request = parse_params::<TransactionRequest>(params)?;
+
+    if !request.additional_utxo.is_empty() {
+        return Err(invalid_params("additionalUtxo is not supported"));
+    }
+
+    hex::decode(&request.transaction.cbor).map_err(|error| {
+        ErrorObject::owned(
+            ErrorCode::ParseError.code(),
+            "invalid transaction hex encoding",
+            Some(error.to_string()),
+        )
+    })
+}
+
+/// Ogmios code of a rejection by the head, from the name of the ledger
+/// failure in its reason
+fn rejection_failure(reason: &str) -> (i32, &'static str) {
+    SUBMIT_FAILURES
+        .iter()
+        .find(|(name, ..)| reason.contains(name))
+        .map(|(_, code, message)| (*code, *message))
+        .unwrap_or(UNKNOWN_FAILURE)
+}
+
+/// Ogmios code of a local validation failure, from its first violation
+fn violation_failure(violation: Option<&Violation>) -> (i32, &'static str) {
+    match violation {
+        Some(Violation::UnsupportedEra { .. }) => (3005, "era mismatch"),
+        Some(Violation::MissingInput { .. } | Violation::MissingReferenceInput { .. }) => {
+            (3117, "unknown output references")
+        }
+        Some(Violation::ValueNotConserved { .. }) => (3123, "value not conserved"),
+        Some(Violation::FeeTooSmall { .. }) => (3122, "transaction fee too small"),
+        Some(Violation::OutputTooSmall { .. }) => (3125, "insufficiently funded outputs"),
+        Some(Violation::MissingWitness { .. }) => (3101, "missing signatories"),
+        None => UNKNOWN_FAILURE,
+    }
+}
+
+/// Maps the ledger rejections of the submit path to Ogmios codes, other
+/// errors are returned as they are
+fn submit_error(error: SubmitError) -> ErrorObjectOwned {
+    match error {
+        SubmitError::Rejected(reason) => {
+            let (code, message) = rejection_failure(&reason);
+            ErrorObject::owned(code, message, Some(reason))
+        }
+        SubmitError::Violations(violations) => {
+            let (code, message) = violation_failure(violations.first());
+            ErrorObject::owned(code, message, Some(violations))
+        }
+        SubmitError::Rpc(error) => error,
+    }
+}
+
+pub async fn submit_transaction<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "submitTransaction", "Received Ogmios request.");
+
+    context.ensure_accepting()?;
+
+    let raw = parse_transaction(&params)?;
+    let response = submit_and_wait(&context, raw).await.map_err(submit_error)?;
+
+    Ok(json!({ "transaction": { "id": response["hash"] } }))
+}
+
+/// Ogmios names of the redeemer purposes
+fn purpose(tag: &str) -> &str {
+    match tag {
+        "cert" => "publish",
+        "reward" => "withdraw",
+        other => other,
+    }
+}
+
+pub async fn evaluate_transaction<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "evaluateTransaction", "Received Ogmios request.");
+
+    context.ensure_accepting()?;
+
+    let raw = parse_transaction(&params)?;
+
+    let pparams = context
+        .backend
+        .get_pparams()
+        .await
+        .map_err(|error| internal_error("failed to get pparams", error))?;
+
+    let slot_config = context.backend.slot_config();
+
+    let redeemers = {
+        let utxos = context.backend.read_utxos().await;
+        eval::evaluate(&raw, &utxos.0, &pparams, &slot_config)
+    };
+
+    let redeemers = redeemers.map_err(|error| match error {
+        EvalError::Script(trace) => {
+            ErrorObject::owned(SCRIPT_FAILURE.0, SCRIPT_FAILURE.1, Some(trace))
+        }
+        EvalError::Setup(error) => internal_error("failed to evaluate scripts", error),
+    })?;
+
+    let budgets: Vec<_> = redeemers
+        .iter()
+        .map(|redeemer| {
+            json!({
+                "validator": { "purpose": purpose(redeemer.tag), "index": redeemer.index },
+                "budget": { "memory": redeemer.memory, "cpu": redeemer.steps },
+            })
+        })
+        .collect();
+
+    Ok(json!(budgets))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context as _;
+
+    use super::*;
+
+    #[test]
+    fn maps_head_rejections_by_ledger_failure() {
+        let error = submit_error(SubmitError::Rejected(
+            "ApplyTxError [UtxowFailure (MissingVKeyWitnessesUTXOW ...)]".to_string(),
+        ));
+        assert_eq!(error.code(), 3101);
+        assert_eq!(error.message(), "missing signatories");
+
+        let error = submit_error(SubmitError::Rejected("something new".to_string()));
+        assert_eq!(error.code(), UNKNOWN_FAILURE.0);
+    }
+
+    #[test]
+    fn maps_violations_by_their_first_kind() -> anyhow::Result<()> {
+        let error = submit_error(SubmitError::Violations(vec![
+            Violation::FeeTooSmall {
+                required: 2,
+                provided: 1,
+            },
+            Violation::MissingWitness {
+                key_hash: "00".repeat(28),
+            },
+        ]));
+        assert_eq!(error.code(), 3122);
+
+        let data = error.data().context("violations in the error data")?;
+        let data: serde_json::Value = serde_json::from_str(data.get())?;
+        assert_eq!(data[0]["kind"], "feeTooSmall");
+        assert_eq!(data[1]["kind"], "missingWitness");
+
+        Ok(())
+    }
+
+    #[test]
+    fn passes_other_errors_through() {
+        let error = submit_error(SubmitError::Rpc(invalid_params("bad tx")));
+        assert_eq!(error.code(), ErrorCode::InvalidParams.code());
+    }
+}
@@ -0,0 +1,447 @@
+use std::{collections::HashSet, sync::Arc};
+
+use jsonrpsee::types::{ErrorObjectOwned, Params};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use tx3_cardano::pallas::{crypto::hash::Hasher, ledger::addresses::Address};
+
+use super::{invalid_params, page_size, parse_params};
+use crate::{
+    backend::Backend,
+    hydra::{
+        UtxoSnapshot,
+        model::{TxID, Utxo},
+    },
+    trp::{
+        Context,
+        mapping::{DatumJson, decode_datum_json},
+        report::ValueReport,
+    },
+};
+
+/// Utxo of the head, in the schema shared by the query methods
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UtxoView {
+    /// `tx hash#index`
+    pub r#ref: TxID,
+    pub tx_hash: String,
+    pub index: u64,
+    pub address: String,
+    #[serde(flatten)]
+    pub value: ValueReport,
+    pub datum: Option<DatumView>,
+    pub reference_script: Option<ScriptView>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DatumView {
+    Hash {
+        hash: String,
+    },
+    /// `json` is null if the cbor isn't valid plutus data
+    Inline {
+        hash: String,
+        cbor: String,
+        json: Option<DatumJson>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScriptView {
+    /// SimpleScript, PlutusScriptV1, PlutusScriptV2 or PlutusScriptV3
+    pub r#type: String,
+    pub cbor: String,
+}
+
+impl UtxoView {
+    pub fn new(r#ref: &TxID, utxo: &Utxo) -> Self {
+        let (tx_hash, index) = r#ref.split_once('#').unwrap_or((r#ref.as_str(), "0"));
+
+        let datum = match (&utxo.inline_datum_raw, &utxo.datumhash) {
+            (Some(raw), _) => {
+                let cbor = hex::decode(raw).unwrap_or_default();
+                Some(DatumView::Inline {
+                    hash: Hasher::<256>::hash(&cbor).to_string(),
+                    cbor: raw.clone(),
+                    json: decode_datum_json(&cbor)
+                        .inspect_err(|error| {
+                            debug!(?error, utxo = r#ref, "undecodable inline datum")
+                        })
+                        .ok(),
+                })
+            }
+            (None, Some(hash)) => Some(DatumView::Hash { hash: hash.clone() }),
+            (None, None) => None,
+        };
+
+        Self {
+            r#ref: r#ref.clone(),
+            tx_hash: tx_hash.to_string(),
+            index: index.parse().unwrap_or_default(),
+            address: utxo.address.clone(),
+            value: ValueReport::from_utxo(utxo),
+            datum,
+            reference_script: utxo.reference_script.as_ref().map(|script| ScriptView {
+                r#type: script.r#type.clone(),
+                cbor: script.cbor_hex.clone(),
+            }),
+        }
+    }
+}
+
+/// Filters are combined, a utxo has to match all of the given ones
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryUtxosRequest {
+    /// Bech32 address the utxos are locked at
+    pub address: Option<String>,
+    /// Hex key or script hash of the payment part of the address
+    pub payment_credential: Option<String>,
+    /// Hex policy id of an asset the utxos hold
+    pub policy: Option<String>,
+    /// Hex policy id followed by the hex asset name of an asset the utxos hold
+    pub asset: Option<String>,
+    /// Only these `tx hash#index` refs
+    pub refs: Option<Vec<TxID>>,
+    /// `ref` of the last utxo of the previous page
+    pub cursor: Option<TxID>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryUtxosResponse {
+    pub utxos: Vec<UtxoView>,
+    /// Cursor of the next page, absent on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<TxID>,
+}
+
+/// Hex chars of a policy id, 28 bytes
+const POLICY_ID_HEX_LEN: usize = 56;
+
+impl QueryUtxosRequest {
+    fn validate(&self) -> Result<(), ErrorObjectOwned> {
+        let malformed =
+            |asset: &String| asset.len() < POLICY_ID_HEX_LEN || hex::decode(asset).is_err();
+
+        if self.asset.as_ref().is_some_and(malformed) {
+            return Err(invalid_params(
+                "asset must be a hex policy id followed by the hex asset name",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Refs of the utxos matching all the filters, each one served by the
+    /// snapshot filters
+    fn matching_refs(&self, snapshot: &UtxoSnapshot) -> HashSet<TxID> {
+        let mut filters = vec![];
+
+        if let Some(address) = &self.address {
+            filters.push(match Address::from_bech32(address) {
+                Ok(address) => snapshot.get_utxo_by_address(&address.to_vec()),
+                Err(_) => vec![],
+            });
+        }
+
+        if let Some(credential) = &self.payment_credential {
+            filters.push(match hex::decode(credential) {
+                Ok(credential) => snapshot.get_utxo_by_credentials(Some(&credential), None),
+                Err(_) => vec![],
+            });
+        }
+
+        if let Some(policy) = &self.policy {
+            filters.push(match hex::decode(policy) {
+                Ok(policy) => snapshot.get_utxo_by_asset_policy(&policy),
+                Err(_) => vec![],
+            });
+        }
+
+        if let Some(asset) = &self.asset {
+            // checked by validate
+            let (policy, name) = asset.split_at(POLICY_ID_HEX_LEN);
+            let policy = hex::decode(policy).unwrap_or_default();
+            let name = hex::decode(name).unwrap_or_default();
+            filters.push(snapshot.get_utxo_by_asset(&policy, &name));
+        }
+
+        let mut refs: HashSet<TxID> = match &self.refs {
+            Some(refs) => refs
+                .iter()
+                .filter(|r#ref| snapshot.0.contains_key(*r#ref))
+                .cloned()
+                .collect(),
+            None => snapshot.0.keys().cloned().collect(),
+        };
+
+        for filter in filters {
+            let filter: HashSet<_> = filter.into_iter().collect();
+            refs.retain(|r#ref| filter.contains(r#ref));
+        }
+
+        refs
+    }
+
+    /// Page of the matching utxos after the cursor, ordered by ref
+    fn page(&self, snapshot: &UtxoSnapshot) -> Result<QueryUtxosResponse, ErrorObjectOwned> {
+        self.validate()?;
+        let limit = page_size(self.limit)?;
+
+        let mut matching: Vec<_> = self
+            .matching_refs(snapshot)
+            .into_iter()
+            .filter(|r#ref| self.cursor.as_ref().is_none_or(|cursor| r#ref > cursor))
+            .collect();
+
+        // refs are unique, so ordering by them gives stable pages
+        matching.sort_unstable();
+
+        let next_cursor = (matching.len() > limit).then(|| matching[limit - 1].clone());
+
+        let utxos = matching
+            .iter()
+            .take(limit)
+            .filter_map(|r#ref| Some(UtxoView::new(r#ref, snapshot.0.get(r#ref)?)))
+            .collect();
+
+        Ok(QueryUtxosResponse { utxos, next_cursor })
+    }
+}
+
+pub async fn query_utxos<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<QueryUtxosResponse, ErrorObjectOwned> {
+    info!(method = "trp.queryUtxos", "Received TRP request.");
+
+    let request = if params.is_object() {
+        parse_params::<QueryUtxosRequest>(&params)?
+    } else {
+        QueryUtxosRequest::default()
+    };
+
+    let snapshot = context.backend.read_utxos().await;
+    request.page(&snapshot)
+}
+
+#[derive(Deserialize)]
+pub struct GetUtxoRequest {
+    /// `tx hash#index`
+    pub r#ref: TxID,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GetUtxoResponse {
+    /// Null if the utxo isn't in the snapshot
+    pub utxo: Option<UtxoView>,
+}
+
+pub async fn get_utxo<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<GetUtxoResponse, ErrorObjectOwned> {
+    info!(method = "trp.getUtxo", "Received TRP request.");
+
+    let request = parse_params::<GetUtxoRequest>(&params)?;
+
+    let snapshot = context.backend.read_utxos().await;
+    let utxo = snapshot
+        .0
+        .get(&request.r#ref)
+        .map(|utxo| UtxoView::new(&request.r#ref, utxo));
+
+    Ok(GetUtxoResponse { utxo })
+}
+
+#[derive(Deserialize)]
+pub struct GetBalanceRequest {
+    /// Bech32 address
+    pub address: String,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GetBalanceResponse {
+    pub address: String,
+    #[serde(flatten)]
+    pub value: ValueReport,
+    /// Number of utxos at the address
+    pub utxo_count: usize,
+}
+
+pub async fn get_balance<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+) -> Result<GetBalanceResponse, ErrorObjectOwned> {
+    info!(method = "trp.getBalance", "Received TRP request.");
+
+    let request = parse_params::<GetBalanceRequest>(&params)?;
+
+    let snapshot = context.backend.read_utxos().await;
+
+    let mut value = ValueReport::default();
+    let mut utxo_count = 0;
+
+    for utxo in snapshot
+        .0
+        .values()
+        .filter(|utxo| utxo.address == request.address)
+    {
+        let utxo_value = ValueReport::from_utxo(utxo);
+
+        value.lovelace += utxo_value.lovelace;
+        for (policy, assets) in utxo_value.assets {
+            let policy = value.assets.entry(policy).or_default();
+            for (name, amount) in assets {
+                *policy.entry(name).or_default() += amount;
+            }
+        }
+
+        utxo_count += 1;
+    }
+
+    Ok(GetBalanceResponse {
+        address: request.address,
+        value,
+        utxo_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::{
+        ledger::{testing, txid},
+        trp::methods::MAX_PAGE_SIZE,
+    };
+
+    /// Utxos `#0..#4` at the user and `#5` at the vendor
+    fn utxos() -> HashMap<TxID, Utxo> {
+        (0..6)
+            .map(|index| {
+                let address = if index < 5 {
+                    testing::USER
+                } else {
+                    testing::VENDOR
+                };
+                (
+                    txid("00".repeat(32), index),
+                    testing::lovelace_utxo(address, 2_000_000),
+                )
+            })
+            .collect()
+    }
+
+    fn page(request: &QueryUtxosRequest) -> Result<QueryUtxosResponse, ErrorObjectOwned> {
+        let utxos = RwLock::new(utxos());
+        request.page(&UtxoSnapshot(utxos.try_read().unwrap()))
+    }
+
+    fn refs(response: &QueryUtxosResponse) -> Vec<&str> {
+        response
+            .utxos
+            .iter()
+            .map(|utxo| &utxo.r#ref[65..])
+            .collect()
+    }
+
+    #[test]
+    fn pages_through_the_matches_with_cursors() -> anyhow::Result<()> {
+        let mut request = QueryUtxosRequest {
+            address: Some(testing::USER.to_string()),
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let mut pages = vec![];
+        loop {
+            let page = page(&request)?;
+            pages.push(refs(&page).join(","));
+
+            match page.next_cursor {
+                Some(cursor) => request.cursor = Some(cursor),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages, ["0,1", "2,3", "4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ends_on_an_exact_last_page() -> anyhow::Result<()> {
+        let request = QueryUtxosRequest {
+            limit: Some(6),
+            ..Default::default()
+        };
+
+        let page = page(&request)?;
+        assert_eq!(page.utxos.len(), 6);
+        assert!(page.next_cursor.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn combines_the_filters() -> anyhow::Result<()> {
+        let request = QueryUtxosRequest {
+            address: Some(testing::USER.to_string()),
+            refs: Some(vec![txid("00".repeat(32), 1), txid("00".repeat(32), 5)]),
+            ..Default::default()
+        };
+
+        assert_eq!(refs(&page(&request)?), ["1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_by_payment_credential() -> anyhow::Result<()> {
+        let Address::Shelley(vendor) = Address::from_bech32(testing::VENDOR)? else {
+            anyhow::bail!("vendor isn't a shelley address");
+        };
+
+        let request = QueryUtxosRequest {
+            payment_credential: Some(vendor.payment().as_hash().to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(refs(&page(&request)?), ["5"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_out_of_range_limits() {
+        for limit in [0, MAX_PAGE_SIZE + 1] {
+            let request = QueryUtxosRequest {
+                limit: Some(limit),
+                ..Default::default()
+            };
+
+            assert!(page(&request).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_assets() {
+        for asset in ["abcd", &"zz".repeat(30)] {
+            let request = QueryUtxosRequest {
+                asset: Some(asset.to_string()),
+                ..Default::default()
+            };
+
+            assert!(page(&request).is_err());
+        }
+    }
+}
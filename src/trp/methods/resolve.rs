@@ -1,81 +1,404 @@
-use chrono::DateTime;
-use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params};
-use std::sync::Arc;
+use jsonrpsee::{
+    Extensions,
+    types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params},
+};
+use serde::Deserialize;
+use std::{collections::HashSet, sync::Arc};
 use tracing::info;
-use tx3_cardano::ChainPoint;
+use tx3_cardano::{ChainPoint, PParams};
 use tx3_resolver::trp;
+use tx3_tir::{
+    compile::{self, CompiledTx, Compiler},
+    encoding::AnyTir,
+    reduce,
+};
 
-use crate::trp::Context;
+use crate::{
+    backend::Backend,
+    hydra::{
+        UtxoSnapshot,
+        model::{ExUnitPrices, TxID},
+    },
+    ledger::{eval, time::SlotConfig},
+    trp::{Context, auth, report::TxReport, utxos::UnreservedUtxos},
+};
 
-pub async fn execute(
+/// Resolves retried when their inputs were reserved in the meantime
+const RESERVATION_ATTEMPTS: usize = 3;
+
+pub async fn execute<B: Backend>(
     params: Params<'_>,
-    context: Arc<Context>,
+    context: Arc<Context<B>>,
+    extensions: Extensions,
 ) -> Result<serde_json::Value, ErrorObjectOwned> {
     info!(method = "trp.resolve", "Received TRP request.");
 
-    let request: trp::ResolveParams = params.parse()?;
-    let (tx, args) = trp::parse_resolve_request(request).map_err(|x| {
+    let (resolved, _) = resolve_in_context(params, &context, &extensions, false).await?;
+
+    Ok(serde_json::json!({ "tx": hex::encode(resolved.payload) }))
+}
+
+/// Same as `trp.resolve`, with a decoded report of the resolved tx next to it
+pub async fn dry_run<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+    extensions: Extensions,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "trp.dryRun", "Received TRP request.");
+
+    let (resolved, report) = resolve_in_context(params, &context, &extensions, true).await?;
+
+    Ok(serde_json::json!({
+        "tx": hex::encode(resolved.payload),
+        "report": report,
+    }))
+}
+
+/// Options from the server config, with the request overrides applied if
+/// the caller is trusted
+fn resolve_options<B: Backend>(
+    params: &Params<'_>,
+    context: &Context<B>,
+    extensions: &Extensions,
+) -> Result<ResolveOptions, ErrorObjectOwned> {
+    let config = &context.config;
+
+    let mut options = ResolveOptions {
+        extra_fees: config.extra_fees,
+        max_optimize_rounds: config.max_optimize_rounds,
+        evaluation: config
+            .evaluate_scripts
+            .then(|| context.backend.slot_config()),
+    };
+
+    let overrides = params
+        .parse::<ResolveExtras>()
+        .ok()
+        .and_then(|extras| extras.options);
+
+    let Some(overrides) = overrides else {
+        return Ok(options);
+    };
+
+    if !context.is_trusted(extensions) {
+        return Err(auth::unauthorized(
+            "resolve options require a trusted api key",
+        ));
+    }
+
+    if let Some(extra_fees) = overrides.extra_fees {
+        options.extra_fees = extra_fees;
+    }
+
+    if let Some(max_optimize_rounds) = overrides.max_optimize_rounds {
+        options.max_optimize_rounds = max_optimize_rounds;
+    }
+
+    if let Some(evaluate_scripts) = overrides.evaluate_scripts {
+        options.evaluation = evaluate_scripts.then(|| context.backend.slot_config());
+    }
+
+    Ok(options)
+}
+
+/// Resolves the request against the backend snapshot. Unless it is a dry
+/// run, the inputs of the tx are reserved; dry runs return a report instead.
+pub async fn resolve_in_context<B: Backend>(
+    params: Params<'_>,
+    context: &Context<B>,
+    extensions: &Extensions,
+    dry_run: bool,
+) -> Result<(Resolved, Option<TxReport>), ErrorObjectOwned> {
+    context.ensure_accepting()?;
+
+    let options = resolve_options(&params, context, extensions)?;
+
+    let backend = &context.backend;
+    let utxos = backend.read_utxos().await;
+
+    let chain_point = backend.chain_point().await.map_err(|e| {
         ErrorObject::owned(
-            ErrorCode::InvalidParams.code(),
-            "Failed to parse resolve request",
-            Some(x.to_string()),
+            ErrorCode::InternalError.code(),
+            "Failed to get chain point",
+            Some(e.to_string()),
         )
     })?;
 
-    let hydra = context.hydra_adapter.clone();
-    let utxos = hydra.read_utxos().await;
+    let sponsored = params
+        .parse::<ResolveExtras>()
+        .is_ok_and(|extras| extras.sponsor);
+
+    let prices = backend
+        .protocol_parameters()
+        .await
+        .map_err(pparams_error)?
+        .execution_unit_prices;
+
+    let options = if sponsored {
+        ResolveOptions {
+            extra_fees: 0,
+            ..options
+        }
+    } else {
+        options
+    };
+
+    // reservations are taken optimistically, a resolve that lost its inputs
+    // to a concurrent one is retried against the updated reservations
+    let mut attempts = 0;
+    let resolved = loop {
+        attempts += 1;
+
+        let request: trp::ResolveParams = params.parse()?;
+        let reserved = context.reservations.reserved();
+
+        // the sponsor pays fees and min utxo, so the user side is resolved as
+        // if there were none
+        let pparams = get_pparams(context).await?;
+        let compiler_pparams = if sponsored {
+            PParams {
+                min_fee_coefficient: 0,
+                min_fee_constant: 0,
+                coins_per_utxo_byte: 0,
+                ..pparams
+            }
+        } else {
+            pparams
+        };
+        let compiler_prices = if sponsored {
+            ExUnitPrices::default()
+        } else {
+            prices
+        };
+
+        let mut resolved = resolve(
+            request,
+            &utxos,
+            &reserved,
+            compiler_pparams,
+            &compiler_prices,
+            chain_point.clone(),
+            &options,
+        )
+        .await?;
+
+        if sponsored {
+            resolved.payload = sponsor_resolved(
+                context,
+                &resolved.payload,
+                &utxos,
+                &reserved,
+                &get_pparams(context).await?,
+                &prices,
+                &options,
+            )?;
+        }
+
+        // dry runs are for inspection and don't hold on to their inputs
+        if dry_run || context.reservations.try_reserve(&resolved.payload) {
+            break resolved;
+        }
+
+        if attempts == RESERVATION_ATTEMPTS {
+            return Err(ErrorObject::owned(
+                ErrorCode::ServerIsBusy.code(),
+                "inputs taken by concurrent resolves",
+                None::<String>,
+            ));
+        }
+    };
+
+    if !dry_run {
+        return Ok((resolved, None));
+    }
 
-    // TODO: very inefficient to query it time we resolve a tx
-    let pparams = hydra.get_pparams().await.map_err(|e| {
+    let mut report = TxReport::from_cbor(&resolved.payload, Some(&utxos.0)).map_err(|e| {
         ErrorObject::owned(
             ErrorCode::InternalError.code(),
-            "Failed to get pparams",
+            "Failed to build tx report",
             Some(e.to_string()),
         )
     })?;
+    report.optimize_passes = Some(resolved.passes);
 
-    let progress = hydra.get_progress().await;
+    Ok((resolved, Some(report)))
+}
 
-    let timestamp = if progress.timestamp.is_empty() {
-        0u64
-    } else {
-        let dt = DateTime::parse_from_rfc3339(&progress.timestamp).map_err(|e| {
-            ErrorObject::owned(
-                ErrorCode::InternalError.code(),
-                "Failed to parse timestamp",
-                Some(e.to_string()),
+/// Protocol parameters of the backend, fetched for every compile since the
+/// compiler takes ownership of them
+async fn get_pparams<B: Backend>(context: &Context<B>) -> Result<PParams, ErrorObjectOwned> {
+    context.backend.get_pparams().await.map_err(pparams_error)
+}
+
+fn pparams_error(error: anyhow::Error) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        ErrorCode::InternalError.code(),
+        "Failed to get pparams",
+        Some(error.to_string()),
+    )
+}
+
+/// Adds the sponsor input to a resolved tx, re-evaluates its scripts with the
+/// new inputs and registers it for co-signing
+fn sponsor_resolved<B: Backend>(
+    context: &Context<B>,
+    payload: &[u8],
+    utxos: &UtxoSnapshot<'_>,
+    reserved: &HashSet<TxID>,
+    pparams: &PParams,
+    prices: &ExUnitPrices,
+    options: &ResolveOptions,
+) -> Result<Vec<u8>, ErrorObjectOwned> {
+    let refused = |error: anyhow::Error| {
+        ErrorObject::owned(
+            ErrorCode::InvalidRequest.code(),
+            "sponsorship refused",
+            Some(format!("{error:#}")),
+        )
+    };
+
+    let Some(sponsor) = &context.sponsor else {
+        return Err(ErrorObject::owned(
+            ErrorCode::InvalidParams.code(),
+            "sponsorship is not enabled",
+            None::<String>,
+        ));
+    };
+
+    let prepared = sponsor
+        .prepare(payload, &utxos.0, reserved, pparams, prices)
+        .map_err(refused)?;
+
+    let payload = match options.evaluation {
+        Some(slot_config) => {
+            // the fee the ex units add comes out of the sponsor change
+            let payer = Some(sponsor.address());
+            eval::evaluate_and_apply(
+                &prepared.cbor,
+                &utxos.0,
+                pparams,
+                prices,
+                &slot_config,
+                payer,
             )
-        })?;
-        dt.timestamp_millis() as u64
+            .map_err(eval_error)?
+        }
+        None => prepared.cbor.clone(),
     };
 
+    sponsor.register(&payload, prepared).map_err(refused)?;
+
+    Ok(payload)
+}
+
+/// Counts the compile passes the resolver runs while optimizing the tx
+struct PassCounter<'a, C> {
+    inner: &'a mut C,
+    passes: usize,
+}
+
+impl<C: Compiler> Compiler for PassCounter<'_, C> {
+    type CompilerOp = C::CompilerOp;
+    type Expression = C::Expression;
+
+    fn compile(&mut self, tir: &AnyTir) -> Result<CompiledTx, compile::Error> {
+        self.passes += 1;
+        self.inner.compile(tir)
+    }
+
+    fn reduce_op(&self, op: Self::CompilerOp) -> Result<Self::Expression, reduce::Error> {
+        self.inner.reduce_op(op)
+    }
+}
+
+/// Knobs of a single resolve, from the server config and, for trusted
+/// callers, the request
+#[derive(Debug, Clone)]
+pub struct ResolveOptions {
+    /// Lovelace added on top of the fee the compiler computes
+    pub extra_fees: u64,
+    pub max_optimize_rounds: usize,
+    /// Evaluates the scripts of the tx with the given slot config
+    pub evaluation: Option<SlotConfig>,
+}
+
+/// Per-request overrides of [`ResolveOptions`], sent as `options` next to
+/// the resolve params
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResolveOverrides {
+    extra_fees: Option<u64>,
+    max_optimize_rounds: Option<usize>,
+    evaluate_scripts: Option<bool>,
+}
+
+/// Fields sent next to the resolve params
+#[derive(Deserialize)]
+struct ResolveExtras {
+    #[serde(default)]
+    options: Option<ResolveOverrides>,
+    /// Has the configured sponsor pay for the tx
+    #[serde(default)]
+    sponsor: bool,
+}
+
+pub struct Resolved {
+    /// CBOR of the resolved transaction
+    pub payload: Vec<u8>,
+    /// Compile passes run by the resolver, the first one included
+    pub passes: usize,
+}
+
+/// Resolves a TRP request against a utxo snapshot, independent of where the
+/// snapshot comes from. Reserved utxos are left out of coin selection.
+///
+/// When evaluation is enabled, the scripts of the tx are run and the compiler
+/// estimates of the redeemer ex units are replaced by the evaluated ones.
+pub async fn resolve(
+    request: trp::ResolveParams,
+    utxos: &UtxoSnapshot<'_>,
+    reserved: &HashSet<TxID>,
+    pparams: PParams,
+    prices: &ExUnitPrices,
+    chain_point: ChainPoint,
+    options: &ResolveOptions,
+) -> Result<Resolved, ErrorObjectOwned> {
+    let (tx, args) = trp::parse_resolve_request(request).map_err(|x| {
+        ErrorObject::owned(
+            ErrorCode::InvalidParams.code(),
+            "Failed to parse resolve request",
+            Some(x.to_string()),
+        )
+    })?;
+
     let mut compiler = tx3_cardano::Compiler::new(
         pparams,
         tx3_cardano::Config {
-            extra_fees: Some(0),
-        },
-        ChainPoint {
-            slot: progress.seq,
-            hash: vec![0; 32],
-            timestamp: timestamp as u128,
+            extra_fees: Some(options.extra_fees),
         },
+        chain_point,
     );
 
-    let resolved = tx3_resolver::resolve_tx(
-        tx,
-        &args,
-        &mut compiler,
-        &utxos,
-        context.config.max_optimize_rounds,
-    )
-    .await
-    .map_err(|err| {
-        ErrorObject::owned(
-            ErrorCode::InternalError.code(),
-            "Failed to resolve",
-            Some(err.to_string()),
-        )
-    });
+    let mut counter = PassCounter {
+        inner: &mut compiler,
+        passes: 0,
+    };
+
+    let store = UnreservedUtxos {
+        snapshot: utxos,
+        reserved,
+    };
+
+    let resolved =
+        tx3_resolver::resolve_tx(tx, &args, &mut counter, &store, options.max_optimize_rounds)
+            .await
+            .map_err(|err| {
+                ErrorObject::owned(
+                    ErrorCode::InternalError.code(),
+                    "Failed to resolve",
+                    Some(err.to_string()),
+                )
+            });
 
     let resolved = match resolved {
         Ok(resolved) => resolved,
@@ -85,5 +408,43 @@ pub async fn execute(
         }
     };
 
-    Ok(serde_json::json!({ "tx": hex::encode(resolved.payload) }))
+    let passes = counter.passes;
+
+    let Some(slot_config) = options.evaluation else {
+        return Ok(Resolved {
+            payload: resolved.payload,
+            passes,
+        });
+    };
+
+    let payload = eval::evaluate_and_apply(
+        &resolved.payload,
+        &utxos.0,
+        &compiler.pparams,
+        prices,
+        &slot_config,
+        None,
+    )
+    .map_err(|error| {
+        tracing::warn!(%error, "Failed to evaluate resolved tx.");
+        eval_error(error)
+    })?;
+
+    Ok(Resolved { payload, passes })
+}
+
+/// Script failures are the caller's problem, anything else is ours
+pub fn eval_error(error: eval::EvalError) -> ErrorObjectOwned {
+    match error {
+        eval::EvalError::Script(trace) => ErrorObject::owned(
+            ErrorCode::InvalidRequest.code(),
+            "script evaluation failed",
+            Some(trace),
+        ),
+        eval::EvalError::Setup(error) => ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "Failed to evaluate scripts",
+            Some(format!("{error:#}")),
+        ),
+    }
 }
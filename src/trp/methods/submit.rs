@@ -1,33 +1,86 @@
 use std::{sync::Arc, time::Duration};
 
-use base64::{Engine, prelude::BASE64_STANDARD};
-use jsonrpsee::types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params};
+use jsonrpsee::{
+    Extensions,
+    types::{ErrorCode, ErrorObject, ErrorObjectOwned, Params},
+};
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast;
 use tracing::{debug, error, info};
 use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
 
 use crate::{
+    backend::Backend,
     hydra::{
         self,
         model::{HydraMessage, NewTx},
     },
-    trp::Context,
+    ledger::{
+        self, Violation,
+        witness::{self, VKeyWitness},
+    },
+    trp::{Context, auth},
 };
 
-use super::Encoding;
+use super::{BytesEnvelope, resolve::resolve_in_context};
 
 const SUBMIT_TIMEOUT: Duration = Duration::from_secs(30);
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WitnessType {
+    VKey,
+}
+
+/// Detached signature of the tx body
 #[derive(Deserialize)]
-pub struct TrpSubmitTxRequest {
-    pub encoding: Encoding,
-    pub payload: String,
+pub struct TrpSubmitWitness {
+    pub key: BytesEnvelope,
+    pub signature: BytesEnvelope,
+    /// Only vkey witnesses are supported, other types fail to deserialize
+    #[serde(rename = "type", default = "default_witness_type")]
+    pub r#type: WitnessType,
+}
+
+fn default_witness_type() -> WitnessType {
+    WitnessType::VKey
 }
 
 #[derive(Deserialize)]
 pub struct TrpSubmitRequest {
-    pub tx: TrpSubmitTxRequest,
+    pub tx: BytesEnvelope,
+    /// Witnesses to add to the tx before submitting it
+    #[serde(default)]
+    pub witnesses: Vec<TrpSubmitWitness>,
+}
+
+/// Checks the detached witnesses against the tx body and adds them to its
+/// witness set, the body bytes are left as they are
+fn merge_witnesses(
+    raw: Vec<u8>,
+    witnesses: &[TrpSubmitWitness],
+) -> Result<Vec<u8>, ErrorObjectOwned> {
+    if witnesses.is_empty() {
+        return Ok(raw);
+    }
+
+    let witnesses = witnesses
+        .iter()
+        .map(|witness| match witness.r#type {
+            WitnessType::VKey => Ok(VKeyWitness {
+                vkey: witness.key.decode("witness key")?,
+                signature: witness.signature.decode("witness signature")?,
+            }),
+        })
+        .collect::<Result<Vec<_>, ErrorObjectOwned>>()?;
+
+    witness::verify_and_add(&raw, &witnesses).map_err(|error| {
+        debug!(?error, "rejected submit witnesses");
+        ErrorObject::owned(
+            ErrorCode::InvalidParams.code(),
+            "invalid witness",
+            Some(format!("{error:#}")),
+        )
+    })
 }
 
 #[derive(Serialize)]
@@ -35,13 +88,78 @@ pub struct TrpSubmitResponse {
     pub hash: String,
 }
 
-pub async fn execute(
+/// Failure of [`submit_and_wait`]. Ledger rejections are kept apart so that
+/// the APIs mapping them to their own error codes don't parse messages.
+#[derive(Debug)]
+pub enum SubmitError {
+    /// Failed the local validation against the snapshot
+    Violations(Vec<Violation>),
+    /// Rejected by the head, with its reason
+    Rejected(String),
+    /// Failed before reaching the ledger
+    Rpc(ErrorObjectOwned),
+}
+
+impl From<ErrorObjectOwned> for SubmitError {
+    fn from(error: ErrorObjectOwned) -> Self {
+        SubmitError::Rpc(error)
+    }
+}
+
+impl From<SubmitError> for ErrorObjectOwned {
+    fn from(error: SubmitError) -> Self {
+        match error {
+            SubmitError::Violations(violations) => ErrorObject::owned(
+                ErrorCode::InvalidRequest.code(),
+                "invalid transaction",
+                Some(violations),
+            ),
+            SubmitError::Rejected(reason) => ErrorObject::owned(
+                ErrorCode::InvalidRequest.code(),
+                "invalid transaction",
+                Some(reason),
+            ),
+            SubmitError::Rpc(error) => error,
+        }
+    }
+}
+
+/// Checks the tx against the current snapshot so that obviously bad txs
+/// don't cost a round trip to the head
+async fn validate_locally<B: Backend>(
+    context: &Context<B>,
+    metx: &MultiEraTx<'_>,
+) -> Result<(), SubmitError> {
+    let pparams = context.backend.get_pparams().await.map_err(|error| {
+        error!(?error);
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to get pparams",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let violations = {
+        let utxos = context.backend.read_utxos().await;
+        ledger::validate(&utxos.0, metx, &pparams)
+    };
+
+    if !violations.is_empty() {
+        debug!(?violations, "tx failed local validation");
+        return Err(SubmitError::Violations(violations));
+    }
+
+    Ok(())
+}
+
+pub async fn execute<B: Backend>(
     params: Params<'_>,
-    context: Arc<Context>,
-    hydra_channel: Arc<broadcast::Sender<hydra::model::Event>>,
+    context: Arc<Context<B>>,
 ) -> Result<serde_json::Value, ErrorObjectOwned> {
     tracing::info!(method = "trp.submit", "Received TRP request.");
 
+    context.ensure_accepting()?;
+
     let request = params.parse::<TrpSubmitRequest>().map_err(|error| {
         error!(?error);
         ErrorObject::owned(
@@ -51,26 +169,112 @@ pub async fn execute(
         )
     })?;
 
-    let raw = match request.tx.encoding {
-        Encoding::Hex => hex::decode(request.tx.payload).map_err(|error| {
-            error!(?error);
+    let raw = request.tx.decode("tx")?;
+    let raw = merge_witnesses(raw, &request.witnesses)?;
+
+    Ok(submit_and_wait(&context, raw).await?)
+}
+
+#[derive(Deserialize)]
+struct WithSigners {
+    /// Names of the configured wallets that sign the resolved tx
+    signers: Vec<String>,
+}
+
+/// Resolves a tx, signs it with server side wallets and submits it, waiting
+/// for the head to accept or reject it
+pub async fn resolve_and_submit<B: Backend>(
+    params: Params<'_>,
+    context: Arc<Context<B>>,
+    extensions: Extensions,
+) -> Result<serde_json::Value, ErrorObjectOwned> {
+    info!(method = "trp.resolveAndSubmit", "Received TRP request.");
+
+    let WithSigners { signers } = params.parse().map_err(|error| {
+        ErrorObject::owned(
+            ErrorCode::InvalidParams.code(),
+            "invalid params",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let api_key = auth::api_key(&extensions).map(String::as_str);
+
+    let wallets = signers
+        .iter()
+        .map(|name| match context.wallets.get(name) {
+            Some(wallet) if wallet.allows(api_key) => Ok(wallet),
+            // unknown and forbidden wallets look the same to the caller
+            _ => Err(auth::unauthorized(&format!(
+                "wallet {name} is not available"
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (resolved, _) = resolve_in_context(params, &context, &extensions, false).await?;
+
+    let body_hash = witness::body_hash(&resolved.payload).map_err(|error| {
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to hash resolved tx",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let witnesses: Vec<_> = wallets
+        .iter()
+        .map(|wallet| wallet.witness(&body_hash))
+        .collect();
+
+    let raw = witness::add_vkey_witnesses(&resolved.payload, &witnesses).map_err(|error| {
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to sign resolved tx",
+            Some(error.to_string()),
+        )
+    })?;
+
+    Ok(submit_and_wait(&context, raw).await?)
+}
+
+/// Adds the sponsor signature to txs the sponsor paid for, other txs pass
+/// through untouched
+async fn cosign_sponsored<B: Backend>(
+    context: &Context<B>,
+    raw: Vec<u8>,
+) -> Result<Vec<u8>, ErrorObjectOwned> {
+    let Some(sponsor) = &context.sponsor else {
+        return Ok(raw);
+    };
+
+    let pparams = context.backend.get_pparams().await.map_err(|error| {
+        error!(?error);
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed to get pparams",
+            Some(error.to_string()),
+        )
+    })?;
+
+    let utxos = context.backend.read_utxos().await;
+    sponsor
+        .cosign(raw, &context.wallets, &utxos.0, &pparams)
+        .map_err(|error| {
+            debug!(?error, "sponsor refused to sign");
             ErrorObject::owned(
-                ErrorCode::ParseError.code(),
-                "invalid tx hex encoding",
-                Some(error.to_string()),
+                ErrorCode::InvalidRequest.code(),
+                "sponsorship refused",
+                Some(format!("{error:#}")),
             )
-        })?,
-        Encoding::Base64 => BASE64_STANDARD
-            .decode(request.tx.payload)
-            .map_err(|error| {
-                error!(?error);
-                ErrorObject::owned(
-                    ErrorCode::ParseError.code(),
-                    "invalid tx base64 encoding",
-                    Some(error.to_string()),
-                )
-            })?,
-    };
+        })
+}
+
+/// Validates the tx, sends it to the backend and waits for its outcome
+pub async fn submit_and_wait<B: Backend>(
+    context: &Context<B>,
+    raw: Vec<u8>,
+) -> Result<serde_json::Value, SubmitError> {
+    let raw = cosign_sponsored(context, raw).await?;
 
     let metx = MultiEraTx::decode(&raw).map_err(|error| {
         error!(?error);
@@ -86,30 +290,47 @@ pub async fn execute(
             ErrorCode::InvalidParams.code(),
             "invalid tx",
             None as Option<String>,
-        ));
+        )
+        .into());
+    }
+
+    {
+        let utxos = context.backend.read_utxos().await;
+        context.history.submitted(&metx, &raw, &utxos.0);
+    }
+
+    if context.config.local_validation {
+        validate_locally(context, &metx)
+            .await
+            .inspect_err(|error| {
+                if let SubmitError::Violations(violations) = error {
+                    let reason = serde_json::to_string(violations).unwrap_or_default();
+                    context.history.rejected(&metx.hash().to_string(), reason);
+                }
+            })?;
     }
 
     let hash = metx.hash();
 
+    // subscribe before sending, backends may reply before `submit` returns
+    let mut rx = context.backend.events();
+
     let message = HydraMessage::NewTx(NewTx::new(raw));
-    context
-        .hydra_adapter
-        .submit(message)
-        .await
-        .map_err(|error| {
-            error!(?error);
-            ErrorObject::owned(
-                ErrorCode::InternalError.code(),
-                "failed sending tx to hydra",
-                Some(error.to_string()),
-            )
-        })?;
+    context.backend.submit(message).await.map_err(|error| {
+        error!(?error);
+        context
+            .history
+            .rejected(&hash.to_string(), format!("failed sending tx: {error:#}"));
+        ErrorObject::owned(
+            ErrorCode::InternalError.code(),
+            "failed sending tx to hydra",
+            Some(error.to_string()),
+        )
+    })?;
 
     info!(?hash, "submitting tx");
     let hash = hex::encode(hash);
 
-    let mut rx = hydra_channel.subscribe();
-
     let response =
         serde_json::to_value(TrpSubmitResponse { hash: hash.clone() }).map_err(|error| {
             error!(?error);
@@ -127,19 +348,11 @@ pub async fn execute(
                     hydra::model::Event::TxInvalid {
                         transaction,
                         validation_error,
-                    } => {
-                        if transaction.tx_id == hash {
-                            break Err(ErrorObject::owned(
-                                ErrorCode::InvalidRequest.code(),
-                                "invalid transaction",
-                                Some(validation_error.reason),
-                            ));
-                        }
+                    } if transaction.tx_id == hash => {
+                        break Err(SubmitError::Rejected(validation_error.reason));
                     }
-                    hydra::model::Event::TxValid { tx_id } => {
-                        if tx_id == hash {
-                            break Ok(response);
-                        }
+                    hydra::model::Event::TxValid { tx_id } if tx_id == hash => {
+                        break Ok(response);
                     }
                     _ => {}
                 },
@@ -152,7 +365,8 @@ pub async fn execute(
                         ErrorCode::InternalError.code(),
                         "internal channel error",
                         None::<String>,
-                    ));
+                    )
+                    .into());
                 }
             }
         }
@@ -167,7 +381,86 @@ pub async fn execute(
                 ErrorCode::ServerIsBusy.code(),
                 "submit request timeout",
                 None::<String>,
-            ))
+            )
+            .into())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::ledger::{payment::build_payment, testing, txid};
+
+    /// Unsigned payment of `amount` from the test key to the vendor
+    fn payment(amount: u64) -> Vec<u8> {
+        let utxos = HashMap::from([(
+            txid("00".repeat(32), 0),
+            testing::lovelace_utxo(&testing::key_address(), 100_000_000),
+        )]);
+
+        build_payment(
+            &utxos,
+            &testing::key_address(),
+            testing::VENDOR,
+            amount,
+            &testing::pparams(),
+        )
+        .unwrap()
+        .cbor
+    }
+
+    fn detached(witness: &VKeyWitness) -> TrpSubmitWitness {
+        serde_json::from_value(json!({
+            "key": { "encoding": "hex", "payload": hex::encode(&witness.vkey) },
+            "signature": { "encoding": "hex", "payload": hex::encode(&witness.signature) },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn adds_detached_witnesses_to_the_tx() -> anyhow::Result<()> {
+        let raw = payment(5_000_000);
+        let witness = detached(&testing::vkey_witness(&raw));
+
+        let merged = merge_witnesses(raw.clone(), &[witness])?;
+        let tx = MultiEraTx::decode(&merged)?;
+
+        assert_eq!(tx.vkey_witnesses().len(), 1);
+        assert_eq!(tx.hash(), MultiEraTx::decode(&raw)?.hash());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_signatures_of_another_body() {
+        let raw = payment(5_000_000);
+        let witness = detached(&testing::vkey_witness(&payment(6_000_000)));
+
+        let error = merge_witnesses(raw, &[witness]).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidParams.code());
+    }
+
+    #[test]
+    fn leaves_txs_without_witnesses_untouched() -> anyhow::Result<()> {
+        let raw = payment(5_000_000);
+        assert_eq!(merge_witnesses(raw.clone(), &[])?, raw);
+
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_other_witness_types() {
+        let witness = serde_json::from_value::<TrpSubmitWitness>(json!({
+            "key": { "encoding": "hex", "payload": "00" },
+            "signature": { "encoding": "hex", "payload": "00" },
+            "type": "bootstrap",
+        }));
+
+        assert!(witness.is_err());
+    }
+}
@@ -1,23 +1,45 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use jsonrpsee::{RpcModule, server::Server, server::ServerConfig};
-use serde::Deserialize;
+use jsonrpsee::{
+    Extensions, RpcModule,
+    server::{Server, ServerConfig, middleware::http::ProxyGetRequestLayer},
+    types::{ErrorCode, ErrorObject, ErrorObjectOwned},
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
-use tokio_util::sync::CancellationToken;
-use tower::ServiceBuilder;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::{ServiceBuilder, util::MapRequestLayer};
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::hydra::{self, HydraAdapter};
+use crate::{
+    backend::Backend,
+    hydra::model::Event,
+    wallet::{self, Wallets},
+};
 
+pub mod auth;
+pub mod blockfrost;
+pub mod faucet;
+pub mod history;
+pub mod kupo;
 mod mapping;
-mod methods;
+pub(crate) mod methods;
+pub mod report;
+mod reservations;
+pub mod sponsor;
+pub mod utxorpc;
 mod utxos;
+pub mod webhooks;
 
-pub async fn run(
+use faucet::Faucet;
+use history::History;
+use reservations::Reservations;
+use sponsor::Sponsor;
+
+pub async fn run<B: Backend>(
     config: Config,
-    hydra_adapter: Arc<HydraAdapter>,
-    hydra_channel: Arc<broadcast::Sender<hydra::model::Event>>,
+    backend: Arc<B>,
     cancellation_token: CancellationToken,
 ) -> anyhow::Result<()> {
     let cors_layer = if config.permissive_cors {
@@ -26,7 +48,13 @@ pub async fn run(
         CorsLayer::new()
     };
 
-    let middleware = ServiceBuilder::new().layer(cors_layer);
+    let probes_layer =
+        ProxyGetRequestLayer::new([("/healthz", "health.live"), ("/readyz", "health.ready")])?;
+
+    let middleware = ServiceBuilder::new()
+        .layer(MapRequestLayer::new(auth::tag_api_key))
+        .layer(probes_layer)
+        .layer(cors_layer);
     let server_config = ServerConfig::builder()
         .max_connections(config.max_connections)
         .build();
@@ -36,24 +64,132 @@ pub async fn run(
         .build(&config.listen_address)
         .await?;
 
-    let mut module = RpcModule::new(Context {
-        hydra_adapter,
+    let wallets = Wallets::load(&config.wallets)?;
+    let sponsor = config
+        .sponsor
+        .clone()
+        .map(|sponsor| Sponsor::try_new(sponsor, &wallets))
+        .transpose()?;
+    let faucet = config
+        .faucet
+        .clone()
+        .map(|faucet| Faucet::try_new(faucet, &wallets))
+        .transpose()?;
+    let submits = TaskTracker::new();
+    let reservations = Arc::new(Reservations::new(Duration::from_secs(
+        config.reservation_ttl_secs,
+    )));
+    let history = Arc::new(History::new(config.history_size));
+
+    // subscribed up front so no rejection is missed between startup and the
+    // first resolve
+    let mut events = backend.events();
+    let webhook_events = config.webhooks.as_ref().map(|_| backend.events());
+    history.seed(backend.read_utxos().await.0.clone());
+
+    let context = Arc::new(Context {
+        backend,
         config: config.clone(),
+        submits: submits.clone(),
+        shutdown: cancellation_token.clone(),
+        reservations: Arc::clone(&reservations),
+        history: Arc::clone(&history),
+        wallets,
+        sponsor,
+        faucet,
     });
 
-    module.register_async_method("trp.resolve", |params, context, _| async {
-        methods::resolve::execute(params, context).await
+    let mut module = RpcModule::from_arc(Arc::clone(&context));
+
+    module.register_async_method("trp.resolve", |params, context, extensions| async {
+        methods::resolve::execute(params, context, extensions).await
     })?;
 
-    module.register_async_method("trp.submit", move |params, context, _| {
-        let hydra_channel = Arc::clone(&hydra_channel);
-        async move { methods::submit::execute(params, context, hydra_channel).await }
+    module.register_async_method("trp.dryRun", |params, context, extensions| async {
+        methods::resolve::dry_run(params, context, extensions).await
+    })?;
+
+    module.register_async_method("trp.submit", |params, context, _| {
+        let submits = context.submits.clone();
+        submits.track_future(async move { methods::submit::execute(params, context).await })
+    })?;
+
+    module.register_async_method("trp.resolveAndSubmit", |params, context, extensions| {
+        let submits = context.submits.clone();
+        submits.track_future(async move {
+            methods::submit::resolve_and_submit(params, context, extensions).await
+        })
+    })?;
+
+    if config.faucet.is_some() {
+        module.register_async_method("faucet.request", |params, context, _| {
+            let submits = context.submits.clone();
+            submits.track_future(async move { methods::faucet::request(params, context).await })
+        })?;
+    }
+
+    module.register_async_method("trp.evaluate", |params, context, _| async {
+        methods::evaluate::execute(params, context).await
+    })?;
+
+    module.register_async_method("trp.queryUtxos", |params, context, _| async {
+        methods::query::query_utxos(params, context).await
+    })?;
+
+    module.register_async_method("trp.getUtxo", |params, context, _| async {
+        methods::query::get_utxo(params, context).await
+    })?;
+
+    module.register_async_method("trp.getBalance", |params, context, _| async {
+        methods::query::get_balance(params, context).await
+    })?;
+
+    if config.ogmios {
+        module.register_async_method("queryLedgerState/utxo", |params, context, _| async {
+            methods::ogmios::query_utxo(params, context).await
+        })?;
+
+        module.register_async_method(
+            "queryLedgerState/protocolParameters",
+            |_, context, _| async { methods::ogmios::protocol_parameters(context).await },
+        )?;
+
+        module.register_async_method("queryNetwork/tip", |_, context, _| async {
+            methods::ogmios::tip(context).await
+        })?;
+
+        module.register_async_method("submitTransaction", |params, context, _| {
+            let submits = context.submits.clone();
+            submits.track_future(async move {
+                methods::ogmios::submit_transaction(params, context).await
+            })
+        })?;
+
+        module.register_async_method("evaluateTransaction", |params, context, _| async {
+            methods::ogmios::evaluate_transaction(params, context).await
+        })?;
+    }
+
+    module.register_async_method("trp.getTx", |params, context, _| async {
+        methods::history::get_tx(params, context).await
+    })?;
+
+    module.register_async_method("trp.listTxs", |params, context, _| async {
+        methods::history::list_txs(params, context).await
     })?;
 
     module.register_async_method("health", |_, context, _| async {
         methods::health::execute(context).await
     })?;
 
+    module.register_async_method("health.live", |_, context, _| async {
+        methods::health::live(context).await
+    })?;
+
+    module.register_async_method("health.ready", |_, context, _| async {
+        methods::health::ready(context).await
+    })?;
+
     info!(
         address = config.listen_address.to_string(),
         "TRP server running"
@@ -68,19 +204,140 @@ pub async fn run(
 
     let cancellation = async {
         cancellation_token.cancelled().await;
-        info!("gracefully shuting down trp");
+        info!(
+            pending_submits = submits.len(),
+            "gracefully shuting down trp"
+        );
+
+        submits.close();
+        let grace_period = Duration::from_secs(config.shutdown_grace_period_secs);
+        if tokio::time::timeout(grace_period, submits.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                pending_submits = submits.len(),
+                "shutdown grace period elapsed with pending submits"
+            );
+        }
+
         let _ = handle.stop();
         Ok::<(), anyhow::Error>(())
     };
 
-    tokio::try_join!(server, cancellation)?;
+    let observe = async {
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        match &event {
+                            Event::TxInvalid { transaction, .. } => {
+                                reservations.release(&transaction.tx_id);
+                            }
+                            Event::SnapshotConfirmed { snapshot, .. } => {
+                                reservations.release_spent(&snapshot.utxo);
+                            }
+                            _ => {}
+                        }
+                        history.observe(&event);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let blockfrost = serve_optional(config.blockfrost.as_ref(), |listener| {
+        blockfrost::serve(listener, Arc::clone(&context), cancellation_token.clone())
+    });
+
+    let kupo = serve_optional(config.kupo.as_ref(), |listener| {
+        kupo::serve(listener, Arc::clone(&context), cancellation_token.clone())
+    });
+
+    let utxorpc = serve_optional(config.utxorpc.as_ref(), |listener| {
+        utxorpc::serve(listener, Arc::clone(&context), cancellation_token.clone())
+    });
+
+    let webhooks = async {
+        match (&config.webhooks, webhook_events) {
+            (Some(webhooks), Some(webhook_events)) => {
+                webhooks::run(
+                    webhooks,
+                    Arc::clone(&context.backend),
+                    webhook_events,
+                    cancellation_token.clone(),
+                )
+                .await
+            }
+            _ => Ok(()),
+        }
+    };
+
+    tokio::try_join!(
+        server,
+        cancellation,
+        observe,
+        blockfrost,
+        kupo,
+        utxorpc,
+        webhooks
+    )?;
 
     Ok(())
 }
 
-struct Context {
-    hydra_adapter: Arc<HydraAdapter>,
+/// Runs an extra listener of the server, if it is configured
+async fn serve_optional<'a, F>(
+    listener: Option<&'a ListenerConfig>,
+    serve: impl FnOnce(&'a ListenerConfig) -> F,
+) -> anyhow::Result<()>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    match listener {
+        Some(listener) => serve(listener).await,
+        None => Ok(()),
+    }
+}
+
+pub(crate) struct Context<B: Backend> {
+    backend: Arc<B>,
     config: Config,
+    /// In-flight `trp.submit` calls, drained on shutdown
+    submits: TaskTracker,
+    shutdown: CancellationToken,
+    /// Inputs of resolved txs that are not yet submitted
+    reservations: Arc<Reservations>,
+    /// Txs seen by the server, for `trp.getTx` and `trp.listTxs`
+    history: Arc<History>,
+    wallets: Wallets,
+    sponsor: Option<Sponsor>,
+    faucet: Option<Faucet>,
+}
+
+impl<B: Backend> Context<B> {
+    /// Rejects new work once shutdown has started, in-flight calls are left to finish
+    fn ensure_accepting(&self) -> Result<(), ErrorObjectOwned> {
+        if self.shutdown.is_cancelled() {
+            return Err(ErrorObject::owned(
+                ErrorCode::ServerIsBusy.code(),
+                "server is shutting down",
+                None::<String>,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the request came with one of the configured trusted api keys
+    fn is_trusted(&self, extensions: &Extensions) -> bool {
+        auth::api_key(extensions).is_some_and(|key| self.config.trusted_api_keys.contains(key))
+    }
 }
 
 fn default_max_optimize_rounds() -> usize {
@@ -91,13 +348,89 @@ fn default_max_connections() -> u32 {
     100
 }
 
-#[derive(Deserialize, Clone)]
+fn default_shutdown_grace_period_secs() -> u64 {
+    30
+}
+
+fn default_local_validation() -> bool {
+    true
+}
+
+fn default_evaluate_scripts() -> bool {
+    true
+}
+
+fn default_reservation_ttl_secs() -> u64 {
+    60
+}
+
+fn default_history_size() -> usize {
+    10_000
+}
+
+/// Extra listener next to the JSON-RPC one
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ListenerConfig {
+    pub listen_address: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
-    listen_address: String,
+    pub listen_address: String,
     #[serde(default)]
     permissive_cors: bool,
     #[serde(default = "default_max_optimize_rounds")]
     max_optimize_rounds: usize,
+    /// Lovelace added to the fee computed by the compiler, for heads whose
+    /// ledger charges more than the linear fee
+    #[serde(default)]
+    extra_fees: u64,
+    /// API keys allowed to override resolve options per request, left out
+    /// of `check-config`
+    #[serde(default, skip_serializing)]
+    trusted_api_keys: Vec<String>,
+    /// Named signing keys for `trp.resolveAndSubmit` and the sponsor
+    #[serde(default)]
+    wallets: HashMap<String, wallet::Config>,
+    /// Pays fees of resolves that ask for it, disabled if unset
+    #[serde(default)]
+    sponsor: Option<sponsor::Config>,
+    /// Funds addresses through `faucet.request`, disabled if unset
+    #[serde(default)]
+    faucet: Option<faucet::Config>,
+    /// Serves the Ogmios-compatible query, submit and evaluate methods
+    #[serde(default)]
+    ogmios: bool,
+    /// Blockfrost-compatible REST listener, disabled if unset
+    #[serde(default)]
+    blockfrost: Option<ListenerConfig>,
+    /// Kupo-compatible HTTP listener, disabled if unset
+    #[serde(default)]
+    kupo: Option<ListenerConfig>,
+    /// UTxO RPC gRPC listener, disabled if unset
+    #[serde(default)]
+    utxorpc: Option<ListenerConfig>,
+    /// Endpoints notified of head activity, disabled if unset
+    #[serde(default)]
+    webhooks: Option<webhooks::Config>,
     #[serde(default = "default_max_connections")]
     max_connections: u32,
+    /// Max seconds since the last snapshot for `/readyz` to pass, unbounded if unset
+    #[serde(default)]
+    readiness_max_snapshot_age_secs: Option<u64>,
+    /// Seconds to wait for pending submits before closing on shutdown
+    #[serde(default = "default_shutdown_grace_period_secs")]
+    shutdown_grace_period_secs: u64,
+    /// Runs phase-1 validation against the snapshot before forwarding submits
+    #[serde(default = "default_local_validation")]
+    local_validation: bool,
+    /// Evaluates the scripts of resolved txs to set their redeemer ex units
+    #[serde(default = "default_evaluate_scripts")]
+    evaluate_scripts: bool,
+    /// Seconds the inputs of a resolved tx are kept out of other resolves, 0 disables it
+    #[serde(default = "default_reservation_ttl_secs")]
+    reservation_ttl_secs: u64,
+    /// Txs kept for `trp.getTx` and `trp.listTxs`, the oldest are dropped first, 0 disables it
+    #[serde(default = "default_history_size")]
+    history_size: usize,
 }
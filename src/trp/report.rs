@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Context;
+use serde::Serialize;
+use tx3_cardano::pallas::{
+    crypto::hash::Hasher,
+    ledger::{
+        primitives::conway::DatumOption,
+        traverse::{MultiEraInput, MultiEraOutput, MultiEraPolicyAssets, MultiEraTx},
+    },
+};
+
+use crate::{
+    hydra::model::{AssetValue, TxID, Utxo},
+    ledger::txid,
+};
+
+/// `policy` -> `asset name` -> amount, hex encoded
+pub type Assets = BTreeMap<String, BTreeMap<String, i128>>;
+
+/// Human readable summary of a transaction CBOR
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TxReport {
+    pub hash: String,
+    pub size: usize,
+    pub fee: Option<u64>,
+    pub inputs: Vec<InputReport>,
+    pub reference_inputs: Vec<InputReport>,
+    pub outputs: Vec<OutputReport>,
+    pub mint: Assets,
+    pub validity: ValidityReport,
+    /// Compile passes the resolver ran, only known for freshly resolved txs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimize_passes: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InputReport {
+    pub tx_id: TxID,
+    /// Value of the spent utxo, when it is known
+    #[serde(flatten)]
+    pub value: Option<ValueReport>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueReport {
+    pub lovelace: u64,
+    pub assets: Assets,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputReport {
+    pub address: String,
+    pub lovelace: u64,
+    pub assets: Assets,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datum: Option<DatumReport>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DatumReport {
+    Hash { hash: String },
+    Inline { hash: String, cbor: String },
+}
+
+/// Slot bounds of the tx, both optional
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidityReport {
+    pub invalid_before: Option<u64>,
+    pub invalid_hereafter: Option<u64>,
+}
+
+fn assets_from_policies(policies: &[MultiEraPolicyAssets]) -> Assets {
+    let mut assets: Assets = BTreeMap::new();
+    for policy_assets in policies {
+        let policy = assets
+            .entry(policy_assets.policy().to_string())
+            .or_default();
+        for asset in policy_assets.assets() {
+            policy.insert(hex::encode(asset.name()), asset.any_coin());
+        }
+    }
+
+    assets
+}
+
+impl ValueReport {
+    pub fn from_utxo(utxo: &Utxo) -> Self {
+        let mut lovelace = 0;
+        let mut assets: Assets = BTreeMap::new();
+
+        for (policy, value) in &utxo.value.assets {
+            match value {
+                AssetValue::Lovelace(amount) => lovelace = *amount,
+                AssetValue::Multi(by_name) => {
+                    let policy = assets.entry(policy.clone()).or_default();
+                    for (name, amount) in by_name {
+                        policy.insert(name.clone(), *amount as i128);
+                    }
+                }
+            }
+        }
+
+        Self { lovelace, assets }
+    }
+}
+
+impl InputReport {
+    fn from_input(input: &MultiEraInput, utxos: Option<&HashMap<TxID, Utxo>>) -> Self {
+        let tx_id = txid(input.hash(), input.index());
+        let value = utxos
+            .and_then(|utxos| utxos.get(&tx_id))
+            .map(ValueReport::from_utxo);
+
+        Self { tx_id, value }
+    }
+}
+
+impl OutputReport {
+    fn from_output(output: &MultiEraOutput) -> anyhow::Result<Self> {
+        let address = output
+            .address()
+            .context("decoding output address")?
+            .to_string();
+
+        let value = output.value();
+
+        let datum = output.datum().map(|datum| match datum {
+            DatumOption::Hash(hash) => DatumReport::Hash {
+                hash: hash.to_string(),
+            },
+            DatumOption::Data(data) => DatumReport::Inline {
+                hash: Hasher::<256>::hash(data.0.raw_cbor()).to_string(),
+                cbor: hex::encode(data.0.raw_cbor()),
+            },
+        });
+
+        Ok(Self {
+            address,
+            lovelace: value.coin(),
+            assets: assets_from_policies(&value.assets()),
+            datum,
+        })
+    }
+}
+
+impl TxReport {
+    /// Summarizes a tx, with the values of its inputs when the utxo set
+    /// they come from is given
+    pub fn from_cbor(cbor: &[u8], utxos: Option<&HashMap<TxID, Utxo>>) -> anyhow::Result<Self> {
+        let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+
+        let inputs = tx
+            .inputs()
+            .iter()
+            .map(|input| InputReport::from_input(input, utxos))
+            .collect();
+
+        let reference_inputs = tx
+            .reference_inputs()
+            .iter()
+            .map(|input| InputReport::from_input(input, utxos))
+            .collect();
+
+        let outputs = tx
+            .outputs()
+            .iter()
+            .map(OutputReport::from_output)
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            hash: tx.hash().to_string(),
+            size: cbor.len(),
+            fee: tx.fee(),
+            inputs,
+            reference_inputs,
+            outputs,
+            mint: assets_from_policies(&tx.mints()),
+            validity: ValidityReport {
+                invalid_before: tx.validity_start(),
+                invalid_hereafter: tx.ttl(),
+            },
+            optimize_passes: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tx3_cardano::pallas::codec::minicbor::Encoder;
+
+    use super::*;
+    use crate::ledger::{
+        cbor::{self, TxParts},
+        testing,
+    };
+
+    /// `Constr 0 []`
+    const CONSTR_0: [u8; 3] = [0xd8, 0x79, 0x80];
+    const POLICY: [u8; 28] = [3; 28];
+
+    /// Tx spending a user utxo to an inline datum output and a datum hash
+    /// output, minting and burning under [`POLICY`] within slots 10 to 20
+    fn report_tx() -> anyhow::Result<(Vec<u8>, HashMap<TxID, Utxo>)> {
+        let input_hash = [1; 32];
+        let utxos = HashMap::from([(
+            txid(hex::encode(input_hash), 0),
+            testing::lovelace_utxo(testing::USER, 10_000_000),
+        )]);
+
+        let mut inline = testing::lovelace_utxo(testing::VENDOR, 2_000_000);
+        inline.inline_datum_raw = Some(hex::encode(CONSTR_0));
+        let mut hashed = testing::lovelace_utxo(testing::VENDOR, 3_000_000);
+        hashed.datumhash = Some("22".repeat(32));
+
+        let mut body = Encoder::new(Vec::new());
+        body.map(6)?;
+        body.u8(0)?.array(1)?;
+        body.writer_mut()
+            .extend_from_slice(&cbor::encode_input(&input_hash, 0));
+        body.u8(1)?.array(2)?;
+        body.writer_mut()
+            .extend_from_slice(&cbor::encode_output(&inline)?);
+        body.writer_mut()
+            .extend_from_slice(&cbor::encode_output(&hashed)?);
+        body.u8(2)?.u64(5_000_000)?;
+        body.u8(3)?.u64(20)?;
+        body.u8(8)?.u64(10)?;
+        body.u8(9)?.map(1)?.bytes(&POLICY)?.map(2)?;
+        body.bytes(b"BURN")?.i64(-1)?;
+        body.bytes(b"MINT")?.i64(5)?;
+
+        let tx = cbor::join_tx(&TxParts {
+            body: &body.into_writer(),
+            witness_set: &[0xa0],
+            is_valid: &[0xf5],
+            auxiliary_data: &[0xf6],
+        });
+
+        Ok((tx, utxos))
+    }
+
+    #[test]
+    fn reports_inline_and_hash_datums() -> anyhow::Result<()> {
+        let (tx, _) = report_tx()?;
+        let report = TxReport::from_cbor(&tx, None)?;
+
+        assert_eq!(report.size, tx.len());
+        assert_eq!(report.fee, Some(5_000_000));
+        assert_eq!(report.outputs.len(), 2);
+
+        let Some(DatumReport::Inline { hash, cbor }) = &report.outputs[0].datum else {
+            panic!("expected an inline datum");
+        };
+        assert_eq!(cbor, &hex::encode(CONSTR_0));
+        assert_eq!(hash, &Hasher::<256>::hash(&CONSTR_0).to_string());
+
+        let Some(DatumReport::Hash { hash }) = &report.outputs[1].datum else {
+            panic!("expected a datum hash");
+        };
+        assert_eq!(hash, &"22".repeat(32));
+        assert_eq!(report.outputs[1].address, testing::VENDOR);
+        assert_eq!(report.outputs[1].lovelace, 3_000_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_mint_and_validity() -> anyhow::Result<()> {
+        let (tx, _) = report_tx()?;
+        let report = TxReport::from_cbor(&tx, None)?;
+
+        let minted = &report.mint[&hex::encode(POLICY)];
+        assert_eq!(minted[&hex::encode("MINT")], 5);
+        assert_eq!(minted[&hex::encode("BURN")], -1);
+
+        assert_eq!(report.validity.invalid_before, Some(10));
+        assert_eq!(report.validity.invalid_hereafter, Some(20));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_input_values_when_known() -> anyhow::Result<()> {
+        let (tx, utxos) = report_tx()?;
+
+        let report = TxReport::from_cbor(&tx, Some(&utxos))?;
+        let value = report.inputs[0].value.as_ref().expect("known input");
+        assert_eq!(value.lovelace, 10_000_000);
+        assert!(value.assets.is_empty());
+
+        let report = TxReport::from_cbor(&tx, None)?;
+        assert!(report.inputs[0].value.is_none());
+        assert!(report.reference_inputs.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+//! Soft locks on the inputs of resolved txs, so that concurrent resolves
+//! don't select the same utxos while the first tx is on its way to a
+//! confirmed snapshot.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
+
+use crate::{
+    hydra::model::{TxID, Utxo},
+    ledger::txid,
+};
+
+struct Reservation {
+    tx_hash: String,
+    expires_at: Instant,
+}
+
+pub struct Reservations {
+    ttl: Duration,
+    by_input: Mutex<HashMap<TxID, Reservation>>,
+}
+
+impl Reservations {
+    /// A zero ttl disables reservations
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            by_input: Default::default(),
+        }
+    }
+
+    /// Inputs currently reserved, expired reservations are dropped on the way
+    pub fn reserved(&self) -> HashSet<TxID> {
+        let mut by_input = self.by_input.lock().unwrap();
+        let now = Instant::now();
+        by_input.retain(|_, reservation| reservation.expires_at > now);
+        by_input.keys().cloned().collect()
+    }
+
+    /// Reserves the inputs of a freshly resolved tx. Returns false, without
+    /// reserving anything, if another resolve got hold of one of them first.
+    pub fn try_reserve(&self, cbor: &[u8]) -> bool {
+        if self.ttl.is_zero() {
+            return true;
+        }
+
+        let Ok(tx) = MultiEraTx::decode(cbor) else {
+            return true;
+        };
+
+        let tx_hash = tx.hash().to_string();
+        let inputs: Vec<_> = tx
+            .inputs()
+            .iter()
+            .map(|input| txid(input.hash(), input.index()))
+            .collect();
+
+        let now = Instant::now();
+        let mut by_input = self.by_input.lock().unwrap();
+
+        let taken = inputs.iter().any(|input| {
+            by_input.get(input).is_some_and(|reservation| {
+                reservation.expires_at > now && reservation.tx_hash != tx_hash
+            })
+        });
+
+        if taken {
+            return false;
+        }
+
+        for input in inputs {
+            by_input.insert(
+                input,
+                Reservation {
+                    tx_hash: tx_hash.clone(),
+                    expires_at: now + self.ttl,
+                },
+            );
+        }
+
+        debug!(tx_hash, "inputs reserved");
+        true
+    }
+
+    /// Releases the inputs reserved for a tx
+    pub fn release(&self, tx_hash: &str) {
+        let mut by_input = self.by_input.lock().unwrap();
+        let before = by_input.len();
+        by_input.retain(|_, reservation| reservation.tx_hash != tx_hash);
+
+        if by_input.len() != before {
+            debug!(tx_hash, "reservation released");
+        }
+    }
+
+    /// Releases the inputs a confirmed snapshot no longer holds, their txs
+    /// went through
+    pub fn release_spent(&self, utxos: &HashMap<TxID, Utxo>) {
+        let mut by_input = self.by_input.lock().unwrap();
+        let before = by_input.len();
+        by_input.retain(|input, _| utxos.contains_key(input));
+
+        if by_input.len() != before {
+            debug!(released = before - by_input.len(), "spent inputs released");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{payment::build_payment, testing};
+
+    fn utxos() -> HashMap<TxID, Utxo> {
+        HashMap::from([
+            (
+                txid("00".repeat(32), 0),
+                testing::lovelace_utxo(testing::USER, 100_000_000),
+            ),
+            (
+                txid("00".repeat(32), 1),
+                testing::lovelace_utxo(testing::USER, 50_000_000),
+            ),
+        ])
+    }
+
+    /// Payment spending the largest utxo of the user
+    fn payment(amount: u64) -> Vec<u8> {
+        build_payment(
+            &utxos(),
+            testing::USER,
+            testing::VENDOR,
+            amount,
+            &testing::pparams(),
+        )
+        .unwrap()
+        .cbor
+    }
+
+    #[test]
+    fn keeps_reserved_inputs_from_other_txs() {
+        let reservations = Reservations::new(Duration::from_secs(60));
+
+        assert!(reservations.try_reserve(&payment(5_000_000)));
+        assert!(reservations.reserved().contains(&txid("00".repeat(32), 0)));
+
+        // same inputs, another tx
+        assert!(!reservations.try_reserve(&payment(6_000_000)));
+        // the same tx again
+        assert!(reservations.try_reserve(&payment(5_000_000)));
+    }
+
+    #[test]
+    fn releases_invalid_txs() {
+        let reservations = Reservations::new(Duration::from_secs(60));
+        let cbor = payment(5_000_000);
+        reservations.try_reserve(&cbor);
+
+        let hash = MultiEraTx::decode(&cbor).unwrap().hash().to_string();
+        reservations.release(&hash);
+
+        assert!(reservations.reserved().is_empty());
+    }
+
+    #[test]
+    fn releases_inputs_once_spent() {
+        let reservations = Reservations::new(Duration::from_secs(60));
+        reservations.try_reserve(&payment(5_000_000));
+
+        // still in the snapshot, the tx isn't confirmed yet
+        reservations.release_spent(&utxos());
+        assert_eq!(reservations.reserved().len(), 1);
+
+        let mut confirmed = utxos();
+        confirmed.remove(&txid("00".repeat(32), 0));
+        reservations.release_spent(&confirmed);
+        assert!(reservations.reserved().is_empty());
+    }
+
+    #[test]
+    fn expires_reservations() {
+        let reservations = Reservations::new(Duration::from_millis(1));
+        reservations.try_reserve(&payment(5_000_000));
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(reservations.reserved().is_empty());
+        assert!(reservations.try_reserve(&payment(6_000_000)));
+    }
+}
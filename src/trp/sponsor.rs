@@ -0,0 +1,398 @@
+//! Paymaster for head txs. Sponsored txs are built by the server during
+//! resolve and co-signed on submit, and only txs built here are ever signed
+//! with the sponsor key. Each sponsored address has a lovelace budget and a
+//! tx count per time window, and all of them share a global budget.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, ensure};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use tx3_cardano::{PParams, pallas::ledger::traverse::MultiEraTx};
+
+use crate::{
+    hydra::model::{AssetValue, ExUnitPrices, TxID, Utxo},
+    ledger::{self, Violation, sponsor::sponsor_tx, witness},
+    wallet::{Wallet, Wallets},
+};
+
+/// How long a sponsored tx may wait for its user witnesses
+const SPONSORSHIP_TTL: Duration = Duration::from_secs(600);
+
+fn default_window_secs() -> u64 {
+    86_400
+}
+
+fn default_max_top_up_per_output() -> u64 {
+    2_000_000
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Config {
+    /// Name of the wallet that pays for sponsored txs
+    pub wallet: String,
+    /// Address of the sponsor utxos, its payment key must be the wallet's
+    pub address: String,
+    /// Max lovelace a single tx may cost the sponsor
+    pub max_cost_per_tx: u64,
+    /// Lovelace each sponsored address may spend per window
+    pub budget_per_address: u64,
+    /// Sponsored txs each address may submit per window
+    pub max_txs_per_address: u32,
+    /// Lovelace all sponsored txs together may spend per window
+    pub budget_per_window: u64,
+    /// Max lovelace added to an output below the min utxo, outputs paying
+    /// back to the sponsor aside
+    #[serde(default = "default_max_top_up_per_output")]
+    pub max_top_up_per_output: u64,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+/// Sponsored tx waiting for registration
+pub struct Prepared {
+    pub cbor: Vec<u8>,
+    address: String,
+    cost: u64,
+}
+
+struct Sponsorship {
+    address: String,
+    cost: u64,
+    expires_at: Instant,
+}
+
+struct Usage {
+    window_start: Instant,
+    spent: u64,
+    txs: u32,
+}
+
+impl Usage {
+    fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            spent: 0,
+            txs: 0,
+        }
+    }
+
+    /// Starts over once the window is over
+    fn roll(&mut self, window: Duration) {
+        if self.window_start.elapsed() >= window {
+            *self = Usage::new();
+        }
+    }
+}
+
+pub struct Sponsor {
+    config: Config,
+    sponsored: Mutex<HashMap<String, Sponsorship>>,
+    usage: Mutex<HashMap<String, Usage>>,
+    /// Usage of all the sponsored addresses together
+    total: Mutex<Usage>,
+}
+
+impl Sponsor {
+    pub fn try_new(config: Config, wallets: &Wallets) -> anyhow::Result<Self> {
+        let wallet = wallets
+            .get(&config.wallet)
+            .with_context(|| format!("sponsor wallet {} is not configured", config.wallet))?;
+
+        wallet
+            .ensure_owns(&config.address)
+            .with_context(|| format!("checking sponsor address of wallet {}", config.wallet))?;
+
+        Ok(Self {
+            config,
+            sponsored: Default::default(),
+            usage: Default::default(),
+            total: Mutex::new(Usage::new()),
+        })
+    }
+
+    /// Address the sponsor utxos and change are at
+    pub fn address(&self) -> &str {
+        &self.config.address
+    }
+
+    fn wallet<'a>(&self, wallets: &'a Wallets) -> &'a Wallet {
+        // checked on startup
+        wallets.get(&self.config.wallet).unwrap()
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs)
+    }
+
+    /// Fails if the sponsor or the address have no budget left for a tx of
+    /// the given cost
+    fn check_budget(&self, address: &str, cost: u64) -> anyhow::Result<()> {
+        ensure!(
+            cost <= self.config.max_cost_per_tx,
+            "tx would cost the sponsor {cost}, over the {} limit",
+            self.config.max_cost_per_tx
+        );
+
+        {
+            let mut total = self.total.lock().unwrap();
+            total.roll(self.window());
+            ensure!(
+                total.spent + cost <= self.config.budget_per_window,
+                "sponsor budget for this window is spent"
+            );
+        }
+
+        let mut usage = self.usage.lock().unwrap();
+        let Some(usage) = usage.get_mut(address) else {
+            return Ok(());
+        };
+
+        usage.roll(self.window());
+
+        ensure!(
+            usage.txs < self.config.max_txs_per_address,
+            "{address} reached its sponsored tx limit"
+        );
+        ensure!(
+            usage.spent + cost <= self.config.budget_per_address,
+            "{address} ran out of sponsor budget"
+        );
+
+        Ok(())
+    }
+
+    fn charge(&self, address: &str, cost: u64) {
+        let mut total = self.total.lock().unwrap();
+        total.roll(self.window());
+        total.spent += cost;
+        total.txs += 1;
+
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(address.to_string()).or_insert_with(Usage::new);
+
+        usage.roll(self.window());
+        usage.spent += cost;
+        usage.txs += 1;
+    }
+
+    /// Largest lovelace-only sponsor utxo not held by another resolve
+    fn pick_utxo<'a>(
+        &self,
+        utxos: &'a HashMap<TxID, Utxo>,
+        reserved: &HashSet<TxID>,
+    ) -> Option<(&'a TxID, &'a Utxo)> {
+        utxos
+            .iter()
+            .filter(|(id, utxo)| utxo.address == self.config.address && !reserved.contains(*id))
+            .filter(|(_, utxo)| {
+                utxo.value
+                    .assets
+                    .values()
+                    .all(|value| matches!(value, AssetValue::Lovelace(_)))
+            })
+            .max_by_key(|(_, utxo)| match utxo.value.assets.get("lovelace") {
+                Some(AssetValue::Lovelace(amount)) => *amount,
+                _ => 0,
+            })
+    }
+
+    /// Address the sponsorship is accounted to, the one of the first input
+    /// that isn't the sponsor's
+    fn sponsored_address(
+        &self,
+        tx: &MultiEraTx,
+        utxos: &HashMap<TxID, Utxo>,
+    ) -> anyhow::Result<String> {
+        tx.inputs()
+            .iter()
+            .filter_map(|input| utxos.get(&ledger::txid(input.hash(), input.index())))
+            .map(|utxo| utxo.address.clone())
+            .find(|address| *address != self.config.address)
+            .context("tx has no user inputs to sponsor")
+    }
+
+    /// Adds a sponsor input paying for the tx. The result has to be
+    /// registered once final for [`Sponsor::cosign`] to sign it.
+    pub fn prepare(
+        &self,
+        cbor: &[u8],
+        utxos: &HashMap<TxID, Utxo>,
+        reserved: &HashSet<TxID>,
+        pparams: &PParams,
+        prices: &ExUnitPrices,
+    ) -> anyhow::Result<Prepared> {
+        let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+        let address = self.sponsored_address(&tx, utxos)?;
+
+        let (id, utxo) = self
+            .pick_utxo(utxos, reserved)
+            .context("no sponsor utxo available")?;
+
+        let (hash, index) = id.split_once('#').context("malformed sponsor utxo id")?;
+        let hash = hex::decode(hash).context("decoding sponsor utxo hash")?;
+        let index = index.parse().context("decoding sponsor utxo index")?;
+
+        let sponsored = sponsor_tx(cbor, utxos, &hash, index, utxo, pparams, prices)?;
+        ensure!(
+            sponsored.largest_top_up <= self.config.max_top_up_per_output,
+            "an output would need a top-up of {}, over the {} limit",
+            sponsored.largest_top_up,
+            self.config.max_top_up_per_output
+        );
+        self.check_budget(&address, sponsored.cost)?;
+
+        Ok(Prepared {
+            cbor: sponsored.cbor,
+            address,
+            cost: sponsored.cost,
+        })
+    }
+
+    /// Allows the final form of a prepared tx to be co-signed. Script
+    /// evaluation may have raised the fee out of the sponsor change since it
+    /// was prepared, the budget is checked again for the final cost.
+    pub fn register(&self, cbor: &[u8], prepared: Prepared) -> anyhow::Result<()> {
+        let fee = |cbor: &[u8]| -> anyhow::Result<u64> {
+            let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+            Ok(tx.fee().unwrap_or_default())
+        };
+
+        let cost = prepared.cost + fee(cbor)?.saturating_sub(fee(&prepared.cbor)?);
+        self.check_budget(&prepared.address, cost)?;
+
+        let tx_hash = witness::body_hash(cbor)?.to_string();
+
+        let mut registry = self.sponsored.lock().unwrap();
+        registry.retain(|_, sponsorship| sponsorship.expires_at > Instant::now());
+        registry.insert(
+            tx_hash,
+            Sponsorship {
+                address: prepared.address,
+                cost,
+                expires_at: Instant::now() + SPONSORSHIP_TTL,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Signs a tx built by [`Sponsor::prepare`] once every other witness is
+    /// in place. Txs that weren't sponsored here are returned unchanged.
+    pub fn cosign(
+        &self,
+        cbor: Vec<u8>,
+        wallets: &Wallets,
+        utxos: &HashMap<TxID, Utxo>,
+        pparams: &PParams,
+    ) -> anyhow::Result<Vec<u8>> {
+        let tx_hash = witness::body_hash(&cbor)?.to_string();
+
+        let Some(sponsorship) = self.sponsored.lock().unwrap().remove(&tx_hash) else {
+            return Ok(cbor);
+        };
+
+        let result = self.try_cosign(&cbor, &sponsorship, wallets, utxos, pparams);
+
+        if result.is_err() {
+            // keep it around for a retry with the missing witnesses
+            self.sponsored.lock().unwrap().insert(tx_hash, sponsorship);
+        }
+
+        result
+    }
+
+    fn try_cosign(
+        &self,
+        cbor: &[u8],
+        sponsorship: &Sponsorship,
+        wallets: &Wallets,
+        utxos: &HashMap<TxID, Utxo>,
+        pparams: &PParams,
+    ) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            sponsorship.expires_at > Instant::now(),
+            "sponsorship expired"
+        );
+
+        let wallet = self.wallet(wallets);
+        let sponsor_key = wallet.key_hash().to_string();
+
+        let tx = MultiEraTx::decode(cbor).context("decoding tx")?;
+        let missing: Vec<_> = ledger::validate(utxos, &tx, pparams)
+            .into_iter()
+            .filter_map(|violation| match violation {
+                Violation::MissingWitness { key_hash } if key_hash != sponsor_key => Some(key_hash),
+                _ => None,
+            })
+            .collect();
+
+        ensure!(
+            missing.is_empty(),
+            "missing user witnesses for {}",
+            missing.join(", ")
+        );
+
+        self.check_budget(&sponsorship.address, sponsorship.cost)?;
+
+        let body_hash = witness::body_hash(cbor)?;
+        let signed = witness::add_vkey_witnesses(cbor, &[wallet.witness(&body_hash)])?;
+
+        self.charge(&sponsorship.address, sponsorship.cost);
+        info!(
+            address = sponsorship.address,
+            cost = sponsorship.cost,
+            "tx sponsored"
+        );
+
+        Ok(signed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing;
+
+    fn sponsor() -> Sponsor {
+        Sponsor {
+            config: Config {
+                wallet: "sponsor".into(),
+                address: testing::VENDOR.into(),
+                max_cost_per_tx: 1_000_000,
+                budget_per_address: 2_000_000,
+                max_txs_per_address: 10,
+                budget_per_window: 3_000_000,
+                max_top_up_per_output: default_max_top_up_per_output(),
+                window_secs: default_window_secs(),
+            },
+            sponsored: Default::default(),
+            usage: Default::default(),
+            total: Mutex::new(Usage::new()),
+        }
+    }
+
+    #[test]
+    fn limits_the_spending_of_each_address() {
+        let sponsor = sponsor();
+        sponsor.charge("alice", 1_000_000);
+        sponsor.charge("alice", 1_000_000);
+
+        assert!(sponsor.check_budget("alice", 1).is_err());
+        assert!(sponsor.check_budget("bob", 1_000_000).is_ok());
+    }
+
+    #[test]
+    fn limits_the_spending_of_all_addresses_together() {
+        let sponsor = sponsor();
+        sponsor.charge("alice", 1_000_000);
+        sponsor.charge("bob", 1_000_000);
+        sponsor.charge("carol", 1_000_000);
+
+        assert!(sponsor.check_budget("dave", 1).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! UTxO RPC (v1alpha) gRPC services over the head, so that UTxO RPC clients
+//! can use it as they would a chain. Queries are served from the snapshot,
+//! submits go through the `trp.submit` path and the streams follow the
+//! backend events.
+
+// tonic services fail with a `Status`, which the helpers pass through as is
+#![allow(clippy::result_large_err)]
+
+use std::{pin::Pin, sync::Arc};
+
+use futures_util::{Stream, stream};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tonic::{Status, transport::Server};
+use tracing::{error, info};
+use tx3_cardano::pallas::{
+    interop::utxorpc::{LedgerContext, Mapper, TxoRef, UtxoMap},
+    ledger::{
+        addresses::{Address, ShelleyDelegationPart},
+        traverse::{Era, MultiEraOutput},
+    },
+};
+use utxorpc_spec::utxorpc::v1alpha::{
+    cardano::{AddressPattern, AssetPattern, TxOutputPattern},
+    query::{self, query_service_server::QueryServiceServer},
+    submit::submit_service_server::SubmitServiceServer,
+    watch::watch_service_server::WatchServiceServer,
+};
+
+use crate::{
+    backend::Backend,
+    hydra::model::{AssetValue, Event, TxID, Utxo},
+    ledger::{self, cbor, time::SlotConfig},
+    trp::{Context, ListenerConfig},
+};
+
+mod query_service;
+mod submit_service;
+mod watch_service;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Ledger lookups of the pallas mapper. Inputs aren't resolved, the head
+/// only keeps the current utxo set.
+#[derive(Clone)]
+struct HeadLedger {
+    slot_config: SlotConfig,
+}
+
+impl LedgerContext for HeadLedger {
+    fn get_utxos(&self, _refs: &[TxoRef]) -> Option<UtxoMap> {
+        None
+    }
+
+    fn get_slot_timestamp(&self, slot: u64) -> Option<u64> {
+        Some(self.slot_config.time_of(slot) / 1000)
+    }
+}
+
+fn mapper<B: Backend>(context: &Context<B>) -> Mapper<HeadLedger> {
+    Mapper::new(HeadLedger {
+        slot_config: context.backend.slot_config(),
+    })
+}
+
+fn internal(error: anyhow::Error) -> Status {
+    error!(?error);
+    Status::internal(format!("{error:#}"))
+}
+
+/// Backend events until the channel closes. A subscriber that lags behind
+/// gets a `DATA_LOSS` error and no more events, rather than a silent gap.
+fn events(rx: broadcast::Receiver<Event>) -> impl Stream<Item = Result<Event, Status>> + Send {
+    stream::unfold(Some(rx), |rx| async move {
+        let mut rx = rx?;
+
+        match rx.recv().await {
+            Ok(event) => Some((Ok(event), Some(rx))),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => Some((
+                Err(Status::data_loss(format!("missed {skipped} head events"))),
+                None,
+            )),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    })
+}
+
+fn txid_of(r#ref: &query::TxoRef) -> TxID {
+    ledger::txid(hex::encode(&r#ref.hash), r#ref.index as u64)
+}
+
+fn txo_ref(r#ref: &TxID) -> query::TxoRef {
+    let (hash, index) = r#ref.split_once('#').unwrap_or((r#ref.as_str(), "0"));
+
+    query::TxoRef {
+        hash: hex::decode(hash).unwrap_or_default().into(),
+        index: index.parse().unwrap_or_default(),
+    }
+}
+
+/// Utxo with its ledger cbor and its parsed form
+fn any_utxo(
+    mapper: &Mapper<HeadLedger>,
+    r#ref: &TxID,
+    utxo: &Utxo,
+) -> Result<query::AnyUtxoData, Status> {
+    let native = cbor::encode_output(utxo).map_err(internal)?;
+    let output = MultiEraOutput::decode(Era::Conway, &native)
+        .map_err(|error| internal(anyhow::anyhow!(error)))?;
+    let parsed = mapper.map_tx_output(&output, None);
+
+    Ok(query::AnyUtxoData {
+        native_bytes: native.into(),
+        txo_ref: Some(txo_ref(r#ref)),
+        parsed_state: Some(query::any_utxo_data::ParsedState::Cardano(parsed)),
+        ..Default::default()
+    })
+}
+
+/// The last snapshot, as the ledger tip
+async fn ledger_tip<B: Backend>(context: &Context<B>) -> Result<query::ChainPoint, Status> {
+    let chain_point = context.backend.chain_point().await.map_err(internal)?;
+
+    Ok(query::ChainPoint {
+        slot: chain_point.slot,
+        hash: chain_point.hash.into(),
+        timestamp: chain_point.timestamp as u64,
+        ..Default::default()
+    })
+}
+
+fn address_matches(pattern: &AddressPattern, address: &str) -> bool {
+    let Ok(address) = address.parse::<Address>() else {
+        return false;
+    };
+
+    if !pattern.exact_address.is_empty() && pattern.exact_address[..] != address.to_vec()[..] {
+        return false;
+    }
+
+    let (payment, delegation) = match &address {
+        Address::Shelley(address) => {
+            let delegation = match address.delegation() {
+                ShelleyDelegationPart::Key(hash) | ShelleyDelegationPart::Script(hash) => {
+                    Some(hash.to_vec())
+                }
+                _ => None,
+            };
+            (Some(address.payment().as_hash().to_vec()), delegation)
+        }
+        _ => (None, None),
+    };
+
+    (pattern.payment_part.is_empty() || payment.as_deref() == Some(&pattern.payment_part[..]))
+        && (pattern.delegation_part.is_empty()
+            || delegation.as_deref() == Some(&pattern.delegation_part[..]))
+}
+
+fn asset_matches(pattern: &AssetPattern, utxo: &Utxo) -> bool {
+    let policy = hex::encode(&pattern.policy_id);
+    let name = hex::encode(&pattern.asset_name);
+
+    utxo.value.assets.iter().any(|(policy_id, value)| {
+        let AssetValue::Multi(assets) = value else {
+            return false;
+        };
+
+        (policy.is_empty() || *policy_id == policy)
+            && (name.is_empty() || assets.contains_key(&name))
+    })
+}
+
+fn output_matches(pattern: &TxOutputPattern, utxo: &Utxo) -> bool {
+    pattern
+        .address
+        .as_ref()
+        .is_none_or(|address| address_matches(address, &utxo.address))
+        && pattern
+            .asset
+            .as_ref()
+            .is_none_or(|asset| asset_matches(asset, utxo))
+}
+
+/// Serves the query, submit and watch modules until cancelled
+pub(crate) async fn serve<B: Backend>(
+    config: &ListenerConfig,
+    context: Arc<Context<B>>,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let address = config.listen_address.parse()?;
+
+    info!(address = config.listen_address, "UTxO RPC server running");
+
+    Server::builder()
+        .add_service(QueryServiceServer::new(query_service::QueryService(
+            Arc::clone(&context),
+        )))
+        .add_service(SubmitServiceServer::new(submit_service::SubmitService(
+            Arc::clone(&context),
+        )))
+        .add_service(WatchServiceServer::new(watch_service::WatchService(
+            context,
+        )))
+        .serve_with_shutdown(address, cancellation_token.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
+use utxorpc_spec::utxorpc::v1alpha::{
+    cardano::{self, big_int},
+    query::{self, any_chain_params, any_chain_tx, any_utxo_pattern, query_service_server},
+};
+
+use super::{any_utxo, internal, ledger_tip, mapper, output_matches, txid_of};
+use crate::{
+    backend::Backend,
+    hydra::model::{Ratio, Utxo},
+    trp::{
+        Context,
+        methods::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+    },
+};
+
+pub struct QueryService<B: Backend>(pub Arc<Context<B>>);
+
+fn big_int(value: u64) -> Option<cardano::BigInt> {
+    Some(cardano::BigInt {
+        big_int: Some(big_int::BigInt::Int(value as i64)),
+    })
+}
+
+fn rational(ratio: Ratio) -> Option<cardano::RationalNumber> {
+    Some(cardano::RationalNumber {
+        numerator: ratio.numerator as i32,
+        denominator: ratio.denominator as u32,
+    })
+}
+
+/// Whether the utxo satisfies the predicate, patterns of other chains never match
+fn predicate_matches(predicate: &query::UtxoPredicate, utxo: &Utxo) -> bool {
+    let matched = predicate
+        .r#match
+        .as_ref()
+        .is_none_or(|pattern| match &pattern.utxo_pattern {
+            Some(any_utxo_pattern::UtxoPattern::Cardano(pattern)) => output_matches(pattern, utxo),
+            _ => false,
+        });
+
+    matched
+        && !predicate.not.iter().any(|not| predicate_matches(not, utxo))
+        && predicate
+            .all_of
+            .iter()
+            .all(|all| predicate_matches(all, utxo))
+        && (predicate.any_of.is_empty()
+            || predicate
+                .any_of
+                .iter()
+                .any(|any| predicate_matches(any, utxo)))
+}
+
+#[tonic::async_trait]
+impl<B: Backend> query_service_server::QueryService for QueryService<B> {
+    async fn read_params(
+        &self,
+        _request: Request<query::ReadParamsRequest>,
+    ) -> Result<Response<query::ReadParamsResponse>, Status> {
+        let pparams = self.0.backend.get_pparams().await.map_err(internal)?;
+        let head = self
+            .0
+            .backend
+            .protocol_parameters()
+            .await
+            .map_err(internal)?;
+
+        let cost_model = |version: u8| {
+            pparams
+                .cost_models
+                .get(&version)
+                .map(|values| cardano::CostModel {
+                    values: values.clone(),
+                })
+        };
+
+        let params = cardano::PParams {
+            coins_per_utxo_byte: big_int(pparams.coins_per_utxo_byte),
+            max_tx_size: head.max_tx_size,
+            min_fee_coefficient: big_int(pparams.min_fee_coefficient),
+            min_fee_constant: big_int(pparams.min_fee_constant),
+            stake_key_deposit: big_int(head.stake_address_deposit),
+            pool_deposit: big_int(head.stake_pool_deposit),
+            max_value_size: head.max_value_size,
+            collateral_percentage: head.collateral_percentage,
+            max_collateral_inputs: head.max_collateral_inputs,
+            cost_models: Some(cardano::CostModels {
+                plutus_v1: cost_model(0),
+                plutus_v2: cost_model(1),
+                plutus_v3: cost_model(2),
+            }),
+            prices: Some(cardano::ExPrices {
+                memory: rational(head.execution_unit_prices.price_memory),
+                steps: rational(head.execution_unit_prices.price_steps),
+            }),
+            max_execution_units_per_transaction: Some(cardano::ExUnits {
+                memory: head.max_tx_execution_units.memory,
+                steps: head.max_tx_execution_units.steps,
+            }),
+            min_fee_script_ref_cost_per_byte: rational(head.min_fee_ref_script_cost_per_byte),
+            ..Default::default()
+        };
+
+        Ok(Response::new(query::ReadParamsResponse {
+            values: Some(query::AnyChainParams {
+                params: Some(any_chain_params::Params::Cardano(params)),
+            }),
+            ledger_tip: Some(ledger_tip(&self.0).await?),
+        }))
+    }
+
+    async fn read_utxos(
+        &self,
+        request: Request<query::ReadUtxosRequest>,
+    ) -> Result<Response<query::ReadUtxosResponse>, Status> {
+        let request = request.into_inner();
+        let mapper = mapper(&self.0);
+
+        let items = {
+            let snapshot = self.0.backend.read_utxos().await;
+
+            request
+                .keys
+                .iter()
+                .map(txid_of)
+                .filter_map(|r#ref| {
+                    let utxo = snapshot.0.get(&r#ref)?;
+                    Some(any_utxo(&mapper, &r#ref, utxo))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        Ok(Response::new(query::ReadUtxosResponse {
+            items,
+            ledger_tip: Some(ledger_tip(&self.0).await?),
+        }))
+    }
+
+    async fn search_utxos(
+        &self,
+        request: Request<query::SearchUtxosRequest>,
+    ) -> Result<Response<query::SearchUtxosResponse>, Status> {
+        let request = request.into_inner();
+        let mapper = mapper(&self.0);
+
+        let limit = match request.max_items {
+            0 => DEFAULT_PAGE_SIZE,
+            max_items if max_items < 0 || max_items as usize > MAX_PAGE_SIZE => {
+                return Err(Status::invalid_argument(format!(
+                    "max_items must be between 1 and {MAX_PAGE_SIZE}"
+                )));
+            }
+            max_items => max_items as usize,
+        };
+
+        let (items, next_token) = {
+            let snapshot = self.0.backend.read_utxos().await;
+
+            let mut matching: Vec<_> = snapshot
+                .0
+                .iter()
+                .filter(|(r#ref, _)| {
+                    request.start_token.is_empty() || **r#ref > request.start_token
+                })
+                .filter(|(_, utxo)| {
+                    request
+                        .predicate
+                        .as_ref()
+                        .is_none_or(|predicate| predicate_matches(predicate, utxo))
+                })
+                .collect();
+
+            // refs are unique, so ordering by them gives stable pages
+            matching.sort_unstable_by_key(|(r#ref, _)| *r#ref);
+
+            let next_token = if matching.len() > limit {
+                matching[limit - 1].0.clone()
+            } else {
+                String::new()
+            };
+
+            let items = matching
+                .into_iter()
+                .take(limit)
+                .map(|(r#ref, utxo)| any_utxo(&mapper, r#ref, utxo))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            (items, next_token)
+        };
+
+        Ok(Response::new(query::SearchUtxosResponse {
+            items,
+            ledger_tip: Some(ledger_tip(&self.0).await?),
+            next_token,
+        }))
+    }
+
+    /// Txs are looked up in the tx history, only those whose cbor the server
+    /// saw can be returned
+    async fn read_tx(
+        &self,
+        request: Request<query::ReadTxRequest>,
+    ) -> Result<Response<query::ReadTxResponse>, Status> {
+        let hash = hex::encode(request.into_inner().hash);
+
+        let cbor = self
+            .0
+            .history
+            .get(&hash)
+            .and_then(|record| record.cbor)
+            .ok_or_else(|| Status::not_found(format!("tx {hash} not in the history")))?;
+
+        let native = hex::decode(cbor).map_err(|error| internal(error.into()))?;
+        let tx = MultiEraTx::decode(&native).map_err(|error| internal(error.into()))?;
+        let parsed = mapper(&self.0).map_tx(&tx);
+
+        Ok(Response::new(query::ReadTxResponse {
+            tx: Some(query::AnyChainTx {
+                chain: Some(any_chain_tx::Chain::Cardano(parsed)),
+                native_bytes: native.into(),
+                block_ref: None,
+            }),
+            ledger_tip: Some(ledger_tip(&self.0).await?),
+        }))
+    }
+
+    async fn read_data(
+        &self,
+        _request: Request<query::ReadDataRequest>,
+    ) -> Result<Response<query::ReadDataResponse>, Status> {
+        Err(Status::unimplemented("datums are only served inline"))
+    }
+
+    async fn read_genesis(
+        &self,
+        _request: Request<query::ReadGenesisRequest>,
+    ) -> Result<Response<query::ReadGenesisResponse>, Status> {
+        Err(Status::unimplemented("heads have no genesis"))
+    }
+
+    async fn read_era_summary(
+        &self,
+        _request: Request<query::ReadEraSummaryRequest>,
+    ) -> Result<Response<query::ReadEraSummaryResponse>, Status> {
+        Err(Status::unimplemented("heads have no eras"))
+    }
+}
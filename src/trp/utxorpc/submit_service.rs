@@ -0,0 +1,328 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use futures_util::{StreamExt, future, stream};
+use jsonrpsee::types::{ErrorCode, ErrorObjectOwned};
+use tonic::{Request, Response, Status};
+use utxorpc_spec::utxorpc::v1alpha::submit::{self, any_chain_tx, submit_service_server};
+
+use super::{ResponseStream, events};
+use crate::{
+    backend::Backend,
+    hydra::model::Event,
+    trp::{Context, history::TxStatus, methods::submit::submit_and_wait},
+};
+
+pub struct SubmitService<B: Backend>(pub Arc<Context<B>>);
+
+fn invalid_tx(tx_id: &str, reason: &str) -> Status {
+    Status::failed_precondition(format!("tx {tx_id} is invalid: {reason}"))
+}
+
+fn status(error: ErrorObjectOwned) -> Status {
+    let message = match error.data() {
+        Some(data) => format!("{}: {}", error.message(), data.get()),
+        None => error.message().to_string(),
+    };
+
+    match ErrorCode::from(error.code()) {
+        ErrorCode::InvalidParams | ErrorCode::InvalidRequest | ErrorCode::ParseError => {
+            Status::invalid_argument(message)
+        }
+        ErrorCode::ServerIsBusy => Status::unavailable(message),
+        _ => Status::internal(message),
+    }
+}
+
+#[tonic::async_trait]
+impl<B: Backend> submit_service_server::SubmitService for SubmitService<B> {
+    type WaitForTxStream = ResponseStream<submit::WaitForTxResponse>;
+    type WatchMempoolStream = ResponseStream<submit::WatchMempoolResponse>;
+
+    /// Answers once the head accepted or rejected the tx, like `trp.submit`
+    async fn submit_tx(
+        &self,
+        request: Request<submit::SubmitTxRequest>,
+    ) -> Result<Response<submit::SubmitTxResponse>, Status> {
+        let raw = match request.into_inner().tx.and_then(|tx| tx.r#type) {
+            Some(any_chain_tx::Type::Raw(raw)) => raw.to_vec(),
+            None => return Err(Status::invalid_argument("missing tx")),
+        };
+
+        let context = &self.0;
+        context.ensure_accepting().map_err(status)?;
+
+        let submits = context.submits.clone();
+        let response = submits
+            .track_future(async { submit_and_wait(context, raw).await })
+            .await
+            .map_err(|error| status(error.into()))?;
+
+        let hash = response["hash"].as_str().unwrap_or_default();
+
+        Ok(Response::new(submit::SubmitTxResponse {
+            r#ref: hex::decode(hash).unwrap_or_default().into(),
+        }))
+    }
+
+    /// Reports `MEMPOOL` once the head accepted a tx and `CONFIRMED` once a
+    /// snapshot includes it, rejected txs end the stream with an error. The
+    /// stream ends once every tx is confirmed or rejected. Txs that got there
+    /// before the call are reported first, from the history or, for txs it
+    /// doesn't know, from their outputs in the current snapshot.
+    async fn wait_for_tx(
+        &self,
+        request: Request<submit::WaitForTxRequest>,
+    ) -> Result<Response<Self::WaitForTxStream>, Status> {
+        let mut pending: BTreeSet<String> =
+            request.into_inner().r#ref.iter().map(hex::encode).collect();
+
+        if pending.is_empty() {
+            return Err(Status::invalid_argument("no tx refs to wait for"));
+        }
+
+        // subscribed before looking back, so nothing falls in between
+        let events = events(self.0.backend.events());
+
+        let mut known = Vec::new();
+        {
+            let utxos = self.0.backend.read_utxos().await;
+            for tx_id in pending.clone() {
+                let prefix = format!("{tx_id}#");
+                let in_snapshot = || utxos.0.keys().any(|r#ref| r#ref.starts_with(&prefix));
+
+                let record = self.0.history.get(&tx_id);
+                match record.map(|record| (record.status, record.reason)) {
+                    Some((TxStatus::Valid, _)) => {
+                        known.push(Ok((tx_id, submit::Stage::Mempool)));
+                    }
+                    Some((TxStatus::Invalid, reason)) => {
+                        pending.remove(&tx_id);
+                        known.push(Err(invalid_tx(&tx_id, &reason.unwrap_or_default())));
+                    }
+                    Some((TxStatus::Confirmed, _)) => {
+                        pending.remove(&tx_id);
+                        known.push(Ok((tx_id, submit::Stage::Confirmed)));
+                    }
+                    _ if in_snapshot() => {
+                        pending.remove(&tx_id);
+                        known.push(Ok((tx_id, submit::Stage::Confirmed)));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // `None` marks the end, right after the update that settles the last tx
+        let settled = pending.is_empty();
+        let known = stream::iter(known.into_iter().map(Some).chain(settled.then_some(None)));
+
+        let live = events.flat_map(move |event| {
+            let updates: Vec<Result<_, Status>> = match event {
+                Err(status) => vec![Err(status)],
+                Ok(Event::TxValid { tx_id }) if pending.contains(&tx_id) => {
+                    vec![Ok((tx_id, submit::Stage::Mempool))]
+                }
+                Ok(Event::TxInvalid {
+                    transaction,
+                    validation_error,
+                }) if pending.remove(&transaction.tx_id) => {
+                    vec![Err(invalid_tx(
+                        &transaction.tx_id,
+                        &validation_error.reason,
+                    ))]
+                }
+                Ok(Event::SnapshotConfirmed { snapshot, .. }) => snapshot
+                    .confirmed
+                    .into_iter()
+                    .filter(|tx| pending.remove(&tx.tx_id))
+                    .map(|tx| Ok((tx.tx_id, submit::Stage::Confirmed)))
+                    .collect(),
+                Ok(_) => vec![],
+            };
+
+            let settled = pending.is_empty();
+            stream::iter(updates.into_iter().map(Some).chain(settled.then_some(None)))
+        });
+
+        let stream = known
+            .chain(live)
+            .take_while(|update| future::ready(update.is_some()))
+            .filter_map(future::ready)
+            .map(|update| {
+                update.map(|(tx_id, stage)| submit::WaitForTxResponse {
+                    r#ref: hex::decode(tx_id).unwrap_or_default().into(),
+                    stage: stage as i32,
+                })
+            });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    /// Streams the ids of the txs the head accepts, the tx bodies aren't
+    /// part of the hydra events
+    async fn watch_mempool(
+        &self,
+        request: Request<submit::WatchMempoolRequest>,
+    ) -> Result<Response<Self::WatchMempoolStream>, Status> {
+        if request.into_inner().predicate.is_some() {
+            return Err(Status::unimplemented(
+                "mempool predicates are not supported",
+            ));
+        }
+
+        let stream = events(self.0.backend.events()).filter_map(|event| async move {
+            let tx_id = match event {
+                Ok(Event::TxValid { tx_id }) => tx_id,
+                Ok(_) => return None,
+                Err(status) => return Some(Err(status)),
+            };
+
+            Some(Ok(submit::WatchMempoolResponse {
+                tx: Some(submit::TxInMempool {
+                    r#ref: hex::decode(tx_id).unwrap_or_default().into(),
+                    stage: submit::Stage::Mempool as i32,
+                    ..Default::default()
+                }),
+            }))
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn read_mempool(
+        &self,
+        _request: Request<submit::ReadMempoolRequest>,
+    ) -> Result<Response<submit::ReadMempoolResponse>, Status> {
+        Err(Status::unimplemented(
+            "the head applies txs right away, there is no mempool to read",
+        ))
+    }
+
+    async fn eval_tx(
+        &self,
+        _request: Request<submit::EvalTxRequest>,
+    ) -> Result<Response<submit::EvalTxResponse>, Status> {
+        Err(Status::unimplemented("use trp.evaluate to evaluate txs"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use anyhow::Context as _;
+    use serde_json::json;
+    use tokio_util::{sync::CancellationToken, task::TaskTracker};
+    use utxorpc_spec::utxorpc::v1alpha::submit::submit_service_server::SubmitService as _;
+
+    use super::*;
+    use crate::{
+        devnet::Devnet,
+        hydra::model::{ConfirmedTx, Snapshot, Transaction, ValidationError},
+        ledger::{testing, txid},
+        trp::{history::History, reservations::Reservations},
+        wallet::Wallets,
+    };
+
+    fn service(devnet: Devnet, history: History) -> anyhow::Result<SubmitService<Devnet>> {
+        Ok(SubmitService(Arc::new(Context {
+            backend: Arc::new(devnet),
+            config: serde_json::from_value(json!({ "listen_address": "127.0.0.1:0" }))?,
+            submits: TaskTracker::new(),
+            shutdown: CancellationToken::new(),
+            reservations: Arc::new(Reservations::new(Duration::ZERO)),
+            history: Arc::new(history),
+            wallets: Wallets::load(&HashMap::new())?,
+            sponsor: None,
+            faucet: None,
+        })))
+    }
+
+    /// Updates of a stream that has to end on its own
+    async fn updates(
+        service: &SubmitService<Devnet>,
+        refs: &[String],
+    ) -> anyhow::Result<Vec<Result<(String, i32), Status>>> {
+        let request = submit::WaitForTxRequest {
+            r#ref: refs
+                .iter()
+                .map(|r#ref| Ok(hex::decode(r#ref)?.into()))
+                .collect::<anyhow::Result<_>>()?,
+        };
+        let stream = service
+            .wait_for_tx(Request::new(request))
+            .await?
+            .into_inner();
+
+        let updates = tokio::time::timeout(Duration::from_secs(1), stream.collect::<Vec<_>>())
+            .await
+            .context("stream didn't end")?;
+
+        Ok(updates
+            .into_iter()
+            .map(|update| update.map(|update| (hex::encode(update.r#ref), update.stage)))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn reports_txs_settled_before_the_call() -> anyhow::Result<()> {
+        let (confirmed, invalid) = ("aa".repeat(32), "bb".repeat(32));
+
+        let history = History::new(10);
+        history.observe(&Event::TxValid {
+            tx_id: confirmed.clone(),
+        });
+        history.observe(&Event::SnapshotConfirmed {
+            snapshot: Snapshot {
+                utxo: HashMap::new(),
+                confirmed: vec![ConfirmedTx {
+                    tx_id: confirmed.clone(),
+                    cbor_hex: String::new(),
+                }],
+            },
+            seq: 1,
+            timestamp: String::new(),
+        });
+        history.observe(&Event::TxInvalid {
+            transaction: Transaction {
+                tx_id: invalid.clone(),
+            },
+            validation_error: ValidationError {
+                reason: "BadInputsUTxO".into(),
+            },
+        });
+
+        let service = service(Devnet::seeded(HashMap::new()), history)?;
+        let updates = updates(&service, &[confirmed.clone(), invalid]).await?;
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(
+            updates[0].as_ref().ok(),
+            Some(&(confirmed, submit::Stage::Confirmed as i32))
+        );
+        let rejection = updates[1].as_ref().unwrap_err();
+        assert!(rejection.message().contains("BadInputsUTxO"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn confirms_txs_with_outputs_in_the_snapshot() -> anyhow::Result<()> {
+        let genesis = "00".repeat(32);
+        let devnet = Devnet::seeded(HashMap::from([(
+            txid(&genesis, 0),
+            testing::lovelace_utxo(testing::USER, 10_000_000),
+        )]));
+
+        let service = service(devnet, History::new(10))?;
+        let updates = updates(&service, std::slice::from_ref(&genesis)).await?;
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            updates[0].as_ref().ok(),
+            Some(&(genesis, submit::Stage::Confirmed as i32))
+        );
+
+        Ok(())
+    }
+}
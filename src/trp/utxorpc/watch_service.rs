@@ -0,0 +1,200 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures_util::{StreamExt, stream};
+use tonic::{Request, Response, Status};
+use tracing::warn;
+use tx3_cardano::pallas::ledger::traverse::MultiEraTx;
+use utxorpc_spec::utxorpc::v1alpha::{
+    cardano::{self, AssetPattern},
+    watch::{self, any_chain_tx, any_chain_tx_pattern, watch_service_server, watch_tx_response},
+};
+
+use super::{ResponseStream, address_matches, asset_matches, events, mapper, output_matches};
+use crate::{
+    backend::Backend,
+    hydra::model::{ConfirmedTx, Event, TxID, Utxo},
+    ledger,
+    trp::Context,
+};
+
+pub struct WatchService<B: Backend>(pub Arc<Context<B>>);
+
+/// Utxos a confirmed tx moves, inputs are resolved against the snapshot
+/// before it
+struct TxEffects {
+    consumed: Vec<Utxo>,
+    produced: Vec<Utxo>,
+    /// Hex policy id and asset name of the minted and burned assets
+    minted: Vec<(String, String)>,
+}
+
+impl TxEffects {
+    fn new(tx: &MultiEraTx, utxos: &HashMap<TxID, Utxo>) -> Self {
+        let consumed = tx
+            .consumes()
+            .iter()
+            .filter_map(|input| utxos.get(&ledger::txid(input.hash(), input.index())))
+            .cloned()
+            .collect();
+
+        let produced = tx
+            .produces()
+            .iter()
+            .filter_map(|(_, output)| ledger::utxo_from_output(output).ok())
+            .collect();
+
+        let minted = tx
+            .mints()
+            .iter()
+            .flat_map(|policy| {
+                policy
+                    .assets()
+                    .iter()
+                    .map(|asset| (policy.policy().to_string(), hex::encode(asset.name())))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Self {
+            consumed,
+            produced,
+            minted,
+        }
+    }
+
+    fn moved(&self) -> impl Iterator<Item = &Utxo> {
+        self.consumed.iter().chain(&self.produced)
+    }
+
+    fn mints(&self, pattern: &AssetPattern) -> bool {
+        let policy = hex::encode(&pattern.policy_id);
+        let name = hex::encode(&pattern.asset_name);
+
+        self.minted.iter().any(|(minted_policy, minted_name)| {
+            (policy.is_empty() || *minted_policy == policy)
+                && (name.is_empty() || *minted_name == name)
+        })
+    }
+
+    fn matches(&self, pattern: &cardano::TxPattern) -> bool {
+        pattern.consumes.as_ref().is_none_or(|pattern| {
+            self.consumed
+                .iter()
+                .any(|utxo| output_matches(pattern, utxo))
+        }) && pattern.produces.as_ref().is_none_or(|pattern| {
+            self.produced
+                .iter()
+                .any(|utxo| output_matches(pattern, utxo))
+        }) && pattern.has_address.as_ref().is_none_or(|pattern| {
+            self.moved()
+                .any(|utxo| address_matches(pattern, &utxo.address))
+        }) && pattern
+            .moves_asset
+            .as_ref()
+            .is_none_or(|pattern| self.moved().any(|utxo| asset_matches(pattern, utxo)))
+            && pattern
+                .mints_asset
+                .as_ref()
+                .is_none_or(|pattern| self.mints(pattern))
+    }
+}
+
+/// Whether the tx satisfies the predicate, patterns of other chains never match
+fn predicate_matches(predicate: &watch::TxPredicate, effects: &TxEffects) -> bool {
+    let matched = predicate
+        .r#match
+        .as_ref()
+        .is_none_or(|pattern| match &pattern.chain {
+            Some(any_chain_tx_pattern::Chain::Cardano(pattern)) => effects.matches(pattern),
+            _ => false,
+        });
+
+    matched
+        && !predicate
+            .not
+            .iter()
+            .any(|not| predicate_matches(not, effects))
+        && predicate
+            .all_of
+            .iter()
+            .all(|all| predicate_matches(all, effects))
+        && (predicate.any_of.is_empty()
+            || predicate
+                .any_of
+                .iter()
+                .any(|any| predicate_matches(any, effects)))
+}
+
+#[tonic::async_trait]
+impl<B: Backend> watch_service_server::WatchService for WatchService<B> {
+    type WatchTxStream = ResponseStream<watch::WatchTxResponse>;
+
+    /// Applies the txs of each confirmed snapshot that match the predicate.
+    /// Snapshots are final, so there are no undos.
+    async fn watch_tx(
+        &self,
+        request: Request<watch::WatchTxRequest>,
+    ) -> Result<Response<Self::WatchTxStream>, Status> {
+        let request = request.into_inner();
+
+        if !request.intersect.is_empty() {
+            return Err(Status::unimplemented(
+                "the head keeps no history, txs are watched from the tip",
+            ));
+        }
+
+        let mapper = mapper(&self.0);
+
+        // subscribed before reading the snapshot so no confirmation is missed
+        let rx = self.0.backend.events();
+        let mut utxos = self.0.backend.read_utxos().await.0.clone();
+
+        let stream = events(rx).flat_map(move |event| {
+            let snapshot = match event {
+                Ok(Event::SnapshotConfirmed { snapshot, .. }) => snapshot,
+                Ok(_) => return stream::iter(vec![]),
+                Err(status) => return stream::iter(vec![Err(status)]),
+            };
+
+            let mut applied = vec![];
+
+            for ConfirmedTx { tx_id, cbor_hex } in &snapshot.confirmed {
+                let Ok(cbor) = hex::decode(cbor_hex) else {
+                    warn!(tx_id, "confirmed tx with invalid hex");
+                    continue;
+                };
+
+                let Ok(tx) = MultiEraTx::decode(&cbor) else {
+                    warn!(tx_id, "confirmed tx with invalid cbor");
+                    continue;
+                };
+
+                let effects = TxEffects::new(&tx, &utxos);
+
+                if request
+                    .predicate
+                    .as_ref()
+                    .is_none_or(|predicate| predicate_matches(predicate, &effects))
+                {
+                    applied.push(Ok(watch::WatchTxResponse {
+                        action: Some(watch_tx_response::Action::Apply(watch::AnyChainTx {
+                            chain: Some(any_chain_tx::Chain::Cardano(mapper.map_tx(&tx))),
+                            ..Default::default()
+                        })),
+                    }));
+                }
+
+                // later txs of the snapshot may spend the outputs of this one
+                if let Err(error) = ledger::apply_tx(&mut utxos, &tx) {
+                    warn!(?error, tx_id, "failed to apply confirmed tx");
+                }
+            }
+
+            utxos = snapshot.utxo;
+
+            stream::iter(applied)
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
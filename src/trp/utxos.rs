@@ -1,6 +1,6 @@
 use std::collections::HashSet;
 
-use tx3_cardano::pallas::ledger::addresses::Address;
+use tx3_cardano::pallas::ledger::addresses::{Address, ShelleyDelegationPart};
 use tx3_resolver::{Error, UtxoPattern, UtxoRef, UtxoSet, UtxoStore};
 
 use crate::hydra::{
@@ -62,6 +62,36 @@ impl UtxoSnapshot<'_> {
             .map(|(tx_id, _)| tx_id.clone())
             .collect()
     }
+
+    /// Utxos at shelley addresses with the given payment and delegation key
+    /// or script hashes, a missing one matches any
+    pub fn get_utxo_by_credentials(
+        &self,
+        payment: Option<&[u8]>,
+        delegation: Option<&[u8]>,
+    ) -> Vec<TxID> {
+        let utxo_matches = |utxo: &Utxo| {
+            let Ok(Address::Shelley(address)) = utxo.address.parse::<Address>() else {
+                return false;
+            };
+
+            let delegation_hash = match address.delegation() {
+                ShelleyDelegationPart::Key(hash) | ShelleyDelegationPart::Script(hash) => {
+                    Some(hash.as_slice())
+                }
+                _ => None,
+            };
+
+            payment.is_none_or(|payment| address.payment().as_hash().as_slice() == payment)
+                && delegation.is_none_or(|delegation| delegation_hash == Some(delegation))
+        };
+
+        self.0
+            .iter()
+            .filter(|(_, utxo)| utxo_matches(utxo))
+            .map(|(tx_id, _)| tx_id.clone())
+            .collect()
+    }
 }
 
 impl UtxoStore for UtxoSnapshot<'_> {
@@ -100,3 +130,26 @@ impl UtxoStore for UtxoSnapshot<'_> {
         Ok(utxos)
     }
 }
+
+/// Snapshot view that keeps reserved utxos out of coin selection. Explicit
+/// fetches still see them.
+pub struct UnreservedUtxos<'a, 'b> {
+    pub snapshot: &'a UtxoSnapshot<'b>,
+    pub reserved: &'a HashSet<TxID>,
+}
+
+impl UtxoStore for UnreservedUtxos<'_, '_> {
+    async fn narrow_refs(&self, pattern: UtxoPattern<'_>) -> Result<HashSet<UtxoRef>, Error> {
+        let mut refs = self.snapshot.narrow_refs(pattern).await?;
+        refs.retain(|ref_| {
+            let txid = format!("{}#{}", hex::encode(&ref_.txid), ref_.index);
+            !self.reserved.contains(&txid)
+        });
+
+        Ok(refs)
+    }
+
+    async fn fetch_utxos(&self, refs: HashSet<UtxoRef>) -> Result<UtxoSet, Error> {
+        self.snapshot.fetch_utxos(refs).await
+    }
+}
@@ -0,0 +1,414 @@
+//! Pushes head activity to configured endpoints as signed JSON posts: the
+//! outcome of watched txs, utxos created or spent at watched addresses or
+//! policies, and head status changes. Deliveries are retried with backoff
+//! and the ones that keep failing go to a dead-letter file.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::{Mutex, broadcast},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, info, warn};
+
+use crate::{
+    backend::Backend,
+    hydra::model::{Event, HeadStatus, TxID, Utxo},
+    ledger::time,
+    trp::{history::TxStatus, methods::query::UtxoView},
+};
+
+const SIGNATURE_HEADER: &str = "x-webhook-signature";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+fn default_max_attempts() -> u32 {
+    6
+}
+
+fn default_dead_letter_path() -> PathBuf {
+    PathBuf::from("webhooks-dead-letter.jsonl")
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Endpoint {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature of the deliveries, left out of
+    /// `check-config`
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Tx hashes to report the outcome of, `*` for every tx
+    #[serde(default)]
+    pub transactions: Vec<String>,
+    /// Bech32 addresses to report created and spent utxos at
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// Hex policy ids to report created and spent utxos holding
+    #[serde(default)]
+    pub policies: Vec<String>,
+    #[serde(default)]
+    pub head_status: bool,
+}
+
+impl Endpoint {
+    fn watches_tx(&self, hash: &str) -> bool {
+        self.transactions
+            .iter()
+            .any(|watched| watched == "*" || watched.eq_ignore_ascii_case(hash))
+    }
+
+    fn watches_utxo(&self, utxo: &Utxo) -> bool {
+        self.addresses.contains(&utxo.address)
+            || self
+                .policies
+                .iter()
+                .any(|policy| !utxo.value.assets_by_policy(policy).is_empty())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Config {
+    pub endpoints: Vec<Endpoint>,
+    /// Attempts per delivery, the first one included
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// JSON lines file the failed deliveries are appended to
+    #[serde(default = "default_dead_letter_path")]
+    pub dead_letter_path: PathBuf,
+}
+
+#[derive(Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum Payload<'a> {
+    Transaction {
+        hash: &'a str,
+        status: TxStatus,
+        /// Validation error of invalid txs
+        reason: Option<&'a str>,
+        /// Seq of the snapshot that confirmed the tx
+        snapshot_seq: Option<u64>,
+    },
+    Utxos {
+        snapshot_seq: u64,
+        created: Vec<UtxoView>,
+        spent: Vec<UtxoView>,
+    },
+    HeadStatus {
+        previous: &'a HeadStatus,
+        current: &'a HeadStatus,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    /// Unix millis the payload was built
+    at: u64,
+    #[serde(flatten)]
+    payload: Payload<'a>,
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    at: u64,
+    url: &'a str,
+    attempts: u32,
+    error: &'a str,
+    payload: serde_json::Value,
+}
+
+/// Hex HMAC-SHA256 of `{at}.{body}`, so a captured delivery can't be
+/// replayed under a fresh timestamp
+fn signature(secret: &[u8], at: u64, body: &[u8]) -> String {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(format!("{at}.").as_bytes());
+    mac.update(body);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+struct Dispatcher {
+    config: Config,
+    http: reqwest::Client,
+    deliveries: TaskTracker,
+    dead_letters: Mutex<()>,
+    shutdown: CancellationToken,
+}
+
+impl Dispatcher {
+    fn send(self: &Arc<Self>, endpoint: usize, payload: Payload) {
+        let at = time::now_millis();
+        let envelope = Envelope { at, payload };
+
+        let body = match serde_json::to_vec(&envelope) {
+            Ok(body) => body,
+            Err(error) => {
+                warn!(?error, "failed to encode webhook payload");
+                return;
+            }
+        };
+
+        let dispatcher = Arc::clone(self);
+        self.deliveries
+            .spawn(async move { dispatcher.deliver(endpoint, at, body).await });
+    }
+
+    /// Posts the body until the endpoint answers with a success status, the
+    /// attempts run out or the server shuts down
+    async fn deliver(&self, endpoint: usize, at: u64, body: Vec<u8>) {
+        let endpoint = &self.config.endpoints[endpoint];
+        let signature = format!(
+            "t={at},sha256={}",
+            signature(endpoint.secret.as_bytes(), at, &body)
+        );
+
+        let mut delay = FIRST_RETRY_DELAY;
+        let mut attempts = 0;
+
+        let error = loop {
+            attempts += 1;
+
+            let result = self
+                .http
+                .post(&endpoint.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            let error = match result {
+                Ok(_) => {
+                    debug!(url = endpoint.url, attempts, "webhook delivered");
+                    return;
+                }
+                Err(error) => error.to_string(),
+            };
+
+            if attempts >= self.config.max_attempts {
+                break error;
+            }
+
+            debug!(
+                url = endpoint.url,
+                attempts, error, "webhook delivery failed"
+            );
+
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break error,
+                _ = tokio::time::sleep(delay) => {}
+            }
+
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        };
+
+        warn!(url = endpoint.url, attempts, error, "webhook dead-lettered");
+        self.dead_letter(&endpoint.url, attempts, &error, &body)
+            .await;
+    }
+
+    async fn dead_letter(&self, url: &str, attempts: u32, error: &str, body: &[u8]) {
+        let letter = DeadLetter {
+            at: time::now_millis(),
+            url,
+            attempts,
+            error,
+            payload: serde_json::from_slice(body).unwrap_or_default(),
+        };
+
+        // one letter at a time, so concurrent lines don't interleave
+        let _guard = self.dead_letters.lock().await;
+
+        let result = async {
+            let mut line = serde_json::to_vec(&letter)?;
+            line.push(b'\n');
+
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.config.dead_letter_path)
+                .await?
+                .write_all(&line)
+                .await?;
+
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+
+        if let Err(error) = result {
+            warn!(?error, "failed to write webhook dead letter");
+        }
+    }
+
+    fn transaction(
+        self: &Arc<Self>,
+        hash: &str,
+        status: TxStatus,
+        reason: Option<&str>,
+        snapshot_seq: Option<u64>,
+    ) {
+        for (index, endpoint) in self.config.endpoints.iter().enumerate() {
+            if endpoint.watches_tx(hash) {
+                self.send(
+                    index,
+                    Payload::Transaction {
+                        hash,
+                        status,
+                        reason,
+                        snapshot_seq,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Reports the difference between two consecutive snapshots
+    fn utxos(
+        self: &Arc<Self>,
+        snapshot_seq: u64,
+        before: &HashMap<TxID, Utxo>,
+        after: &HashMap<TxID, Utxo>,
+    ) {
+        let created: Vec<_> = after
+            .iter()
+            .filter(|(r#ref, _)| !before.contains_key(*r#ref))
+            .collect();
+        let spent: Vec<_> = before
+            .iter()
+            .filter(|(r#ref, _)| !after.contains_key(*r#ref))
+            .collect();
+
+        for (index, endpoint) in self.config.endpoints.iter().enumerate() {
+            let watched = |utxos: &[(&TxID, &Utxo)]| -> Vec<UtxoView> {
+                utxos
+                    .iter()
+                    .filter(|(_, utxo)| endpoint.watches_utxo(utxo))
+                    .map(|(r#ref, utxo)| UtxoView::new(r#ref, utxo))
+                    .collect()
+            };
+
+            let created = watched(&created);
+            let spent = watched(&spent);
+
+            if !created.is_empty() || !spent.is_empty() {
+                self.send(
+                    index,
+                    Payload::Utxos {
+                        snapshot_seq,
+                        created,
+                        spent,
+                    },
+                );
+            }
+        }
+    }
+
+    fn head_status(self: &Arc<Self>, previous: &HeadStatus, current: &HeadStatus) {
+        for (index, endpoint) in self.config.endpoints.iter().enumerate() {
+            if endpoint.head_status {
+                self.send(index, Payload::HeadStatus { previous, current });
+            }
+        }
+    }
+}
+
+/// Turns the backend events into webhook deliveries until cancelled, then
+/// waits for the pending deliveries
+pub async fn run<B: Backend>(
+    config: &Config,
+    backend: Arc<B>,
+    mut events: broadcast::Receiver<Event>,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let dispatcher = Arc::new(Dispatcher {
+        config: config.clone(),
+        http: reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?,
+        deliveries: TaskTracker::new(),
+        dead_letters: Mutex::new(()),
+        shutdown: cancellation_token.clone(),
+    });
+
+    info!(endpoints = config.endpoints.len(), "webhooks enabled");
+
+    let mut utxos = backend.read_utxos().await.0.clone();
+    let mut head_status = backend.health_status().await.head_status;
+
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            event = events.recv() => match event {
+                Ok(Event::TxValid { tx_id }) => {
+                    dispatcher.transaction(&tx_id, TxStatus::Valid, None, None);
+                }
+                Ok(Event::TxInvalid { transaction, validation_error }) => {
+                    dispatcher.transaction(
+                        &transaction.tx_id,
+                        TxStatus::Invalid,
+                        Some(&validation_error.reason),
+                        None,
+                    );
+                }
+                Ok(Event::SnapshotConfirmed { snapshot, seq, .. }) => {
+                    for tx in &snapshot.confirmed {
+                        dispatcher.transaction(&tx.tx_id, TxStatus::Confirmed, None, Some(seq));
+                    }
+
+                    dispatcher.utxos(seq, &utxos, &snapshot.utxo);
+                    utxos = snapshot.utxo;
+                }
+                Ok(event) => {
+                    let current = event.head_status();
+                    if let Some(current) = current.filter(|current| *current != head_status) {
+                        dispatcher.head_status(&head_status, &current);
+                        head_status = current;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "webhooks lagged behind the backend events");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+
+    dispatcher.deliveries.close();
+    dispatcher.deliveries.wait().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = br#"{"type":"headStatus"}"#;
+
+    #[test]
+    fn signs_the_timestamp_and_the_body() {
+        assert_eq!(
+            signature(b"change-me", 1_700_000_000_000, BODY),
+            "1eb42bce2d997b15b7083c87bb8e33c36ce85deaf98ebcf4f79fbf372a15d5d1"
+        );
+    }
+
+    #[test]
+    fn binds_the_signature_to_the_timestamp() {
+        assert_eq!(
+            signature(b"change-me", 1_700_000_000_001, BODY),
+            "f0955976fca810aa94aefdf99ab0eb527541477828fa7a5e4479bd31f7cc12aa"
+        );
+    }
+}
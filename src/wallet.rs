@@ -0,0 +1,178 @@
+//! Custodial signing keys the TRP server signs resolved txs with. Keys are
+//! only ever read from files or the environment, never from the config
+//! itself, and the api keys allowed to use them are left out when the
+//! config is printed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::{Context, bail, ensure};
+use serde::{Deserialize, Serialize};
+use tx3_cardano::pallas::{
+    codec::{minicbor, utils::Bytes},
+    crypto::{
+        hash::{Hash, Hasher},
+        key::ed25519::{PublicKey, SecretKey, SecretKeyExtended},
+    },
+    ledger::addresses::{Address, ShelleyPaymentPart},
+};
+
+use crate::ledger::witness::VKeyWitness;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Config {
+    /// File with a cardano-cli signing key text envelope, or a hex key
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Env var with a cardano-cli signing key text envelope, or a hex key
+    #[serde(default)]
+    pub env: Option<String>,
+    /// API keys allowed to sign with this wallet
+    #[serde(default, skip_serializing)]
+    pub allowed_api_keys: Vec<String>,
+}
+
+/// cardano-cli `.skey` / `.sk` file content
+#[derive(Deserialize)]
+struct TextEnvelope {
+    #[serde(rename = "type")]
+    r#type: String,
+    #[serde(rename = "cborHex")]
+    cbor_hex: String,
+}
+
+pub enum SigningKey {
+    Normal(SecretKey),
+    Extended(SecretKeyExtended),
+}
+
+impl SigningKey {
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        match bytes.len() {
+            32 => {
+                let bytes: [u8; 32] = bytes.try_into().unwrap();
+                Ok(Self::Normal(SecretKey::from(bytes)))
+            }
+            // extended keys from cardano-cli carry the public key and chain
+            // code after the 64 bytes of the key itself
+            64 | 128 => {
+                let bytes: [u8; 64] = bytes[..64].try_into().unwrap();
+                let key = SecretKeyExtended::from_bytes(bytes)
+                    .map_err(|error| anyhow::anyhow!("invalid extended key: {error:?}"))?;
+                Ok(Self::Extended(key))
+            }
+            len => bail!("unexpected signing key length {len}"),
+        }
+    }
+
+    /// Parses a text envelope or a bare hex key
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let content = content.trim();
+
+        if content.starts_with('{') {
+            let envelope: TextEnvelope =
+                serde_json::from_str(content).context("decoding key text envelope")?;
+
+            if !envelope.r#type.contains("SigningKey") {
+                bail!("{} is not a signing key", envelope.r#type);
+            }
+
+            let cbor = hex::decode(&envelope.cbor_hex).context("decoding key cbor hex")?;
+            let bytes = minicbor::decode::<Bytes>(&cbor).context("decoding key cbor")?;
+
+            return Self::from_bytes(&bytes);
+        }
+
+        Self::from_bytes(&hex::decode(content).context("decoding hex key")?)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        match self {
+            Self::Normal(key) => key.public_key(),
+            Self::Extended(key) => key.public_key(),
+        }
+    }
+
+    pub fn witness(&self, body_hash: &Hash<32>) -> VKeyWitness {
+        let signature = match self {
+            Self::Normal(key) => key.sign(&body_hash[..]),
+            Self::Extended(key) => key.sign(&body_hash[..]),
+        };
+
+        VKeyWitness {
+            vkey: self.public_key().as_ref().to_vec(),
+            signature: signature.as_ref().to_vec(),
+        }
+    }
+}
+
+pub struct Wallet {
+    key: SigningKey,
+    allowed_api_keys: HashSet<String>,
+}
+
+impl Wallet {
+    fn load(name: &str, config: &Config) -> anyhow::Result<Self> {
+        let content = match (&config.file, &config.env) {
+            (Some(path), None) => std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?,
+            (None, Some(var)) => std::env::var(var).with_context(|| format!("reading ${var}"))?,
+            _ => bail!("wallet {name} needs exactly one of `file` or `env`"),
+        };
+
+        let key = SigningKey::parse(&content).with_context(|| format!("loading wallet {name}"))?;
+
+        Ok(Self {
+            key,
+            allowed_api_keys: config.allowed_api_keys.iter().cloned().collect(),
+        })
+    }
+
+    pub fn allows(&self, api_key: Option<&str>) -> bool {
+        api_key.is_some_and(|key| self.allowed_api_keys.contains(key))
+    }
+
+    pub fn witness(&self, body_hash: &Hash<32>) -> VKeyWitness {
+        self.key.witness(body_hash)
+    }
+
+    /// Payment key hash of the wallet, as found in its addresses
+    pub fn key_hash(&self) -> Hash<28> {
+        Hasher::<224>::hash(self.key.public_key().as_ref())
+    }
+
+    /// Fails unless the payment part of the address is the wallet key
+    pub fn ensure_owns(&self, address: &str) -> anyhow::Result<()> {
+        let address: Address = address.parse().context("decoding address")?;
+        let Address::Shelley(address) = address else {
+            bail!("{address} is not a shelley address");
+        };
+
+        ensure!(
+            matches!(address.payment(), ShelleyPaymentPart::Key(hash) if *hash == self.key_hash()),
+            "address doesn't belong to the wallet"
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Wallets(HashMap<String, Wallet>);
+
+impl Wallets {
+    pub fn load(configs: &HashMap<String, Config>) -> anyhow::Result<Self> {
+        let wallets = configs
+            .iter()
+            .map(|(name, config)| Ok((name.clone(), Wallet::load(name, config)?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self(wallets))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Wallet> {
+        self.0.get(name)
+    }
+}